@@ -0,0 +1,125 @@
+// A shape and a material defined outside the renderer: an axis aligned box whose faces
+// are diffuse in a color per axis. Writes custom_shapes.ppm:
+//
+//     cargo run --release --example custom_shapes
+use std::{f32::consts::PI, sync::Arc};
+
+use raytracer::{render, Aabb, BvhNode, Camera, CameraView, Color, Hit, HitRecord, Hittable, HittableList, Interval, Lambertian, Material, MaterialEnum, Point3d, Ray, Result, Scene, Sky, Sphere, ToneMapping, Vec3d};
+
+struct Cuboid {
+    bbox: Aabb,
+    material: Arc<MaterialEnum>,
+    id: u32,
+}
+
+impl Cuboid {
+    fn new(a: Point3d, b: Point3d, material: Arc<MaterialEnum>, id: u32) -> Cuboid {
+        Cuboid { bbox: Aabb::from_points(&a, &b), material, id }
+    }
+}
+
+impl Hit for Cuboid {
+    // slab test, keeping the axis of the face the ray enters through
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<(HitRecord, Arc<MaterialEnum>)> {
+        let origin = r.origin.as_vec3d();
+        let (mut t_min, mut t_max) = (ray_t.min, ray_t.max);
+        let mut normal = Vec3d::new(0.0, 0.0, 0.0);
+        for axis in 0 .. 3 {
+            let slab = self.bbox.axis_interval(axis);
+            let (o, d) = (origin[axis as usize], r.direction[axis as usize]);
+            let (t0, t1) = ((slab.min - o) / d, (slab.max - o) / d);
+            let (near, far, sign) = if t0 < t1 { (t0, t1, -1.0) } else { (t1, t0, 1.0) };
+            if near > t_min {
+                t_min = near;
+                normal = Vec3d::new(0.0, 0.0, 0.0);
+                match axis {
+                    0 => normal.x = sign,
+                    1 => normal.y = sign,
+                    _ => normal.z = sign,
+                }
+            }
+            t_max = t_max.min(far);
+            if t_max <= t_min {
+                return None;
+            }
+        }
+        if !ray_t.surrounds(t_min) {
+            return None;
+        }
+
+        let mut hr = HitRecord { point: r.at(t_min), t: t_min, object_id: self.id, ..HitRecord::default() };
+        hr.set_face_normal(r, normal);
+        Some((hr, self.material.clone()))
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+}
+
+// Lambertian with the albedo picked by the axis of the normal
+struct AxisColors {
+    colors: [Color; 3],
+}
+
+impl AxisColors {
+    fn albedo_at(&self, normal: &Vec3d) -> Color {
+        let n = [normal.x.abs(), normal.y.abs(), normal.z.abs()];
+        let axis = (0 .. 3).max_by(|a, b| n[*a].total_cmp(&n[*b])).unwrap();
+        self.colors[axis]
+    }
+}
+
+impl Material for AxisColors {
+    fn scatter(&self, ray_in: &Ray, hr: &HitRecord) -> (Ray, Color, bool) {
+        let mut direction = hr.normal.clone() + Vec3d::random_unit();
+        if direction.near_zero() {
+            direction = hr.normal.clone();
+        }
+        (Ray::new(hr.point.clone(), direction, Some(ray_in.tm)), self.albedo_at(&hr.normal), true)
+    }
+
+    fn eval(&self, _ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> Option<Color> {
+        let cosine = Vec3d::dot(&hr.normal, &Vec3d::unit(direction)).max(0.0);
+        Some(self.albedo_at(&hr.normal) * (cosine / PI))
+    }
+
+    fn pdf(&self, _ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> f32 {
+        Vec3d::dot(&hr.normal, &Vec3d::unit(direction)).max(0.0) / PI
+    }
+
+    fn albedo(&self, hr: &HitRecord) -> Color {
+        self.albedo_at(&hr.normal)
+    }
+}
+
+fn main() -> Result<()> {
+    let mut list = HittableList::default();
+    let ground = Arc::new(MaterialEnum::Lambertian(Lambertian { albedo: Color { r: 0.5, g: 0.5, b: 0.5 } }));
+    list.add(Hittable::Sphere(Sphere::new(Point3d::new(0.0, -1000.0, 0.0), 1000.0, ground)));
+
+    let faces = Arc::new(MaterialEnum::Custom(Arc::new(AxisColors {
+        colors: [Color { r: 0.7, g: 0.2, b: 0.2 }, Color { r: 0.2, g: 0.7, b: 0.2 }, Color { r: 0.2, g: 0.2, b: 0.7 }],
+    })));
+    for (i, x) in [-3.0, 0.0, 3.0].into_iter().enumerate() {
+        let cuboid = Cuboid::new(Point3d::new(x - 0.8, 0.0, -0.8), Point3d::new(x + 0.8, 1.6, 0.8), faces.clone(), i as u32 + 100);
+        list.add(Hittable::Custom(Arc::new(cuboid)));
+    }
+
+    let world = HittableList::new(Hittable::BvhNode(BvhNode::new(&mut list)));
+
+    let view = CameraView {
+        vfov: 25.0,
+        lookfrom: Point3d::new(9.0, 5.0, 9.0),
+        lookat: Point3d::new(0.0, 0.5, 0.0),
+        vup: Vec3d::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.0,
+        focus_dist: 10.0,
+    };
+    let camera = Camera::initialize(16.0 / 9.0, 400, 20, 32, view);
+
+    let image = render(camera, Scene { world, sky: Sky::Gradient }, 4)?;
+    image.write_ppm("custom_shapes.ppm", &ToneMapping::default())?;
+    println!("Saved {}x{} image to custom_shapes.ppm", image.width, image.height);
+    Ok(())
+}
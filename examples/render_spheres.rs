@@ -0,0 +1,36 @@
+// Renders three spheres on a ground plane through the library API and writes spheres.ppm:
+//
+//     cargo run --release --example render_spheres
+use std::sync::Arc;
+
+use raytracer::{render, BvhNode, Camera, CameraView, Color, Dielectric, Hittable, HittableList, Lambertian, MaterialEnum, Metal, Point3d, Result, Scene, Sky, Sphere, ToneMapping, Vec3d};
+
+fn main() -> Result<()> {
+    let mut list = HittableList::default();
+    let ground = Arc::new(MaterialEnum::Lambertian(Lambertian { albedo: Color { r: 0.5, g: 0.5, b: 0.5 } }));
+    list.add(Hittable::Sphere(Sphere::new(Point3d::new(0.0, -1000.0, 0.0), 1000.0, ground)));
+
+    let diffuse = Arc::new(MaterialEnum::Lambertian(Lambertian { albedo: Color { r: 0.4, g: 0.2, b: 0.1 } }));
+    let glass = Arc::new(MaterialEnum::Dielectric(Dielectric::new(1.5)));
+    let metal = Arc::new(MaterialEnum::Metal(Metal { albedo: Color { r: 0.7, g: 0.6, b: 0.5 }, fuzz: 0.0 }));
+    list.add(Hittable::Sphere(Sphere::new(Point3d::new(-4.0, 1.0, 0.0), 1.0, diffuse)));
+    list.add(Hittable::Sphere(Sphere::new(Point3d::new(0.0, 1.0, 0.0), 1.0, glass)));
+    list.add(Hittable::Sphere(Sphere::new(Point3d::new(4.0, 1.0, 0.0), 1.0, metal)));
+
+    let world = HittableList::new(Hittable::BvhNode(BvhNode::new(&mut list)));
+
+    let view = CameraView {
+        vfov: 20.0,
+        lookfrom: Point3d::new(13.0, 2.0, 3.0),
+        lookat: Point3d::new(0.0, 0.0, 0.0),
+        vup: Vec3d::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.0,
+        focus_dist: 10.0,
+    };
+    let camera = Camera::initialize(16.0 / 9.0, 400, 20, 32, view);
+
+    let image = render(camera, Scene { world, sky: Sky::Gradient }, 4)?;
+    image.write_ppm("spheres.ppm", &ToneMapping::default())?;
+    println!("Saved {}x{} image to spheres.ppm", image.width, image.height);
+    Ok(())
+}
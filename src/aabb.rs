@@ -3,27 +3,35 @@
 use crate::interval::Interval;
 use crate::{camera::Ray, Point3d};
 
+/// An axis-aligned bounding box
 #[derive(Clone, Debug)]
-pub(crate) struct Aabb {
-    pub(crate) x: Interval,
-    pub(crate) y: Interval,
-    pub(crate) z: Interval,
+pub struct Aabb {
+    /// extent along x
+    pub x: Interval,
+    /// extent along y
+    pub y: Interval,
+    /// extent along z
+    pub z: Interval,
 }
 
-impl Aabb {
-    pub(crate) fn default() -> Aabb {
+impl Default for Aabb {
+    fn default() -> Aabb {
         Aabb {
             x: Interval::default(),
             y: Interval::default(),
             z: Interval::default(),
         }
     }
+}
 
-    pub(crate) fn universe() -> Self {
+impl Aabb {
+    /// The box of all space
+    pub fn universe() -> Self {
         Aabb::default()
     }
 
-    pub(crate) fn empty() -> Self {
+    /// The box of nothing, the neutral element of [`Aabb::from_boxes`]
+    pub fn empty() -> Self {
         Aabb {
             x: Interval::empty(),
             y: Interval::empty(),
@@ -31,7 +39,8 @@ impl Aabb {
         }
     }
 
-    pub(crate) fn new(x: Interval, y: Interval, z: Interval) -> Aabb {
+    /// The box of three extents
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Aabb {
         Aabb {
             x: Interval { min: x.min, max: x.max }, 
             y: Interval { min: y.min, max: y.max },
@@ -39,7 +48,8 @@ impl Aabb {
         }
     }
 
-    pub(crate) fn from_points(a: &Point3d, b: &Point3d) -> Aabb {
+    /// The box with the opposite corners `a` and `b`
+    pub fn from_points(a: &Point3d, b: &Point3d) -> Aabb {
         Aabb::new(
             if a.0.x <= b.0.x {Interval::new(a.0.x, b.0.x)} else {Interval::new(b.0.x, a.0.x)}, 
             if a.0.y <= b.0.y {Interval::new(a.0.y, b.0.y)} else {Interval::new(b.0.y, a.0.y)}, 
@@ -47,7 +57,8 @@ impl Aabb {
         )
     }
 
-    pub(crate) fn from_boxes(b1: Aabb, b2: Aabb) -> Aabb {
+    /// The smallest box around both boxes
+    pub fn from_boxes(b1: Aabb, b2: Aabb) -> Aabb {
         Aabb::new(
             Interval::from_intervals(&b1.x, &b2.x),
             Interval::from_intervals(&b1.y, &b2.y),
@@ -56,7 +67,8 @@ impl Aabb {
     }
 
 
-    pub(crate) fn axis_interval(&self, n: i32) -> &Interval {
+    /// Extent along the axis 0 (x), 1 (y) or 2 (z)
+    pub fn axis_interval(&self, n: i32) -> &Interval {
         match n {
             1 => &self.y,
             2 => &self.z,
@@ -64,7 +76,8 @@ impl Aabb {
         }
     }
 
-    pub(crate) fn longest_axis(&self) -> i32 {
+    /// The axis the box is longest along, 0 (x), 1 (y) or 2 (z)
+    pub fn longest_axis(&self) -> i32 {
         if self.x.size() > self.y.size() {
             if self.x.size() > self.z.size() { 0 } else { 2 }
         } else {
@@ -72,7 +85,8 @@ impl Aabb {
         }
     }
 
-    pub(crate) fn hit(&self, r: &Ray, ray_t: Interval) -> bool {
+    /// True if the ray passes through the box within `ray_t`
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> bool {
         let mut ray_t = ray_t;
 
         let ray_orig = &r.origin;
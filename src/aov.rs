@@ -0,0 +1,247 @@
+use std::{collections::HashMap, fs, io::Write, sync::Arc};
+
+use crate::{camera::Ray, config, error::{unknown, Error, Result}, hit_record::{HitRecord, HittableList}, material::{Material, MaterialEnum}, sampler, vec3d::Vec3d, Color};
+
+/// Render passes that can be written next to the beauty image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    /// color of the first surface hit
+    Albedo,
+    /// shading normal at the first hit
+    Normal,
+    /// distance along the camera axis
+    Depth,
+    /// world position of the first hit
+    Position,
+    /// a number per object of the world, an instance counts as one
+    ObjectId,
+    /// a number per material instance
+    MaterialId,
+    /// light that scattered at most once (plus the background), indirect is the rest
+    Direct,
+    /// light that scattered more than once
+    Indirect,
+    /// samples every pixel took
+    Samples,
+}
+
+const PASSES: &[&str] = &["albedo", "normal", "depth", "position", "object_id", "material_id", "direct", "indirect", "samples"];
+
+impl Pass {
+    pub(crate) fn from_name(name: &str) -> Option<Pass> {
+        match name {
+            "albedo" => Some(Pass::Albedo),
+            "normal" => Some(Pass::Normal),
+            "depth" => Some(Pass::Depth),
+            "position" => Some(Pass::Position),
+            "object_id" => Some(Pass::ObjectId),
+            "material_id" => Some(Pass::MaterialId),
+            "direct" => Some(Pass::Direct),
+            "indirect" => Some(Pass::Indirect),
+            "samples" => Some(Pass::Samples),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Pass::Albedo => "albedo",
+            Pass::Normal => "normal",
+            Pass::Depth => "depth",
+            Pass::Position => "position",
+            Pass::ObjectId => "object_id",
+            Pass::MaterialId => "material_id",
+            Pass::Direct => "direct",
+            Pass::Indirect => "indirect",
+            Pass::Samples => "samples",
+        }
+    }
+
+    /// The passes named in `[aov]`, none without the section
+    pub fn from_settings(c: &Option<config::Aov>) -> Result<Vec<Pass>> {
+        c.as_ref().map_or(Ok(vec![]), |c| {
+            c.passes.iter().map(|name| Pass::from_name(name).ok_or_else(|| unknown("aov.passes", "pass", name, PASSES))).collect()
+        })
+    }
+}
+
+// What a camera sample saw at its first hit, and the part of its radiance that reached
+// the camera directly. Pixels keep the average over their samples, except for the ids
+// which are taken from the first sample (ids can't be blended)
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Aovs {
+    pub(crate) albedo: Color,
+    pub(crate) normal: [f32; 3],
+    pub(crate) depth: f32,
+    pub(crate) position: [f32; 3],
+    pub(crate) object_id: u32,
+    pub(crate) material_id: u32,
+    pub(crate) direct: Color,
+    pub(crate) indirect: Color,
+    // path vertex being traced and its throughput, only used while tracing
+    vertex: u8,
+    throughput: Color,
+    material: usize,    // address of the first material hit, MaterialIds turns it into an id
+}
+
+// Ids of the material instances of a scene for the material id pass, from 1 in the order
+// of the tree. Keyed by the address of the shared material, so objects sharing one
+// material share the id and every other material gets its own
+pub(crate) type MaterialIds = HashMap<usize, u32>;
+
+// Materials of custom shapes aren't known and get 0 like the background
+pub(crate) fn material_ids(world: &HittableList) -> MaterialIds {
+    let mut ids = MaterialIds::new();
+    world.for_each_material(&mut |m: &Arc<MaterialEnum>| {
+        let next = ids.len() as u32 + 1;
+        ids.entry(Arc::as_ptr(m) as usize).or_insert(next);
+    });
+    ids
+}
+
+impl Aovs {
+    // Called on every hit of the path, returns if the scattered ray still adds to the direct light
+    pub(crate) fn record_hit(&mut self, r: &Ray, hr: &HitRecord, mat: &MaterialEnum, direct: Option<Color>, attenuation: Option<Color>) -> bool {
+        if self.vertex > 0 {
+            return false;
+        }
+        self.vertex = 1;
+        self.albedo = mat.albedo(hr);
+        self.normal = [hr.normal.x, hr.normal.y, hr.normal.z];
+        self.depth = hr.t * r.direction.length();
+        self.position = [hr.point[0], hr.point[1], hr.point[2]];
+        self.object_id = hr.object_id;
+        self.material = mat as *const MaterialEnum as usize;
+        self.direct = direct.unwrap_or_default();
+        self.throughput = attenuation.unwrap_or_default();
+        attenuation.is_some()
+    }
+
+    // Radiance of a ray leaving the scene, the background or the sky after the first hit
+    pub(crate) fn record_escape(&mut self, radiance: Color) {
+        match self.vertex {
+            0 => self.direct = radiance,
+            1 => self.direct = self.direct + self.throughput * radiance,
+            _ => (),
+        }
+    }
+
+    // Turns the distance to the first hit into a depth along the camera axis and the
+    // material into its id
+    pub(crate) fn finish(&mut self, r: &Ray, forward: &Vec3d, color: Color, material_ids: &MaterialIds) {
+        self.material_id = material_ids.get(&self.material).copied().unwrap_or(0);
+        self.depth *= Vec3d::dot(&Vec3d::unit(&r.direction), forward);
+        self.indirect = color + self.direct * -1.0;
+    }
+
+    pub(crate) fn add(&mut self, sample: &Aovs, first: bool) {
+        let add3 = |a: &mut [f32; 3], b: &[f32; 3]| a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+        self.albedo = self.albedo + sample.albedo;
+        add3(&mut self.normal, &sample.normal);
+        self.depth += sample.depth;
+        add3(&mut self.position, &sample.position);
+        self.direct = self.direct + sample.direct;
+        self.indirect = self.indirect + sample.indirect;
+        if first {
+            self.object_id = sample.object_id;
+            self.material_id = sample.material_id;
+        }
+    }
+
+    pub(crate) fn scale(&mut self, s: f32) {
+        self.albedo = self.albedo * s;
+        self.normal.iter_mut().for_each(|v| *v *= s);
+        self.depth *= s;
+        self.position.iter_mut().for_each(|v| *v *= s);
+        self.direct = self.direct * s;
+        self.indirect = self.indirect * s;
+    }
+
+    fn channels(&self, pass: Pass, samples: u16) -> [f32; 3] {
+        let c = |c: Color| [c.r, c.g, c.b];
+        match pass {
+            Pass::Albedo => c(self.albedo),
+            Pass::Normal => self.normal,
+            Pass::Depth => [self.depth; 3],
+            Pass::Position => self.position,
+            Pass::Direct => c(self.direct),
+            Pass::Indirect => c(self.indirect),
+            Pass::Samples => [f32::from(samples); 3],
+            Pass::ObjectId => [self.object_id as f32; 3],
+            Pass::MaterialId => [self.material_id as f32; 3],
+        }
+    }
+}
+
+// Writes every pass as <prefix>_<pass>. The ids become a random color per id (ppm) for
+// masking, everything else keeps its linear float values (pfm)
+pub(crate) fn write_passes(prefix: &str, passes: &[Pass], width: u16, height: u16, aovs: &[Aovs], samples: &[u16]) -> Result<()> {
+    for pass in passes {
+        let ids = matches!(pass, Pass::ObjectId | Pass::MaterialId);
+        let path = format!("{prefix}_{}.{}", pass.name(), if ids { "ppm" } else { "pfm" });
+        write_pass(&path, *pass, width, height, aovs, samples).map_err(|e| Error::io(&path, e))?;
+    }
+    Ok(())
+}
+
+fn write_pass(path: &str, pass: Pass, width: u16, height: u16, aovs: &[Aovs], samples: &[u16]) -> std::io::Result<()> {
+    let mut f = fs::File::create(path)?;
+    if matches!(pass, Pass::ObjectId | Pass::MaterialId) {
+        writeln!(f, "P3\n{width} {height}\n255")?;
+        for a in aovs {
+            let id = a.channels(pass, 0)[0] as u32;
+            let h = if id == 0 { 0 } else { sampler::hash(id) };
+            writeln!(f, "{} {} {}", h & 0xff, (h >> 8) & 0xff, (h >> 16) & 0xff)?;
+        }
+        return Ok(());
+    }
+
+    // color pfm, a negative scale means little endian, the rows go bottom to top
+    write!(f, "PF\n{width} {height}\n-1.0\n")?;
+    let mut bytes = Vec::with_capacity(aovs.len() * 12);
+    for row in (0 .. usize::from(height)).rev() {
+        for i in row * usize::from(width) .. (row + 1) * usize::from(width) {
+            for v in aovs[i].channels(pass, samples[i]) {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+    }
+    f.write_all(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{aov::Pass, camera::{Camera, CameraView, RenderOptions}, hit_record::{Hittable, HittableList, Sphere}, material::{Lambertian, MaterialEnum}, progress::Progress, scene::Scene, sky::Sky, vec3d::Vec3d, Color, Point3d};
+
+    #[test]
+    fn every_material_gets_its_own_id() {
+        let lambertian = |r: f32| Arc::new(MaterialEnum::Lambertian(Lambertian { albedo: Color { r, g: 0.5, b: 0.5 } }));
+        let (red, gray) = (lambertian(0.9), lambertian(0.5));
+        let mut world = HittableList::default();
+        world.add(Hittable::Sphere(Sphere::new(Point3d::new(-1.5, 0.0, 0.0), 1.0, red.clone())));
+        world.add(Hittable::Sphere(Sphere::new(Point3d::new(1.5, 0.0, 0.0), 1.0, gray)));
+        world.add(Hittable::Sphere(Sphere::new(Point3d::new(0.0, 1.5, 0.0), 0.5, red)));
+
+        let view = CameraView {
+            vfov: 90.0,
+            lookfrom: Point3d::new(0.0, 0.0, 3.0),
+            lookat: Point3d::new(0.0, 0.0, 0.0),
+            vup: Vec3d::new(0.0, 1.0, 0.0),
+            defocus_angle: 0.0,
+            focus_dist: 3.0,
+        };
+        let options = RenderOptions { aovs: vec![Pass::MaterialId], ..RenderOptions::default() };
+        let camera = Arc::new(Camera::initialize(1.0, 16, 4, 1, view).with_options(options));
+        Camera::render(camera.clone(), Arc::new(Scene { world, sky: Sky::Gradient }), 1, &mut Progress::quiet()).unwrap();
+
+        // the left and the right ball on row 8, the small one above the middle on row 4
+        let aovs = camera.film.lock().unwrap().averaged_aovs();
+        let id = |x: usize, y: usize| aovs[y * 16 + x].material_id;
+        let (left, right, top) = (id(3, 8), id(12, 8), id(8, 4));
+        assert!(left != 0 && right != 0 && left != right, "{left} {right}");
+        assert_eq!(top, left);
+        assert_eq!(id(8, 15), 0);
+    }
+}
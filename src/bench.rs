@@ -0,0 +1,260 @@
+use std::{fs, sync::Arc, time::{Duration, Instant}};
+
+use config::{Config, File, FileFormat};
+use serde_derive::Deserialize;
+
+use crate::{bhv::BvhNode, camera::{Camera, CameraView, Ray}, error::{Error, Result}, hit_record::{Hit, Hittable, HittableList, Sphere, Triangle}, interval::Interval, material::{Dielectric, Lambertian, MaterialEnum, Metal}, progress::Progress, sampler::{self, Pcg32, SamplerKind}, scene::Scene, sky::Sky, vec3d::Vec3d, Color, Point3d};
+
+const SEED: u64 = 7;
+const WIDTH: u16 = 160;
+const MAX_DEPTH: u8 = 8;
+const SPP: u16 = 4;
+const INCOHERENT_RAYS: u32 = 200_000;
+// the camera rays of the small image are cast this often per run, too fast to time once
+const PRIMARY_PASSES: usize = 10;
+const RUNS: u32 = 5;
+// slower than this fraction of the baseline counts as a regression, unless given
+const TOLERANCE: f64 = 0.10;
+
+// One benchmark scene. Times are the best of a few runs, the least noisy estimate
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct BenchResult {
+    pub(crate) scene: String,
+    pub(crate) objects: usize,
+    pub(crate) build_seconds: f64,
+    pub(crate) primary_rays_per_second: f64,
+    pub(crate) incoherent_rays_per_second: f64,
+    pub(crate) render_seconds_per_spp: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct BenchFile {
+    results: Vec<BenchResult>,
+}
+
+// Fixed seed scenes: random spheres of three sizes and a torus of triangles, which clusters
+// its primitives on a surface the way a mesh does
+fn scenes() -> Vec<(&'static str, HittableList)> {
+    vec![
+        ("spheres_500", random_spheres(500)),
+        ("spheres_2000", random_spheres(2000)),
+        ("spheres_5000", random_spheres(5000)),
+        ("torus_mesh", torus_mesh(96, 32)),
+    ]
+}
+
+fn random_material(rng: &mut Pcg32) -> Arc<MaterialEnum> {
+    let mut color = || Color{r: rng.random(), g: rng.random(), b: rng.random()};
+    let albedo = color();
+    Arc::new(match rng.random() {
+        0.0 .. 0.8 => MaterialEnum::Lambertian(Lambertian{albedo}),
+        0.8 .. 0.95 => MaterialEnum::Metal(Metal{albedo, fuzz: 0.2}),
+        _ => MaterialEnum::Dielectric(Dielectric::new(1.5)),
+    })
+}
+
+fn random_spheres(n: usize) -> HittableList {
+    let mut rng = Pcg32::new(SEED);
+    let mut list = HittableList::default();
+    let ground = Arc::new(MaterialEnum::Lambertian(Lambertian{albedo: Color{r: 0.5, g: 0.5, b: 0.5}}));
+    list.add(Hittable::Sphere(Sphere::new(Point3d::new(0.0, -1000.0, 0.0), 1000.0, ground)));
+
+    // same density for every count, so bigger scenes are bigger and not just more crowded
+    let extent = (n as f32).sqrt() * 0.5;
+    for _ in 0 .. n {
+        let center = Point3d::new(rng.random_range(-extent .. extent), 0.2, rng.random_range(-extent .. extent));
+        let material = random_material(&mut rng);
+        list.add(Hittable::Sphere(Sphere::new(center, 0.2, material)));
+    }
+    list
+}
+
+// Two triangles for every quad of a segments x sides grid on the torus
+fn torus_mesh(segments: u32, sides: u32) -> HittableList {
+    let mut rng = Pcg32::new(SEED);
+    let mut list = HittableList::default();
+    let (major, minor) = (3.0, 1.0);
+    let point = |s: u32, t: u32| {
+        let u = 2.0 * std::f32::consts::PI * s as f32 / segments as f32;
+        let v = 2.0 * std::f32::consts::PI * t as f32 / sides as f32;
+        Point3d::new(
+            (major + minor * v.cos()) * u.cos(),
+            1.0 + minor * v.sin(),
+            (major + minor * v.cos()) * u.sin(),
+        )
+    };
+
+    for s in 0 .. segments {
+        for t in 0 .. sides {
+            let material = random_material(&mut rng);
+            let (a, b, c, d) = (point(s, t), point(s + 1, t), point(s + 1, t + 1), point(s, t + 1));
+            list.add(Hittable::Triangle(Triangle::new(a.clone(), b, c.clone(), material.clone())));
+            list.add(Hittable::Triangle(Triangle::new(a, c, d, material)));
+        }
+    }
+    list
+}
+
+fn best_of<T>(runs: u32, mut f: impl FnMut() -> T) -> (Duration, T) {
+    let mut best = None;
+    for _ in 0 .. runs {
+        let start = Instant::now();
+        let v = f();
+        let elapsed = start.elapsed();
+        if best.as_ref().is_none_or(|(b, _)| elapsed < *b) {
+            best = Some((elapsed, v));
+        }
+    }
+    best.unwrap()
+}
+
+fn camera(spp: u16) -> Camera {
+    let cv = CameraView {
+        vfov: 30.0,
+        lookfrom: Point3d::new(13.0, 4.0, 3.0),
+        lookat: Point3d::new(0.0, 0.0, 0.0),
+        vup: Vec3d::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.0,
+        focus_dist: 10.0,
+    };
+    Camera::initialize(16.0 / 9.0, WIDTH, MAX_DEPTH, spp, cv)
+}
+
+fn run_scene(name: &str, list: HittableList) -> Result<BenchResult> {
+    let objects = list.len();
+    let (build, world) = best_of(RUNS, || {
+        let mut list = list.clone();
+        HittableList::new(Hittable::BvhNode(BvhNode::new(&mut list)))
+    });
+
+    // camera rays through every pixel center, neighbours take similar paths through the BVH
+    let cam = camera(1);
+    let primary: Vec<Ray> = (0 .. cam.image_height).flat_map(|j| (0 .. cam.image_width).map(move |i| (i, j)))
+        .map(|(i, j)| {
+            sampler::start_sample(SamplerKind::Independent, 0, 1, i, j, 0);
+            cam.get_ray(i, j, &Vec3d::new(0.0, 0.0, 0.0))
+        })
+        .collect();
+
+    // rays from random points in the scene into random directions, like diffuse bounces
+    let bbox = world.bounding_box();
+    let mut rng = Pcg32::new(SEED);
+    let incoherent: Vec<Ray> = (0 .. INCOHERENT_RAYS).map(|_| {
+        // the ground sphere makes the box huge, stay near the objects
+        let mut within = |i: &Interval| {
+            let (lo, hi) = (i.min.max(-50.0), i.max.min(50.0));
+            rng.random_range(lo .. hi.max(lo + 1e-3))
+        };
+        let origin = Point3d::new(within(&bbox.x), within(&bbox.y), within(&bbox.z));
+        let direction = Vec3d::new(rng.random_range(-1.0 .. 1.0), rng.random_range(-1.0 .. 1.0), rng.random_range(-1.0 .. 1.0));
+        Ray::new(origin, direction, None)
+    }).collect();
+
+    let cast = |rays: &[Ray], passes: usize| (0 .. passes)
+        .map(|_| rays.iter().filter(|r| world.hit(r, Interval::new(0.001, f32::INFINITY)).is_some()).count())
+        .sum::<usize>();
+    let (primary_time, _) = best_of(RUNS, || cast(&primary, PRIMARY_PASSES));
+    let (incoherent_time, _) = best_of(RUNS, || cast(&incoherent, 1));
+
+    // whole renders on one thread, the time per sample doesn't depend on the core count
+    let scene = Arc::new(Scene { world, sky: Sky::Gradient });
+    let mut progress = Progress::quiet();
+    let (render, rendered) = best_of(3, || Camera::render(Arc::new(camera(SPP)), scene.clone(), 1, &mut progress));
+    rendered?;
+
+    let result = BenchResult {
+        scene: name.to_string(),
+        objects,
+        build_seconds: build.as_secs_f64(),
+        primary_rays_per_second: (primary.len() * PRIMARY_PASSES) as f64 / primary_time.as_secs_f64(),
+        incoherent_rays_per_second: incoherent.len() as f64 / incoherent_time.as_secs_f64(),
+        render_seconds_per_spp: render.as_secs_f64() / f64::from(SPP),
+    };
+    println!("{:<14} {:>6} objects  build {:>8.2}ms  primary {:>10.0} rays/s  incoherent {:>10.0} rays/s  render {:>7.3}s/spp",
+        result.scene, result.objects, 1000.0 * result.build_seconds, result.primary_rays_per_second,
+        result.incoherent_rays_per_second, result.render_seconds_per_spp);
+    Ok(result)
+}
+
+fn to_json(results: &[BenchResult]) -> String {
+    let entries: Vec<String> = results.iter().map(|r| format!(
+        "    {{\"scene\": \"{}\", \"objects\": {}, \"build_seconds\": {:.6}, \"primary_rays_per_second\": {:.0}, \"incoherent_rays_per_second\": {:.0}, \"render_seconds_per_spp\": {:.6}}}",
+        r.scene, r.objects, r.build_seconds, r.primary_rays_per_second, r.incoherent_rays_per_second, r.render_seconds_per_spp)).collect();
+    format!("{{\n  \"results\": [\n{}\n  ]\n}}\n", entries.join(",\n"))
+}
+
+fn load(path: &str) -> Result<Vec<BenchResult>> {
+    let file: BenchFile = Config::builder()
+        .add_source(File::new(path, FileFormat::Json))
+        .build()
+        .and_then(|c| c.try_deserialize())
+        .map_err(|e| Error::Asset { file: path.to_string(), message: e.to_string() })?;
+    Ok(file.results)
+}
+
+// Relative slowdowns of the new results, positive is slower. Throughputs are turned into
+// times so every metric compares the same way
+pub(crate) fn compare(baseline: &[BenchResult], current: &[BenchResult]) -> Vec<(String, f64)> {
+    let mut changes = vec![];
+    for c in current {
+        let Some(b) = baseline.iter().find(|b| b.scene == c.scene) else {
+            continue;
+        };
+        let metrics = [
+            ("build", c.build_seconds / b.build_seconds),
+            ("primary", b.primary_rays_per_second / c.primary_rays_per_second),
+            ("incoherent", b.incoherent_rays_per_second / c.incoherent_rays_per_second),
+            ("render", c.render_seconds_per_spp / b.render_seconds_per_spp),
+        ];
+        for (metric, ratio) in metrics {
+            changes.push((format!("{} {metric}", c.scene), ratio - 1.0));
+        }
+    }
+    changes
+}
+
+/// Runs every benchmark scene and writes the results to output. With a baseline the results
+/// are compared to it, returns false if anything got slower than the tolerance
+pub fn run(output: &str, baseline: Option<&str>, tolerance: Option<f64>) -> Result<bool> {
+    let tolerance = tolerance.unwrap_or(TOLERANCE);
+    let baseline = baseline.map(load).transpose()?;
+
+    let results = scenes().into_iter().map(|(name, list)| run_scene(name, list)).collect::<Result<Vec<BenchResult>>>()?;
+    fs::write(output, to_json(&results)).map_err(|e| Error::io(output, e))?;
+    println!("Saved benchmark results to {output}");
+
+    let Some(baseline) = baseline else {
+        return Ok(true);
+    };
+    let mut passed = true;
+    for (metric, change) in compare(&baseline, &results) {
+        let regressed = change > tolerance;
+        passed &= !regressed;
+        println!("{metric:<28} {:+6.1}%{}", 100.0 * change, if regressed { "  REGRESSION" } else { "" });
+    }
+    Ok(passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench::{compare, BenchResult};
+
+    fn result(render_seconds_per_spp: f64) -> BenchResult {
+        BenchResult {
+            scene: "spheres_500".to_string(),
+            objects: 500,
+            build_seconds: 0.01,
+            primary_rays_per_second: 1e6,
+            incoherent_rays_per_second: 5e5,
+            render_seconds_per_spp,
+        }
+    }
+
+    #[test]
+    fn slower_renders_are_positive_changes() {
+        let changes = compare(&[result(1.0)], &[result(1.5)]);
+        let render = changes.iter().find(|(m, _)| m == "spheres_500 render").unwrap();
+        assert!((render.1 - 0.5).abs() < 1e-9);
+        assert!(changes.iter().filter(|(m, _)| m != "spheres_500 render").all(|(_, c)| c.abs() < 1e-9));
+    }
+}
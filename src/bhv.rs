@@ -1,11 +1,10 @@
 use std::{cmp::Ordering, sync::Arc};
 
-use rand::Rng;
-
-use crate::{aabb::Aabb, camera::Ray, hit_record::{Hit, HitRecord, Hittable, HittableList}, interval::Interval, material::MaterialEnum};
+use crate::{aabb::Aabb, camera::Ray, hit_record::{Hit, HitRecord, Hittable, HittableList}, interval::Interval, material::MaterialEnum, stats::{self, Counter}, Point3d};
 
+/// Bounding volume hierarchy over a list of objects
 #[derive(Clone)]
-pub(crate) struct BvhNode {
+pub struct BvhNode {
     left: Arc<Hittable>,
     right: Arc<Hittable>,
     bbox: Aabb,
@@ -13,6 +12,7 @@ pub(crate) struct BvhNode {
 
 impl Hit for BvhNode {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<(HitRecord, Arc<MaterialEnum>)> {
+        stats::count(Counter::BvhNodeTests);
         if !self.bbox.hit(r, ray_t.clone()) {
             return None;
         }
@@ -38,14 +38,10 @@ impl Hit for BvhNode {
 
 }
 
+// A total order, comparing with a tolerance isn't transitive and makes the sort panic
+// when many boxes start within the tolerance of each other
 fn box_compare(a: &Hittable, b: &Hittable, axis_index: i32) -> Ordering {
-    if a.bounding_box().axis_interval(axis_index).min < b.bounding_box().axis_interval(axis_index).min - 0.001 {
-        Ordering::Less
-    } else if a.bounding_box().axis_interval(axis_index).min > b.bounding_box().axis_interval(axis_index).min + 0.001 {
-        Ordering::Greater
-    } else {
-        Ordering::Equal
-    }
+    a.bounding_box().axis_interval(axis_index).min.total_cmp(&b.bounding_box().axis_interval(axis_index).min)
 }
 
 fn x_axis_comparator(a: &Hittable, b: &Hittable) -> Ordering {
@@ -61,7 +57,8 @@ fn z_axis_comparator(a: &Hittable, b: &Hittable) -> Ordering {
 }
 
 impl BvhNode {
-    pub(crate) fn new(hittable_list: &mut HittableList) -> BvhNode {
+    /// Builds the tree over the objects of the list, which are reordered
+    pub fn new(hittable_list: &mut HittableList) -> BvhNode {
         let x = hittable_list.objects.len();
         return BvhNode::from_list(&mut hittable_list.objects, 0, x);
     }
@@ -115,4 +112,124 @@ impl BvhNode {
             bbox
         }
     }
+
+    pub(crate) fn for_each_material(&self, f: &mut dyn FnMut(&Arc<MaterialEnum>)) {
+        self.left.for_each_material(f);
+        self.right.for_each_material(f);
+    }
+
+    // See Hittable::replace_sphere_material, the tree keeps its shape
+    pub(crate) fn replace_sphere_material(&self, pick: &dyn Fn(&Point3d, f32) -> bool, material: &Arc<MaterialEnum>) -> Option<BvhNode> {
+        let left = self.left.replace_sphere_material(pick, material);
+        let right = self.right.replace_sphere_material(pick, material);
+        if left.is_none() && right.is_none() {
+            return None;
+        }
+        Some(BvhNode {
+            left: left.map_or_else(|| self.left.clone(), Arc::new),
+            right: right.map_or_else(|| self.right.clone(), Arc::new),
+            bbox: self.bbox.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{aabb::Aabb, camera::Ray, hit_record::{Hit, HitRecord, Hittable, HittableList, Instance, Sphere, Triangle}, interval::Interval, material::{Lambertian, Material, MaterialEnum}, bhv::BvhNode, vec3d::Vec3d, Color, Point3d};
+
+    // A square in the z = 0 plane, the kind of shape a downstream crate would add
+    struct Square {
+        center: Point3d,
+        half: f32,
+        bbox: Aabb,
+        material: Arc<MaterialEnum>,
+    }
+
+    impl Hit for Square {
+        fn hit(&self, r: &Ray, ray_t: Interval) -> Option<(HitRecord, Arc<MaterialEnum>)> {
+            let t = (self.center.0.z - r.origin.0.z) / r.direction.z;
+            if !ray_t.surrounds(t) {
+                return None;
+            }
+            let p = r.at(t);
+            if (p.0.x - self.center.0.x).abs() > self.half || (p.0.y - self.center.0.y).abs() > self.half {
+                return None;
+            }
+            let mut hr = HitRecord { point: p, t, object_id: 99, ..HitRecord::default() };
+            hr.set_face_normal(r, Vec3d::new(0.0, 0.0, 1.0));
+            Some((hr, self.material.clone()))
+        }
+
+        fn bounding_box(&self) -> &Aabb {
+            &self.bbox
+        }
+    }
+
+    // Absorbs everything, enough to tell it from the built-in materials
+    struct Black;
+
+    impl Material for Black {
+        fn scatter(&self, ray_in: &Ray, hr: &HitRecord) -> (Ray, Color, bool) {
+            (Ray::new(hr.point.clone(), hr.normal.clone(), Some(ray_in.tm)), Color::default(), false)
+        }
+    }
+
+    #[test]
+    fn custom_shapes_are_found_through_the_bvh() {
+        let gray = Arc::new(MaterialEnum::Lambertian(Lambertian{albedo: Color{r: 0.5, g: 0.5, b: 0.5}}));
+        let black = Arc::new(MaterialEnum::Custom(Arc::new(Black)));
+        let mut list = HittableList::default();
+        for i in 0 .. 8 {
+            list.add(Hittable::Sphere(Sphere::new(Point3d::new(i as f32 * 3.0 - 12.0, 0.0, -5.0), 1.0, gray.clone())));
+        }
+        let center = Point3d::new(1.5, 4.0, -2.0);
+        let bbox = Aabb::from_points(&Point3d::new(1.0, 3.5, -2.0), &Point3d::new(2.0, 4.5, -2.0));
+        list.add(Hittable::Custom(Arc::new(Square { center, half: 0.5, bbox, material: black })));
+        let bvh = BvhNode::new(&mut list);
+
+        let r = Ray::new(Point3d::new(1.5, 4.0, 5.0), Vec3d::new(0.0, 0.0, -1.0), None);
+        let (hr, mat) = bvh.hit(&r, Interval::new(0.001, f32::INFINITY)).unwrap();
+        assert_eq!(hr.object_id, 99);
+        assert!((hr.t - 7.0).abs() < 1e-4);
+        assert!(!mat.scatter(&r, &hr).2);
+        assert!(matches!(*mat, MaterialEnum::Custom(_)));
+
+        // the spheres behind it are still there
+        let r = Ray::new(Point3d::new(-12.0, 0.0, 5.0), Vec3d::new(0.0, 0.0, -1.0), None);
+        assert!(bvh.hit(&r, Interval::new(0.001, f32::INFINITY)).is_some_and(|(hr, _)| hr.object_id != 99));
+    }
+
+    #[test]
+    fn instances_move_and_scale_their_object() {
+        let gray = Arc::new(MaterialEnum::Lambertian(Lambertian{albedo: Color{r: 0.5, g: 0.5, b: 0.5}}));
+        // a triangle in the z = 0 plane around the origin, facing +z
+        let mut shape = HittableList::default();
+        shape.add(Hittable::Triangle(Triangle::new(Point3d::new(-1.0, -1.0, 0.0), Point3d::new(1.0, -1.0, 0.0), Point3d::new(0.0, 1.0, 0.0), gray.clone())));
+        let shape = Arc::new(Hittable::BvhNode(BvhNode::new(&mut shape)));
+
+        let mut list = HittableList::default();
+        list.add(Hittable::Instance(Instance::new(shape.clone(), Vec3d::new(0.0, 0.0, -5.0), 1.0)));
+        list.add(Hittable::Instance(Instance::new(shape, Vec3d::new(10.0, 0.0, -5.0), 3.0)));
+        let bvh = BvhNode::new(&mut list);
+
+        let r = Ray::new(Point3d::new(0.0, 0.0, 5.0), Vec3d::new(0.0, 0.0, -1.0), None);
+        let (hr, _) = bvh.hit(&r, Interval::new(0.001, f32::INFINITY)).unwrap();
+        assert!((hr.t - 10.0).abs() < 1e-4 && hr.front_face && hr.normal.z > 0.99);
+        assert_eq!(hr.object_id, 1);
+
+        // (10, 2) is above the unscaled triangle but inside the scaled one
+        let r = Ray::new(Point3d::new(10.0, 2.0, 5.0), Vec3d::new(0.0, 0.0, -2.0), None);
+        let (hr, _) = bvh.hit(&r, Interval::new(0.001, f32::INFINITY)).unwrap();
+        assert!((hr.t - 5.0).abs() < 1e-4 && (hr.point.0.y - 2.0).abs() < 1e-4, "{} {:?}", hr.t, hr.point);
+        assert_eq!(hr.object_id, 2);
+        let r = Ray::new(Point3d::new(12.5, 2.0, 5.0), Vec3d::new(0.0, 0.0, -1.0), None);
+        assert!(bvh.hit(&r, Interval::new(0.001, f32::INFINITY)).is_none());
+
+        // a degenerate triangle is never hit
+        let line = Triangle::new(Point3d::new(-1.0, 0.0, 0.0), Point3d::new(0.0, 0.0, 0.0), Point3d::new(1.0, 0.0, 0.0), gray);
+        let r = Ray::new(Point3d::new(0.0, 0.0, 5.0), Vec3d::new(0.0, 0.0, -1.0), None);
+        assert!(line.hit(&r, Interval::new(0.001, f32::INFINITY)).is_none());
+    }
 }
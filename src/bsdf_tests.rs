@@ -0,0 +1,274 @@
+// Physical correctness of the materials: white furnace renders, and for every material
+// with a density the reciprocity of eval(), the normalization of pdf() and a chi-square
+// test of the directions scatter() samples against pdf()
+use std::{f32::consts::PI, sync::Arc};
+
+use crate::{camera::{Camera, CameraView, Ray, RenderOptions}, config, hit_record::{HitRecord, Hittable, HittableList, Sphere}, material::{Dielectric, Lambertian, Material, MaterialEnum, Metal, RoughConductor, RoughDielectric}, microfacet::Ggx, principled::Principled, progress::Progress, scene::Scene, sky::Sky, spectrum::Dispersion, texture::Texture, vec3d::Vec3d, Color, Point3d};
+
+const WHITE: Color = Color{r: 1.0, g: 1.0, b: 1.0};
+
+// a perfect mirror conductor, the Fresnel reflectance of a huge extinction is 1
+fn mirror_conductor(roughness: f32, anisotropy: f32) -> MaterialEnum {
+    MaterialEnum::RoughConductor(RoughConductor{eta: WHITE, k: WHITE * 1e4, distribution: Ggx::new(roughness, anisotropy)})
+}
+
+fn rough_glass(roughness: f32) -> MaterialEnum {
+    MaterialEnum::RoughDielectric(RoughDielectric{refraction_index: 1.5, distribution: Ggx::new(roughness, 0.0), absorption: Color::default()})
+}
+
+fn principled(metallic: f32, roughness: f32, transmission: f32) -> MaterialEnum {
+    MaterialEnum::Principled(Box::new(Principled{
+        base_color: Texture::Solid(WHITE),
+        metallic: Texture::constant(metallic),
+        roughness: Texture::constant(roughness),
+        specular: Texture::constant(0.5),
+        clearcoat: Texture::constant(0.0),
+        clearcoat_roughness: 0.1,
+        sheen: Texture::constant(0.0),
+        transmission: Texture::constant(transmission),
+        ior: 1.5,
+        anisotropy: 0.0,
+    }))
+}
+
+// Mean pixel of a sphere of the material in a white furnace, the sky is 1 everywhere.
+// Materials that neither absorb nor emit disappear, every pixel stays at 1
+fn furnace(material: MaterialEnum) -> f32 {
+    furnace_with(material, RenderOptions::default())
+}
+
+fn furnace_with(material: MaterialEnum, options: RenderOptions) -> f32 {
+    let mut world = HittableList::default();
+    world.add(Hittable::Sphere(Sphere::new(Point3d::new(0.0, 0.0, 0.0), 1.0, Arc::new(material))));
+    let sky = Sky::new(&Some(config::Sky {
+        model: "uniform".to_string(), radiance: None, turbidity: None, intensity: None, sun_direction: None,
+        day_of_year: None, solar_time: None, latitude: None, sun_irradiance: None, sun_angular_radius: None,
+    })).unwrap();
+
+    let cv = CameraView {
+        vfov: 30.0,
+        lookfrom: Point3d::new(0.0, 0.0, 4.0),
+        lookat: Point3d::new(0.0, 0.0, 0.0),
+        vup: Vec3d::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.0,
+        focus_dist: 1.0,
+    };
+    // the sphere covers the whole image, the corners included
+    let camera = Arc::new(Camera::initialize(1.0, 12, 64, 64, cv).with_options(options));
+    let mut progress = Progress::quiet();
+    Camera::render(camera.clone(), Arc::new(Scene { world, sky }), 1, &mut progress).unwrap();
+
+    let pixels = camera.pixels.lock().unwrap();
+    pixels.iter().map(|c| c.luminance()).sum::<f32>() / pixels.len() as f32
+}
+
+#[test]
+fn lossless_materials_vanish_in_the_furnace() {
+    let lossless = [
+        ("lambertian", MaterialEnum::Lambertian(Lambertian{albedo: WHITE})),
+        ("mirror", MaterialEnum::Metal(Metal{albedo: WHITE, fuzz: 0.0})),
+        ("glass", MaterialEnum::Dielectric(Dielectric::new(1.5))),
+    ];
+    for (name, material) in lossless {
+        let e = furnace(material);
+        assert!((e - 1.0).abs() < 1e-3, "{name} {e}");
+    }
+}
+
+// Dispersive glass keeps only the hero wavelength past its first refraction, a path
+// through the sphere and out must not be scaled up at every later hit
+#[test]
+fn dispersive_glass_vanishes_in_the_spectral_furnace() {
+    let bk7 = Dielectric { dispersion: Dispersion::preset("bk7"), ..Dielectric::new(1.5) };
+    let e = furnace_with(MaterialEnum::Dielectric(bk7), RenderOptions { spectral: true, ..RenderOptions::default() });
+    assert!((e - 1.0).abs() < 5e-2, "{e}");
+}
+
+// Single scattering microfacet models lose the light that would bounce between the
+// microfacets, more the rougher they are, but they must never create energy
+#[test]
+fn rough_materials_never_gain_energy_in_the_furnace() {
+    let rough = [
+        ("fuzzy metal", MaterialEnum::Metal(Metal{albedo: WHITE, fuzz: 0.5}), 0.6),
+        ("rough conductor", mirror_conductor(0.5, 0.0), 0.8),
+        ("rough glass", rough_glass(0.5), 0.8),
+        ("principled metal", principled(1.0, 0.5, 0.0), 0.8),
+        ("principled glass", principled(0.0, 0.5, 1.0), 0.8),
+        // a white diffuse base under the specular coat
+        ("smooth plastic", principled(0.0, 0.05, 0.0), 0.97),
+        ("rough plastic", principled(0.0, 0.7, 0.0), 0.9),
+    ];
+    for (name, material, lower) in rough {
+        let e = furnace(material);
+        assert!(e <= 1.0 + 2e-2 && e >= lower, "{name} {e}");
+    }
+}
+
+fn hit_record() -> HitRecord {
+    // tilted, so the shading frame isn't aligned with the world axes
+    HitRecord { normal: Vec3d::unit(&Vec3d::new(0.3, 0.5, 0.8)), front_face: true, ..HitRecord::default() }
+}
+
+// Ray arriving from the direction wo (pointing away from the surface)
+fn incoming(wo: &Vec3d) -> Ray {
+    Ray::new(Point3d::from_vec3d(wo.clone()), Vec3d::mul(wo, -1.0), None)
+}
+
+// Direction at the given angle from the normal of hit_record()
+fn direction(hr: &HitRecord, cos_theta: f32, phi: f32) -> Vec3d {
+    let (t, b) = Vec3d::basis(&hr.normal);
+    let sin_theta = f32::sqrt((1.0 - cos_theta * cos_theta).max(0.0));
+    t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + hr.normal.clone() * cos_theta
+}
+
+// Materials with a density, at grazing and steep incidence
+fn sampled_materials() -> Vec<(&'static str, MaterialEnum)> {
+    vec![
+        ("lambertian", MaterialEnum::Lambertian(Lambertian{albedo: WHITE * 0.8})),
+        ("rough conductor", mirror_conductor(0.5, 0.0)),
+        ("anisotropic conductor", mirror_conductor(0.6, 0.7)),
+        ("rough glass", rough_glass(0.5)),
+        ("principled", principled(0.3, 0.5, 0.0)),
+        ("principled glass", principled(0.0, 0.6, 0.7)),
+    ]
+}
+
+const INCIDENT_COSINES: [f32; 2] = [0.9, 0.3];
+
+#[test]
+fn reflection_is_reciprocal() {
+    let hr = hit_record();
+    for (name, material) in sampled_materials() {
+        for i in 0 .. 200 {
+            let a = direction(&hr, 0.05 + 0.9 * (i % 10) as f32 / 10.0, 0.7 * i as f32);
+            let b = direction(&hr, 0.05 + 0.9 * (i / 20) as f32 / 10.0, 2.0 + 1.3 * i as f32);
+            let (Some(f_ab), Some(f_ba)) = (material.eval(&incoming(&a), &hr, &b), material.eval(&incoming(&b), &hr, &a)) else {
+                continue;
+            };
+            // eval is the BSDF times the cosine of the scattered direction
+            let cos = |v: &Vec3d| Vec3d::dot(v, &hr.normal);
+            let (ab, ba) = (f_ab.luminance() / cos(&b), f_ba.luminance() / cos(&a));
+            assert!((ab - ba).abs() <= 1e-3 * ab.max(ba).max(1.0), "{name} {ab} {ba}");
+        }
+    }
+}
+
+// Integral of the density over the sphere, by stratified integration in cos theta and phi
+// (equal solid angle cells)
+fn integrate_pdf(material: &MaterialEnum, hr: &HitRecord, wo: &Vec3d, cells: (usize, usize), range: ((f32, f32), (f32, f32))) -> f32 {
+    let ((cos_lo, cos_hi), (phi_lo, phi_hi)) = range;
+    let (n_cos, n_phi) = cells;
+    let cell = (cos_hi - cos_lo) / n_cos as f32 * (phi_hi - phi_lo) / n_phi as f32;
+    let r = incoming(wo);
+    let mut sum = 0.0;
+    for i in 0 .. n_cos {
+        for j in 0 .. n_phi {
+            let cos_theta = cos_lo + (cos_hi - cos_lo) * (i as f32 + 0.5) / n_cos as f32;
+            let phi = phi_lo + (phi_hi - phi_lo) * (j as f32 + 0.5) / n_phi as f32;
+            sum += material.pdf(&r, hr, &direction(hr, cos_theta, phi)) * cell;
+        }
+    }
+    sum
+}
+
+#[test]
+fn densities_integrate_to_the_scattered_fraction() {
+    let hr = hit_record();
+    for (name, material) in sampled_materials() {
+        for cos_o in INCIDENT_COSINES {
+            let wo = direction(&hr, cos_o, 0.4);
+            let total = integrate_pdf(&material, &hr, &wo, (400, 400), ((-1.0, 1.0), (0.0, 2.0 * PI)));
+
+            // samples that aren't scattered (below the surface for a reflection) are missing
+            // from the density, so it integrates to the fraction of scattered samples
+            let n = 20_000;
+            let scattered = (0 .. n).filter(|_| material.scatter(&incoming(&wo), &hr).2).count() as f32 / n as f32;
+            assert!(total <= 1.0 + 1e-2, "{name} at cos {cos_o}: {total}");
+            assert!((total - scattered).abs() < 2e-2, "{name} at cos {cos_o}: {total} vs {scattered} scattered");
+        }
+    }
+}
+
+// Upper quantile of the chi-square distribution at p = 0.001 (Wilson-Hilferty)
+fn chi_square_critical(dof: usize) -> f64 {
+    let k = dof as f64;
+    let z = 3.09;
+    k * (1.0 - 2.0 / (9.0 * k) + z * (2.0 / (9.0 * k)).sqrt()).powi(3)
+}
+
+#[test]
+fn sampled_directions_follow_the_density() {
+    const COS_BINS: usize = 10;
+    const PHI_BINS: usize = 20;
+    const SAMPLES: usize = 100_000;
+
+    let hr = hit_record();
+    let (t, b) = Vec3d::basis(&hr.normal);
+    for (name, material) in sampled_materials() {
+        for cos_o in INCIDENT_COSINES {
+            let wo = direction(&hr, cos_o, 0.4);
+
+            // the last bin counts the samples that weren't scattered
+            let mut observed = vec![0.0f64; COS_BINS * PHI_BINS + 1];
+            for _ in 0 .. SAMPLES {
+                let (r, _, scattered) = material.scatter(&incoming(&wo), &hr);
+                if !scattered {
+                    observed[COS_BINS * PHI_BINS] += 1.0;
+                    continue;
+                }
+                let d = Vec3d::unit(&r.direction);
+                let cos_theta = Vec3d::dot(&d, &hr.normal).clamp(-1.0, 1.0);
+                let phi = Vec3d::dot(&d, &b).atan2(Vec3d::dot(&d, &t)).rem_euclid(2.0 * PI);
+                let i = (((cos_theta + 1.0) / 2.0 * COS_BINS as f32) as usize).min(COS_BINS - 1);
+                let j = ((phi / (2.0 * PI) * PHI_BINS as f32) as usize).min(PHI_BINS - 1);
+                observed[i * PHI_BINS + j] += 1.0;
+            }
+
+            let mut expected = vec![0.0f64; COS_BINS * PHI_BINS + 1];
+            for i in 0 .. COS_BINS {
+                for j in 0 .. PHI_BINS {
+                    let cos_range = (-1.0 + 2.0 * i as f32 / COS_BINS as f32, -1.0 + 2.0 * (i + 1) as f32 / COS_BINS as f32);
+                    let phi_range = (2.0 * PI * j as f32 / PHI_BINS as f32, 2.0 * PI * (j + 1) as f32 / PHI_BINS as f32);
+                    let p = integrate_pdf(&material, &hr, &wo, (16, 16), (cos_range, phi_range));
+                    expected[i * PHI_BINS + j] = f64::from(p) * SAMPLES as f64;
+                }
+            }
+            let scattered: f64 = expected.iter().sum();
+            expected[COS_BINS * PHI_BINS] = (SAMPLES as f64 - scattered).max(0.0);
+
+            // bins expecting too few samples are pooled, the statistic needs about 5 per bin
+            let (mut chi2, mut dof) = (0.0, 0);
+            let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+            for (o, e) in observed.iter().zip(&expected) {
+                if *e < 5.0 {
+                    pooled_observed += o;
+                    pooled_expected += e;
+                } else {
+                    chi2 += (o - e) * (o - e) / e;
+                    dof += 1;
+                }
+            }
+            if pooled_expected >= 5.0 {
+                chi2 += (pooled_observed - pooled_expected) * (pooled_observed - pooled_expected) / pooled_expected;
+                dof += 1;
+            }
+            let critical = chi_square_critical(dof - 1);
+            assert!(chi2 < critical, "{name} at cos {cos_o}: chi2 {chi2:.1} over {critical:.1} with {} dof", dof - 1);
+        }
+    }
+}
+
+// Materials without a density scatter into known directions, the glass chooses between
+// them with the Fresnel reflectance
+#[test]
+fn smooth_glass_reflects_the_fresnel_fraction() {
+    let hr = hit_record();
+    let glass = Dielectric::new(1.5);
+    let wo = direction(&hr, 0.5, 1.0);
+    let n = 50_000;
+    let reflected = (0 .. n).filter(|_| Vec3d::dot(&glass.scatter(&incoming(&wo), &hr).0.direction, &hr.normal) > 0.0).count();
+    // Schlick's approximation used by the material, at cos 0.5
+    let r0 = (0.5f32 / 2.5).powi(2);
+    let fresnel = r0 + (1.0 - r0) * 0.5f32.powi(5);
+    assert!((reflected as f32 / n as f32 - fresnel).abs() < 0.01, "{reflected} {fresnel}");
+}
@@ -1,37 +1,142 @@
 use core::f32;
-use std::{sync::{Arc, Mutex}, thread};
+use std::{sync::{atomic::{AtomicU32, Ordering}, Arc, Mutex}, thread, time::{Duration, Instant}};
 
-use crate::{hit_record::{Hit, HittableList}, interval::Interval, material::Material, vec3d::Vec3d, Color, Point3d};
-use rand::Rng;
+use crate::{aov::{self, Aovs, MaterialIds, Pass}, checkpoint::{self, Checkpointing}, config::{self, Settings}, denoise::Denoiser, error::{unknown, Error, Result}, filter::Filter, hit_record::{Hit, HitRecord}, image::Image, interval::Interval, material::{Material, MaterialEnum}, sampler::{self, SamplerKind}, progress::Progress, scene::Scene, spectrum::{Spectrum, Wavelengths}, stats::{self, Counter, Counters, RenderStats}, vec3d::Vec3d, Color, Point3d};
 
 
+/// A ray from the origin along the direction (not normalized), sent at a shutter time
 #[derive(Debug, Clone)]
 pub struct Ray {
+    /// where the ray starts
     pub origin: Point3d,
+    /// where it goes, not normalized
     pub direction: Vec3d,
+    /// shutter time from 0 to 1, moving spheres are where they are at this time
     pub tm: f32,
+    /// hero wavelength in nanometers, set in the spectral mode only
+    pub wavelength: Option<f32>,
 }
 
 impl Ray {
+    /// A ray at the given shutter time, 0 if None
     pub fn new(origin: Point3d, direction: Vec3d, time: Option<f32>) -> Ray {
-        Ray{origin, direction, tm: time.unwrap_or(0.0)} 
+        Ray{origin, direction, tm: time.unwrap_or(0.0), wavelength: None} 
     }
     
+    /// The point at parameter t along the ray
     pub fn at(&self, t: f32) -> Point3d {
         let p = Vec3d::add(&self.origin.0, &Vec3d::mul(&self.direction, t));
         Point3d(p)
     }
 }
 
+/// Where the camera is and how it is focused
 pub struct CameraView {
-    pub vfov: f32,              // vertical view angle (field of view)
-    pub lookfrom: Point3d,      // point camera is looking from
-    pub lookat: Point3d,        // point camera is looking at
-    pub vup: Vec3d,             // camera-relative up direction
-    pub defocus_angle: f32,     // variation angle of rays through each pixel
-    pub focus_dist: f32,        // distance from camera lookfrom point to plane of perfect focus
+    /// vertical view angle (field of view) in degrees
+    pub vfov: f32,
+    /// point camera is looking from
+    pub lookfrom: Point3d,
+    /// point camera is looking at
+    pub lookat: Point3d,
+    /// camera-relative up direction
+    pub vup: Vec3d,
+    /// variation angle of rays through each pixel in degrees, 0 is a pinhole
+    pub defocus_angle: f32,
+    /// distance from camera lookfrom point to plane of perfect focus
+    pub focus_dist: f32,
 }
 
+impl CameraView {
+    /// The configured view, unset values are those of the book cover
+    pub fn new(c: &Option<config::Camera>) -> CameraView {
+        let c = c.clone().unwrap_or_default();
+        let point = |p: [f32; 3]| Point3d::new(p[0], p[1], p[2]);
+        let up = c.vup.unwrap_or([0.0, 1.0, 0.0]);
+        CameraView {
+            vfov: c.vfov.unwrap_or(20.0),
+            lookfrom: point(c.lookfrom.unwrap_or([13.0, 2.0, 3.0])),
+            lookat: point(c.lookat.unwrap_or([0.0, 0.0, 0.0])),
+            vup: Vec3d::new(up[0], up[1], up[2]),
+            defocus_angle: c.defocus_angle.unwrap_or(0.6),
+            focus_dist: c.focus_dist.unwrap_or(10.0),
+        }
+    }
+
+    /// The `[camera]` settings of this view, every value set
+    pub fn to_settings(&self) -> config::Camera {
+        let array = |v: &Vec3d| [v.x, v.y, v.z];
+        config::Camera {
+            lookfrom: Some(array(&self.lookfrom.0)),
+            lookat: Some(array(&self.lookat.0)),
+            vup: Some(array(&self.vup)),
+            vfov: Some(self.vfov),
+            aspect_ratio: None,
+            defocus_angle: Some(self.defocus_angle),
+            focus_dist: Some(self.focus_dist),
+        }
+    }
+}
+
+/// Renderer settings that don't change the camera geometry
+#[derive(Default, Clone)]
+pub struct RenderOptions {
+    pub(crate) spectral: bool,          // trace wavelengths instead of rgb (dispersion)
+    pub(crate) sampler: SamplerKind,    // source of the sample points (pixel, lens, time, bounces)
+    pub(crate) seed: u32,               // same seed, same image
+    pub(crate) adaptive: Option<Adaptive>,
+    pub(crate) filter: Filter,
+    pub(crate) aovs: Vec<Pass>,
+    pub(crate) denoise: Option<Denoiser>,
+    pub(crate) checkpoint: Option<Checkpointing>,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Adaptive {
+    pub(crate) min_samples: u16,
+    pub(crate) max_samples: u16,
+    // highest accepted standard error of a pixel after the gamma 2 transform
+    pub(crate) threshold: f32,
+}
+
+impl RenderOptions {
+    /// The options of the settings, unknown names are an error
+    pub fn from_settings(c: &Settings) -> Result<RenderOptions> {
+        let sampler = match c.sampler.as_deref() {
+            Some(name) => SamplerKind::from_name(name).ok_or_else(|| unknown("sampler", "sampler", name, &["independent", "stratified", "halton", "sobol"]))?,
+            None => SamplerKind::default(),
+        };
+        Ok(RenderOptions {
+            spectral: c.spectral.unwrap_or(false),
+            sampler,
+            seed: c.seed.unwrap_or(0),
+            adaptive: c.adaptive.as_ref().map(|a| Adaptive {
+                min_samples: a.min_samples.max(2),
+                max_samples: a.max_samples.max(a.min_samples),
+                threshold: a.threshold,
+            }),
+            filter: Filter::new(&c.filter)?,
+            aovs: Pass::from_settings(&c.aov)?,
+            denoise: Denoiser::new(&c.denoise),
+            checkpoint: Checkpointing::new(&c.checkpoint, c),
+        })
+    }
+
+    /// The options of the pass number `pass` of a progressive render, which averages passes
+    /// of a sample per pixel: each pass has another seed, and there is no adaptive sampling,
+    /// denoising, render passes or checkpointing
+    pub fn progressive_pass(&self, pass: u32) -> RenderOptions {
+        RenderOptions {
+            seed: self.seed.wrapping_add(pass),
+            adaptive: None,
+            aovs: vec![],
+            denoise: None,
+            checkpoint: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Turns a scene into an image. The rendered pixels stay in the camera, see [`Camera::image`]
 #[derive(Default)]
 pub struct Camera {
     pub(super) image_width: u16,
@@ -39,22 +144,31 @@ pub struct Camera {
     samples_per_pixel: u16,          // count of random samples per pixel (antialiasing)
     max_depth: u8,                  // max number of ray bounces into scene (reflaction)
     center: Point3d,
+    forward: Vec3d,                 // camera axis, for the depth pass
     pixel00_loc: Point3d,
     pixel_delta_u: Vec3d,
     pixel_delta_v: Vec3d,
     pub(super) pixels: Mutex<Vec<Color>>,
+    pub(super) film: Mutex<Film>,
+    last_checkpoint: Mutex<Option<Instant>>,
+    // the first checkpoint of the running render that couldn't be saved
+    checkpoint_error: Mutex<Option<Error>>,
+    rows_done: AtomicU32,
+    counters: Counters,
+    material_ids: Mutex<Arc<MaterialIds>>,     // of the scene being rendered, for the passes
 
     defocus_angle: f32,
 
     defocus_disk_u: Vec3d,
     defocus_disk_v: Vec3d,
+
+    options: RenderOptions,
 }
 
 const BLACK_COLOR: Color = Color{r: 0.0, g: 0.0, b: 0.0};
-const BLACK_VEC:Vec3d = Vec3d{x: 0.0, y: 0.0, z: 0.0};
-const SOMECOLOR_VEC:Vec3d = Vec3d{x: 0.5, y: 0.7, z: 1.0};
 
 impl Camera {
+    /// A camera for an image of the given width, the height follows from the aspect ratio
     pub fn initialize(aspect_ratio: f32, image_width: u16, max_depth: u8, spp: u16, cv: CameraView) -> Camera {
 
          // Calculate the image height and ensure it is at least 1
@@ -103,29 +217,76 @@ impl Camera {
             samples_per_pixel: spp,
             max_depth,
             center,
+            forward: w * -1.0,
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
             pixels: Mutex::new(vec![BLACK_COLOR; usize::from(image_width) * usize::from(image_height)]),
+            film: Mutex::new(Film {
+                splats: vec![vec![]; usize::from(image_height)],
+                stats: vec![PixelStats::default(); usize::from(image_width) * usize::from(image_height)],
+                aovs: vec![],
+            }),
+            last_checkpoint: Mutex::new(None),
+            checkpoint_error: Mutex::new(None),
+            rows_done: AtomicU32::new(0),
+            counters: Counters::default(),
+            material_ids: Mutex::default(),
             defocus_angle: cv.defocus_angle,
             defocus_disk_u,
             defocus_disk_v,
+            options: RenderOptions::default(),
+        }
+    }
+
+    /// The camera rendering with these options
+    pub fn with_options(self, options: RenderOptions) -> Camera {
+        Camera { options, ..self }
+    }
+
+    /// Width of the image in pixels
+    pub fn image_width(&self) -> u16 {
+        self.image_width
+    }
+
+    /// Height of the image in pixels, from the width and the aspect ratio
+    pub fn image_height(&self) -> u16 {
+        self.image_height
+    }
+
+    /// The linear radiance of the last render
+    pub fn image(&self) -> Image {
+        Image {
+            width: self.image_width,
+            height: self.image_height,
+            pixels: self.pixels.lock().unwrap().clone(),
         }
     }
 
+    /// Samples taken by every pixel, they differ with adaptive sampling
+    pub fn sample_counts(&self) -> Vec<u16> {
+        self.film.lock().unwrap().sample_counts()
+    }
+
+    /// Writes the given render passes as `<prefix>_<pass>`, needs them enabled in the options
+    pub fn write_passes(&self, prefix: &str, passes: &[Pass]) -> Result<()> {
+        let film = self.film.lock().unwrap();
+        aov::write_passes(prefix, passes, self.image_width, self.image_height, &film.averaged_aovs(), &film.sample_counts())
+    }
+
+    /// Ray counts of the last render of this camera, with the given timings
+    pub fn stats(&self, build_time: Duration, render_time: Duration) -> RenderStats {
+        RenderStats::from_counters(&self.counters, build_time, render_time)
+    }
+
     fn sample_square(&self) -> Vec3d {
-        let rnd_x = rand::rng().random_range(0.0..1.0);
-        let rnd_y = rand::rng().random_range(0.0..1.0);
+        let (rnd_x, rnd_y) = sampler::random_2d();
         Vec3d::new(rnd_x - 0.5, rnd_y - 0.5, 0.0)
     }
 
-    fn get_ray(&self, i: u16, j: u16) -> Ray {
-        // construct a camera ray originating from the origin and directed at randomly
-        // sampled point around the pixel location i, j
-        
-        //let mut rng = rand::rng();
-
-        let offset = self.sample_square(); // random
+    pub(crate) fn get_ray(&self, i: u16, j: u16, offset: &Vec3d) -> Ray {
+        // construct a camera ray originating from the origin and directed at the
+        // point offset from the pixel location i, j
         
         let pixel_shift = Vec3d::add(
                             &Vec3d::mul(&self.pixel_delta_u, f32::from(i) + offset.x),
@@ -139,7 +300,7 @@ impl Camera {
         };
         let ray_direction = pixel_sample - ray_origin.as_vec3d();
 
-        Ray::new(ray_origin, ray_direction, Some(rand::rng().random_range(0.0 .. 1.0))) // random
+        Ray::new(ray_origin, ray_direction, Some(sampler::random())) // random
     }
 
     fn defocus_disk_sample(&self) -> Point3d {
@@ -151,33 +312,188 @@ impl Camera {
         )
     }
 
-    fn subrender(self: Arc<Self>, start_row: u16, end_row: u16, world: Arc<HittableList>) {
+    fn subrender(self: Arc<Self>, start_row: u16, end_row: u16, world: Arc<Scene>) {
+        let (min_samples, max_samples, threshold) = match self.options.adaptive {
+            Some(a) => (a.min_samples, a.max_samples, a.threshold),
+            None => (self.samples_per_pixel, self.samples_per_pixel, 0.0),
+        };
+
+        let filter = self.options.filter;
+        let record_aovs = self.records_aovs();
+        let material_ids = self.material_ids.lock().unwrap().clone();
+        let reach = i32::from(filter.reach());
+        let width = i32::from(self.image_width);
+
         for j in start_row .. end_row {
 
-            let mut v: Vec<Color> = vec![];
+            // continue from whatever the row already has (from a checkpoint)
+            let p1 = usize::from(j) * usize::from(self.image_width);
+            let p2 = usize::from(self.image_width) + p1;
+            let (mut splats, mut row_stats, mut row_aovs) = {
+                let film = self.film.lock().unwrap();
+                let splats = if film.splats[usize::from(j)].is_empty() {
+                    vec![Splat::default(); (2 * reach as usize + 1) * usize::from(self.image_width)]
+                } else {
+                    film.splats[usize::from(j)].clone()
+                };
+                let aovs = if record_aovs { film.aovs[p1..p2].to_vec() } else { vec![] };
+                (splats, film.stats[p1..p2].to_vec(), aovs)
+            };
 
             for i in 0 .. self.image_width {
 
-                let mut pixel_color = BLACK_VEC; 
+                let stats = &mut row_stats[usize::from(i)];
+                let mut pixel_aovs = row_aovs.get_mut(usize::from(i));
+                let first_sample = stats.n.min(u32::from(max_samples)) as u16;
+
+                for s in first_sample .. max_samples {
+                    if s >= min_samples && stats.error() < threshold {
+                        break;
+                    }
+                    sampler::start_sample(self.options.sampler, self.options.seed, max_samples, i, j, u32::from(s));
+                    let offset = self.sample_square();
+                    let mut sample_aovs = Aovs::default();
+                    let pc = self.sample(i, j, &offset, &world, record_aovs.then_some(&mut sample_aovs), &material_ids);
+                    if let Some(a) = &mut pixel_aovs {
+                        a.add(&sample_aovs, s == 0);
+                    }
+                    stats.add(pc.luminance());
+
+                    for dy in -reach ..= reach {
+                        if !(0 .. i32::from(self.image_height)).contains(&(i32::from(j) + dy)) {
+                            continue;
+                        }
+                        for dx in -reach ..= reach {
+                            let x = i32::from(i) + dx;
+                            if !(0 .. width).contains(&x) {
+                                continue;
+                            }
+                            let weight = filter.evaluate(offset.x - dx as f32, offset.y - dy as f32);
+                            let splat = &mut splats[((dy + reach) * width + x) as usize];
+                            splat.color = splat.color + pc * weight;
+                            splat.weight += weight;
+                        }
+                    }
+                }
+            }
 
-                for _ in 0 .. self.samples_per_pixel {
-                    let r = self.get_ray(i, j);
-                    let pc = Self::ray_color(r, self.max_depth, &world);
-                    pixel_color = pixel_color + Vec3d::new(pc.r, pc.g, pc.b);
+            {
+                let mut film = self.film.lock().unwrap();
+                film.splats[usize::from(j)] = splats;
+                film.stats[p1..p2].copy_from_slice(&row_stats);
+                if record_aovs {
+                    film.aovs[p1..p2].copy_from_slice(&row_aovs);
                 }
-                
-                pixel_color = pixel_color / f32::from(self.samples_per_pixel);
-                
-                v.push(Color { r: pixel_color.x, g: pixel_color.y, b: pixel_color.z });
             }
-            
-            let p1 = usize::from(j) * usize::from(self.image_width);
-            let p2 = usize::from(self.image_width) + p1;
-            self.pixels.lock().unwrap()[p1..p2].copy_from_slice(&v);
+
+            stats::flush(&self.counters);
+            self.rows_done.fetch_add(1, Ordering::Relaxed);
+            self.checkpoint_if_due();
+        }
+    }
+
+    // Saves the film if the checkpoint interval has passed, one thread at a time
+    fn checkpoint_if_due(&self) {
+        let Some(interval) = self.options.checkpoint.as_ref().and_then(|c| c.interval) else {
+            return;
+        };
+        let Ok(mut last) = self.last_checkpoint.try_lock() else {
+            return;
+        };
+        if last.is_some_and(|t| t.elapsed() >= interval) {
+            if let Err(e) = self.save_checkpoint() {
+                self.checkpoint_error.lock().unwrap().get_or_insert(e);
+            }
+            *last = Some(Instant::now());
+        }
+    }
+
+    fn save_checkpoint(&self) -> Result<()> {
+        let Some(c) = &self.options.checkpoint else {
+            return Ok(());
+        };
+        let film = self.film.lock().unwrap();
+        checkpoint::save(&c.path, c.settings_hash, self.image_width, self.image_height, self.options.filter.reach(), &film)
+            .map_err(|e| Error::io(&c.path, e))
+    }
+
+    /// Loads the film saved by an earlier render with the same settings, render() then only
+    /// adds the missing samples
+    pub fn resume(&self) -> Result<()> {
+        let c = self.options.checkpoint.as_ref().ok_or_else(|| Error::invalid("checkpoint", "checkpoints are not configured"))?;
+        let film = checkpoint::load(&c.path, c.settings_hash, self.image_width, self.image_height, self.options.filter.reach())?;
+        *self.film.lock().unwrap() = film;
+        Ok(())
+    }
+
+    // Sums up the splats of the rows around every pixel. The rows are always added in the
+    // same order, so the image doesn't depend on how the rows were split between threads
+    fn resolve(&self) {
+        let reach = usize::from(self.options.filter.reach());
+        let width = usize::from(self.image_width);
+        let height = usize::from(self.image_height);
+        let film = self.film.lock().unwrap();
+        let splats = &film.splats;
+        let mut pixels = self.pixels.lock().unwrap();
+
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let mut sum = Splat::default();
+                let rows = splats.iter().enumerate().take((y + reach).min(height - 1) + 1).skip(y.saturating_sub(reach));
+                for (j, row) in rows {
+                    let Some(splat) = row.get((y + reach - j) * width + x) else {
+                        continue;
+                    };
+                    sum.color = sum.color + splat.color;
+                    sum.weight += splat.weight;
+                }
+                pixels[y * width + x] = if sum.weight > 0.0 { sum.color * (1.0 / sum.weight) } else { BLACK_COLOR };
+            }
+        }
+    }
+
+    // Traces one camera sample through the given offset from the center of the pixel i, j,
+    // the sampler must be already started
+    fn sample(&self, i: u16, j: u16, offset: &Vec3d, world: &Scene, mut aovs: Option<&mut Aovs>, material_ids: &MaterialIds) -> Color {
+        let mut r = self.get_ray(i, j, offset);
+        let camera_ray = r.clone();
+        stats::count(Counter::CameraRays);
+        let color = if self.options.spectral {
+            let wavelengths = Wavelengths::sample();
+            r.wavelength = Some(wavelengths.hero());
+            wavelengths.to_rgb(&Self::ray_spectrum(r, self.max_depth, world, None, false, &wavelengths, aovs.as_deref_mut()))
+        } else {
+            Self::ray_color(r, self.max_depth, world, None, aovs.as_deref_mut())
+        };
+        if let Some(aovs) = aovs {
+            aovs.finish(&camera_ray, &self.forward, color, material_ids);
         }
+        color
     }
 
-    pub fn render(self: Arc<Self>, world: Arc<HittableList>, thread_num: u8) {
+    // The denoiser is guided by the first hit features
+    fn records_aovs(&self) -> bool {
+        !self.options.aovs.is_empty() || self.options.denoise.is_some()
+    }
+
+    /// Renders the scene into the camera on the given number of threads. Fails if a thread
+    /// panicked, the image is incomplete then, or with [`Error::Io`] if a checkpoint couldn't
+    /// be saved, after the image is complete
+    pub fn render(self: Arc<Self>, world: Arc<Scene>, thread_num: u8, progress: &mut Progress) -> Result<()> {
+        {
+            let mut film = self.film.lock().unwrap();
+            if self.records_aovs() && film.aovs.is_empty() {
+                film.aovs = vec![Aovs::default(); usize::from(self.image_width) * usize::from(self.image_height)];
+            }
+        }
+        if self.records_aovs() {
+            *self.material_ids.lock().unwrap() = Arc::new(aov::material_ids(&world.world));
+        }
+        *self.last_checkpoint.lock().unwrap() = Some(Instant::now());
+        *self.checkpoint_error.lock().unwrap() = None;
+        self.rows_done.store(0, Ordering::Relaxed);
+        self.counters.reset();
+
         let thread_num = if thread_num > 0 { thread_num as u16 } else { 1u16 };
         let rows = self.image_height / thread_num;
         let rows_rem = self.image_height % thread_num;
@@ -196,42 +512,253 @@ impl Camera {
        
         if rows_rem > 0 {
             let c = self.clone();
+            let end_row = self.image_height;
             handles.push( thread::spawn(
-                move || c.subrender(thread_num * rows, self.image_height, world.clone())
+                move || c.subrender(thread_num * rows, end_row, world.clone())
             ));
         }
        
+        let rows_total = u32::from(self.image_height);
+        // wake up often so a finished render isn't held back, but report twice a second
+        let mut last_update: Option<Instant> = None;
+        while !handles.iter().all(|h| h.is_finished()) {
+            if last_update.is_none_or(|t| t.elapsed() >= Duration::from_millis(500)) {
+                progress.update(self.rows_done.load(Ordering::Relaxed), rows_total, self.counters.get(Counter::CameraRays));
+                last_update = Some(Instant::now());
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
         for handle in handles {
-            handle.join().unwrap();
+            handle.join().map_err(|e| {
+                let message = e.downcast_ref::<&str>().map(|s| s.to_string()).or_else(|| e.downcast_ref::<String>().cloned());
+                Error::Render(message.unwrap_or("a render thread panicked".to_string()))
+            })?;
+        }
+        progress.update(self.rows_done.load(Ordering::Relaxed), rows_total, self.counters.get(Counter::CameraRays));
+        self.counters.finish();
+
+        let saved = self.save_checkpoint();
+        self.resolve();
+
+        if let Some(denoiser) = self.options.denoise {
+            let mut pixels = self.pixels.lock().unwrap();
+            let aovs = self.film.lock().unwrap().averaged_aovs();
+            *pixels = denoiser.denoise(&pixels, &aovs, usize::from(self.image_width), usize::from(self.image_height));
+        }
+        match self.checkpoint_error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => saved,
         }
     }
 
-    fn ray_color(r: Ray, depth: u8, world: &HittableList) -> Color {
+    // bsdf_pdf is the density the previous bounce scattered this ray with, if that bounce
+    // also sampled the sun directly (the two estimates are combined with multiple importance sampling)
+    // aovs records the first hit of a camera ray
+    fn ray_color(r: Ray, depth: u8, scene: &Scene, bsdf_pdf: Option<f32>, aovs: Option<&mut Aovs>) -> Color {
         if depth == 0 {
             return BLACK_COLOR; 
         }
 
-        if let Some((hr, hit_mat)) = world.hit(&r, Interval{min: 0.001, max: f32::INFINITY}) {
+        sampler::next_bounce();
+        stats::count(Counter::PathRays);
+
+        if let Some((hr, hit_mat)) = scene.world.hit(&r, Interval{min: 0.001, max: f32::INFINITY}) {
+            let direct = Self::sample_sun(&r, &hr, &hit_mat, scene);
+
             // TODO: refactor this!!!
             let (scat_ray, scat_color, scattered) = hit_mat.scatter(&r, &hr);
+            let aovs = aovs.and_then(|a| a.record_hit(&r, &hr, &hit_mat, direct, scattered.then_some(scat_color)).then_some(a));
             return if scattered {
-                let rc = Self::ray_color(scat_ray, depth - 1, world);
-                Color{r: rc.r * scat_color.r, g: rc.g * scat_color.g, b: rc.b * scat_color.b}
+                let pdf = direct.map(|_| hit_mat.pdf(&r, &hr, &scat_ray.direction));
+                let rc = Self::ray_color(scat_ray, depth - 1, scene, pdf, aovs);
+                direct.unwrap_or(BLACK_COLOR) + rc * scat_color
             } else {
-                BLACK_COLOR
+                direct.unwrap_or(BLACK_COLOR)
             }; 
         }
 
-        let unit_direction = Vec3d::unit(&r.direction);
-        let a = 0.5 * (unit_direction.y + 1.0);
+        let sun_weight = match (bsdf_pdf, scene.sky.sun()) {
+            (Some(bsdf_pdf), Some(sun)) => power_heuristic(bsdf_pdf, sun.sun_pdf()),
+            _ => 1.0,
+        };
 
-        let cv = BLACK_VEC * (1.0 - a) + SOMECOLOR_VEC * a;
+        let radiance = scene.sky.radiance(&r.direction, sun_weight);
+        if let Some(aovs) = aovs {
+            aovs.record_escape(radiance);
+        }
+        radiance
+    }
+
+    // Same as ray_color, but follows the sampled wavelengths. Colors of the materials and
+    // lights are upsampled to spectra, dispersive materials keep only the hero wavelength.
+    // hero_only is set once a dispersive hit up the path has dropped the others
+    fn ray_spectrum(r: Ray, depth: u8, scene: &Scene, bsdf_pdf: Option<f32>, hero_only: bool, wavelengths: &Wavelengths, aovs: Option<&mut Aovs>) -> Spectrum {
+        if depth == 0 {
+            return Spectrum::splat(0.0);
+        }
+
+        sampler::next_bounce();
+        stats::count(Counter::PathRays);
+
+        if let Some((hr, hit_mat)) = scene.world.hit(&r, Interval{min: 0.001, max: f32::INFINITY}) {
+            let direct = Self::sample_sun(&r, &hr, &hit_mat, scene);
+            let direct_spectrum = Spectrum::from_rgb(direct.unwrap_or(BLACK_COLOR), wavelengths);
+
+            let (mut scat_ray, scat_color, scattered) = hit_mat.scatter(&r, &hr);
+            let aovs = aovs.and_then(|a| a.record_hit(&r, &hr, &hit_mat, direct, scattered.then_some(scat_color)).then_some(a));
+            return if scattered {
+                scat_ray.wavelength = r.wavelength;
+                let pdf = direct.map(|_| hit_mat.pdf(&r, &hr, &scat_ray.direction));
+                // the mask applies once per path, the dropped wavelengths can't be dropped again
+                let drop_others = !hero_only && hit_mat.is_dispersive();
+                let rc = Self::ray_spectrum(scat_ray, depth - 1, scene, pdf, hero_only || drop_others, wavelengths, aovs);
+                let rc = if drop_others { rc * Wavelengths::hero_only() } else { rc };
+                direct_spectrum + rc * Spectrum::from_rgb(scat_color, wavelengths)
+            } else {
+                direct_spectrum
+            };
+        }
+
+        let sun_weight = match (bsdf_pdf, scene.sky.sun()) {
+            (Some(bsdf_pdf), Some(sun)) => power_heuristic(bsdf_pdf, sun.sun_pdf()),
+            _ => 1.0,
+        };
 
-        Color{r: cv.x, g: cv.y, b: cv.z}
+        let radiance = scene.sky.radiance(&r.direction, sun_weight);
+        if let Some(aovs) = aovs {
+            aovs.record_escape(radiance);
+        }
+        Spectrum::from_rgb(radiance, wavelengths)
     }
+
+    // Direct lighting from the sun disk using a shadow ray. Returns None if the material
+    // can't be light sampled (or there's no sun), so the sun is left to scattered rays only
+    fn sample_sun(r: &Ray, hr: &HitRecord, mat: &MaterialEnum, scene: &Scene) -> Option<Color> {
+        let sun = scene.sky.sun()?;
+        if sun.is_below_horizon() {
+            return None;
+        }
+
+        let (direction, pdf) = sun.sample_sun();
+        let f = mat.eval(r, hr, &direction)?;
+
+        if f.r <= 0.0 && f.g <= 0.0 && f.b <= 0.0 {
+            return Some(BLACK_COLOR);
+        }
+
+        let shadow_ray = Ray::new(hr.point.clone(), direction, Some(r.tm));
+        stats::count(Counter::ShadowRays);
+        if scene.world.hit(&shadow_ray, Interval{min: 0.001, max: f32::INFINITY}).is_some() {
+            return Some(BLACK_COLOR);
+        }
+
+        let weight = power_heuristic(pdf, mat.pdf(r, hr, &shadow_ray.direction));
+        Some(f * sun.sun_radiance() * (weight / pdf))
+    }
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let p2 = pdf * pdf;
+    let o2 = other_pdf * other_pdf;
+    if p2 + o2 == 0.0 { 0.0 } else { p2 / (p2 + o2) }
 }
 
 fn get_image_height(w: u16, a: f32) -> u16 {
     let hf: f32 = f32::from(w) / a;
     if hf < 1.0 { 1 } else { hf as u16 }
 }
+
+// Everything accumulated while rendering. A row is always written at once, so the film
+// can be saved at any time and a render continued from it
+#[derive(Default)]
+pub(crate) struct Film {
+    // filtered samples of every rendered row, splatted onto the rows within the filter reach
+    pub(crate) splats: Vec<Vec<Splat>>,
+    pub(crate) stats: Vec<PixelStats>,
+    // sums over the samples of every pixel, empty if no aovs are recorded
+    pub(crate) aovs: Vec<Aovs>,
+}
+
+impl Film {
+    pub(crate) fn sample_counts(&self) -> Vec<u16> {
+        self.stats.iter().map(|s| s.n.min(u32::from(u16::MAX)) as u16).collect()
+    }
+
+    pub(crate) fn averaged_aovs(&self) -> Vec<Aovs> {
+        self.aovs.iter().zip(&self.stats).map(|(a, s)| {
+            let mut a = *a;
+            a.scale(1.0 / s.n.max(1) as f32);
+            a
+        }).collect()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Splat {
+    pub(crate) color: Color,
+    pub(crate) weight: f32,
+}
+
+// Running mean and variance of the sample luminances of a pixel (Welford)
+#[derive(Clone, Copy, Default)]
+pub(crate) struct PixelStats {
+    pub(crate) n: u32,
+    pub(crate) mean: f32,
+    pub(crate) m2: f32,
+}
+
+impl PixelStats {
+    fn add(&mut self, x: f32) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as f32;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    // Standard error of the pixel mean, scaled by the slope of the gamma 2 transform so
+    // dark pixels aren't held to a stricter standard than the eye can see
+    fn error(&self) -> f32 {
+        if self.n < 2 {
+            return f32::INFINITY;
+        }
+        let variance = self.m2 / (self.n - 1) as f32;
+        let std_error = f32::sqrt(variance / self.n as f32);
+        std_error / (2.0 * f32::sqrt(self.mean.max(1e-4)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{camera::{CameraView, PixelStats}, config::Settings, Point3d};
+
+    #[test]
+    fn flat_pixels_converge_and_noisy_ones_do_not() {
+        let mut flat = PixelStats::default();
+        let mut noisy = PixelStats::default();
+        for i in 0 .. 16 {
+            flat.add(0.5);
+            noisy.add(if i % 2 == 0 { 0.0 } else { 1.0 });
+        }
+        assert!(flat.error() < 1e-6);
+        assert!(noisy.error() > 0.05);
+        assert!((noisy.mean - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn a_view_survives_the_trip_through_its_settings_snippet() {
+        let mut view = CameraView::new(&None);
+        view.lookfrom = Point3d::new(-2.5, 0.75, 1e-5);
+        view.vfov = 33.3;
+        let mut snippet = view.to_settings();
+        snippet.aspect_ratio = Some(1.5);
+
+        let config = include_str!("../config.toml");
+        let text = config.replacen("[camera]\n", &snippet.to_toml(), 1);
+        let c = Settings::from_toml(&text).unwrap();
+        let read = CameraView::new(&c.camera);
+        assert_eq!(read.lookfrom.as_vec3d(), view.lookfrom.as_vec3d());
+        assert_eq!((read.vfov, read.defocus_angle, read.focus_dist), (view.vfov, view.defocus_angle, view.focus_dist));
+        assert_eq!(c.camera.unwrap().aspect_ratio, Some(1.5));
+    }
+}
@@ -0,0 +1,368 @@
+use std::{collections::HashMap, fmt, fs};
+
+use crate::{config::{Camera, Ground, Settings}, error::{Error, Result}, scene, sky::Sky, vec3d::Vec3d};
+
+// Beyond this the f32 hit points on a sphere are off by more than the 0.001 the scattered
+// rays start above the surface, and it shadows itself (the acne of a 1e4 Cornell box)
+const MAX_RADIUS: f32 = 1e4;
+
+/// How bad a finding is, errors stop a render
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// suspicious, the render goes on
+    Warning,
+    /// broken, there is no render
+    Error,
+}
+
+/// Something wrong or suspicious in the settings
+#[derive(Debug, Clone)]
+pub struct Finding {
+    /// how bad it is
+    pub severity: Severity,
+    /// dotted path of the setting, like `ground.radius`
+    pub field: String,
+    /// line of the setting in the file, None for settings made in code or left out
+    pub line: Option<usize>,
+    /// what is wrong
+    pub message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, field: &str, message: impl Into<String>) -> Finding {
+        Finding { severity, field: field.to_string(), line: None, message: message.into() }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}: {}: {}", self.field, self.message)
+    }
+}
+
+/// Checks a settings file before it is rendered. Settings that can't be read at all are
+/// an Err, everything else comes back as findings with the line of the setting
+pub fn check_file(path: &str) -> Result<Vec<Finding>> {
+    let text = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    check_loaded(&text, Settings::from_file(path))
+}
+
+fn check_loaded(text: &str, loaded: Result<Settings>) -> Result<Vec<Finding>> {
+    let mut findings = match loaded {
+        Ok(c) => lint(&c),
+        // an unknown name is an error of a setting like the ones found here
+        Err(Error::Invalid { field, message, .. }) => vec![Finding::new(Severity::Error, &field, message)],
+        Err(e) => return Err(e),
+    };
+    let lines = setting_lines(text);
+    for f in &mut findings {
+        f.line = locate(&lines, &f.field);
+    }
+    Ok(findings)
+}
+
+/// Values that load but would render nothing, something broken or something else than
+/// intended. Names are checked when the settings are loaded already
+pub fn lint(c: &Settings) -> Vec<Finding> {
+    let mut findings = vec![];
+    let mut warn = |field: &str, message: String| findings.push(Finding::new(Severity::Warning, field, message));
+    if c.max_depth == 0 {
+        warn("max_depth", "rays never bounce, the image is black".to_string());
+    }
+    if c.multithread_enabled && c.threads == 0 {
+        warn("threads", "no threads, the render runs on one".to_string());
+    }
+    if let Some(a) = &c.adaptive {
+        if a.min_samples > a.max_samples {
+            warn("adaptive.max_samples", format!("less than min_samples, every pixel takes {}", a.min_samples));
+        }
+    }
+
+    let mut error = |field: &str, message: &str| findings.push(Finding::new(Severity::Error, field, message));
+    if c.width == 0 {
+        error("width", "the image has no pixels");
+    }
+    if c.samples_per_pixel == 0 && c.adaptive.is_none() {
+        error("samples_per_pixel", "no samples, the image is black");
+    }
+
+    lint_ground(&c.ground, &mut findings);
+    // the view of the scene, where the camera settings leave it
+    let camera = match scene::Generator::from_settings(c) {
+        Ok(g) => g.camera(c),
+        Err(_) => c.camera.clone().unwrap_or_default(),
+    };
+    lint_camera(&camera, &mut findings);
+    lint_lights(c, &mut findings);
+    lint_material(c, &mut findings);
+    // the parameters of the scene are checked by making it
+    if let Err(Error::Invalid { field, message, .. }) = scene::Generator::from_settings(c).and_then(|g| g.generate(c)) {
+        if !findings.iter().any(|f| f.field == field) {
+            findings.push(Finding::new(Severity::Error, &field, message));
+        }
+    }
+    if let Some(r) = c.scene.as_ref().and_then(|s| s.cornell_box.as_ref()).and_then(|b| b.wall_radius).filter(|r| *r > MAX_RADIUS) {
+        findings.push(Finding::new(Severity::Warning, "scene.cornell_box.wall_radius", format!(
+            "{r} is too large for f32 hit points, the walls shadow themselves in spots (keep it below {MAX_RADIUS})")));
+    }
+    findings
+}
+
+// [material] is read by the sea of balls where [scene.sea_of_balls] leaves a percent unset
+fn lint_material(c: &Settings, findings: &mut Vec<Finding>) {
+    let Some(m) = &c.material else {
+        return;
+    };
+    let sea = c.scene.as_ref().and_then(|s| s.sea_of_balls.clone()).unwrap_or_default();
+    let is_sea = scene::Generator::from_settings(c).is_ok_and(|g| g.name == "sea_of_balls");
+    let percents = [
+        ("diffuse_percent", m.diffuse_percent, sea.diffuse_percent),
+        ("metal_percent", m.metal_percent, sea.metal_percent),
+        ("dielectric_percent", m.dielectric_percent, sea.dielectric_percent),
+    ];
+    for (name, old, new) in percents {
+        let message = match (old, new) {
+            (None, _) => continue,
+            _ if !is_sea => "unused, only the sea_of_balls scene has material percents".to_string(),
+            (Some(_), Some(v)) => format!("replaced by scene.sea_of_balls.{name} = {v}"),
+            (Some(_), None) => "read for the sea of balls, move it to [scene.sea_of_balls]".to_string(),
+        };
+        findings.push(Finding::new(Severity::Warning, &format!("material.{name}"), message));
+    }
+}
+
+fn lint_ground(g: &Ground, findings: &mut Vec<Finding>) {
+    if g.center.iter().any(|v| !v.is_finite()) {
+        findings.push(Finding::new(Severity::Error, "ground.center", "not a finite point"));
+    }
+    if !g.radius.is_finite() || g.radius <= 0.0 {
+        findings.push(Finding::new(Severity::Error, "ground.radius", format!("{} is clamped to 0, the ground disappears", g.radius)));
+    } else if g.radius > MAX_RADIUS {
+        findings.push(Finding::new(Severity::Warning, "ground.radius", format!(
+            "{} is too large for f32 hit points, the surface shadows itself in spots (keep it below {MAX_RADIUS})", g.radius)));
+    }
+
+    // the ground has one material, the sections of the others are left over
+    let sections = [
+        ("diffuse", g.diffuse.is_some()),
+        ("metal", g.metal.is_some()),
+        ("dielectric", g.dielectric.is_some()),
+        ("conductor", g.conductor.is_some()),
+        ("rough_dielectric", g.rough_dielectric.is_some()),
+        ("principled", g.principled.is_some()),
+    ];
+    for (name, present) in sections {
+        if present && name != g.material && scene::MATERIALS.contains(&g.material.as_str()) {
+            findings.push(Finding::new(Severity::Warning, &format!("ground.{name}"), format!("unused, the ground material is {}", g.material)));
+        }
+    }
+
+    let mut out_of_unit = |field: &str, v: f32| {
+        if !(0.0 ..= 1.0).contains(&v) {
+            findings.push(Finding::new(Severity::Warning, field, format!("{v} is outside 0 ..= 1")));
+        }
+    };
+    match g.material.as_str() {
+        "metal" => g.metal.iter().for_each(|m| out_of_unit("ground.metal.fuzz", m.fuzz)),
+        "conductor" => g.conductor.iter().for_each(|m| out_of_unit("ground.conductor.roughness", m.roughness)),
+        "rough_dielectric" => g.rough_dielectric.iter().for_each(|m| out_of_unit("ground.rough_dielectric.roughness", m.roughness)),
+        _ => {},
+    }
+    let refraction = match g.material.as_str() {
+        "dielectric" => g.dielectric.as_ref().map(|d| ("ground.dielectric.refraction", d.refraction)),
+        "rough_dielectric" => g.rough_dielectric.as_ref().map(|d| ("ground.rough_dielectric.refraction", d.refraction)),
+        _ => None,
+    };
+    if let Some((field, ior)) = refraction.filter(|(_, ior)| !positive(*ior)) {
+        findings.push(Finding::new(Severity::Error, field, format!("{ior} is not a refractive index")));
+    }
+}
+
+// Camera::initialize builds its frame from cross products of the view direction and vup,
+// both have to exist and differ
+fn lint_camera(c: &Camera, findings: &mut Vec<Finding>) {
+    let mut error = |field: &str, message: String| findings.push(Finding::new(Severity::Error, field, message));
+    let vector = |v: [f32; 3]| Vec3d::new(v[0], v[1], v[2]);
+    let lookfrom = vector(c.lookfrom.unwrap_or([13.0, 2.0, 3.0]));
+    let lookat = vector(c.lookat.unwrap_or([0.0, 0.0, 0.0]));
+    let vup = vector(c.vup.unwrap_or([0.0, 1.0, 0.0]));
+
+    let mut finite = true;
+    for (field, v) in [("camera.lookfrom", &lookfrom), ("camera.lookat", &lookat), ("camera.vup", &vup)] {
+        if ![v.x, v.y, v.z].iter().all(|x| x.is_finite()) {
+            error(field, "not a finite vector".to_string());
+            finite = false;
+        }
+    }
+    if finite {
+        let view = lookat - lookfrom;
+        if view.length() < 1e-6 {
+            error("camera.lookat", "the same point as lookfrom, the camera looks nowhere".to_string());
+        } else if vup.length() < 1e-6 || Vec3d::cross(&Vec3d::unit(&view), &Vec3d::unit(&vup)).length() < 1e-4 {
+            error("camera.vup", "parallel to the view direction (or zero), the camera has no up".to_string());
+        }
+    }
+
+    if let Some(vfov) = c.vfov.filter(|v| !positive(*v) || *v >= 180.0) {
+        error("camera.vfov", format!("{vfov} degrees is no field of view, it has to be between 0 and 180"));
+    }
+    if let Some(d) = c.focus_dist.filter(|d| !positive(*d)) {
+        error("camera.focus_dist", format!("{d} puts the image plane behind the camera"));
+    }
+    if let Some(a) = c.aspect_ratio.filter(|a| !positive(*a)) {
+        error("camera.aspect_ratio", format!("{a} is no image shape"));
+    }
+    if let Some(a) = c.defocus_angle.filter(|a| *a < 0.0) {
+        findings.push(Finding::new(Severity::Warning, "camera.defocus_angle", format!("{a} is negative, the same as {}", -a)));
+    }
+}
+
+// The sky is the only light there is, the materials don't emit
+fn lint_lights(c: &Settings, findings: &mut Vec<Finding>) {
+    let Some(s) = &c.sky else {
+        return;
+    };
+    if s.sun_direction.is_some_and(|d| d.iter().any(|v| !v.is_finite())) {
+        findings.push(Finding::new(Severity::Error, "sky.sun_direction", "not a finite vector"));
+        return;
+    }
+    let mut dark = |field: &str, message: &str| findings.push(Finding::new(Severity::Warning, field, message));
+    match Sky::new(&c.sky) {
+        Ok(Sky::Uniform(radiance)) if radiance.r.max(radiance.g).max(radiance.b) <= 0.0 => {
+            dark("sky.radiance", "black, nothing lights the scene");
+        },
+        Ok(Sky::Daylight(d)) => {
+            let sky_dark = s.intensity.is_some_and(|i| i <= 0.0);
+            let sun_dark = d.is_below_horizon() || s.sun_irradiance.is_some_and(|i| i <= 0.0);
+            if sky_dark && sun_dark {
+                dark("sky", "neither the sky nor the sun give light, the image is black");
+            } else if d.is_below_horizon() {
+                dark("sky", "the sun is below the horizon, only the sky lights the scene");
+            }
+        },
+        _ => {},
+    }
+}
+
+// False for NaN as well
+fn positive(v: f32) -> bool {
+    v > 0.0
+}
+
+// Line of every key and section header of a TOML file, by dotted path
+fn setting_lines(text: &str) -> HashMap<String, usize> {
+    let mut lines = HashMap::new();
+    let mut section = String::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = header.trim().to_string();
+            lines.entry(section.clone()).or_insert(i + 1);
+        } else if let Some((key, _)) = line.split_once('=') {
+            let key = key.trim().trim_matches('"');
+            let path = if section.is_empty() { key.to_string() } else { format!("{section}.{key}") };
+            lines.entry(path).or_insert(i + 1);
+        }
+    }
+    lines
+}
+
+// A setting left out points at its section, if that is there
+fn locate(lines: &HashMap<String, usize>, field: &str) -> Option<usize> {
+    let mut path = field;
+    loop {
+        if let Some(line) = lines.get(path) {
+            return Some(*line);
+        }
+        path = &path[.. path.rfind('.')?];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{check::{check_loaded, Severity}, config::Settings};
+
+    const CONFIG: &str = include_str!("../config.toml");
+
+    fn check(from: &str, to: &str) -> Vec<(Severity, String, Option<usize>)> {
+        check_all(&[(from, to)])
+    }
+
+    fn check_all(changes: &[(&str, &str)]) -> Vec<(Severity, String, Option<usize>)> {
+        let mut text = CONFIG.to_string();
+        for (from, to) in changes {
+            assert!(text.contains(from));
+            text = text.replacen(from, to, 1);
+        }
+        check_loaded(&text, Settings::from_toml(&text)).unwrap()
+            .into_iter()
+            .filter(|f| !f.message.starts_with("unused"))
+            .map(|f| (f.severity, f.field, f.line))
+            .collect()
+    }
+
+    fn line_of(text: &str) -> Option<usize> {
+        CONFIG.lines().position(|l| l.starts_with(text)).map(|i| i + 1)
+    }
+
+    #[test]
+    fn shipped_config_only_has_unused_materials() {
+        assert!(check("", "").is_empty());
+    }
+
+    #[test]
+    fn degenerate_cameras_are_errors() {
+        let found = check("# lookat = [0.0, 0.0, 0.0]", "lookat = [13.0, 2.0, 3.0]");
+        assert_eq!(found, vec![(Severity::Error, "camera.lookat".to_string(), line_of("# lookat"))]);
+
+        let found = check("# vup = [0.0, 1.0, 0.0]", "vup = [13.0, 2.0, 3.0]");
+        assert_eq!(found, vec![(Severity::Error, "camera.vup".to_string(), line_of("# vup"))]);
+
+        let found = check("# lookfrom = [13.0, 2.0, 3.0]", "lookfrom = [nan, 2.0, 3.0]");
+        assert_eq!(found, vec![(Severity::Error, "camera.lookfrom".to_string(), line_of("# lookfrom"))]);
+    }
+
+    #[test]
+    fn negative_and_huge_radii_are_found() {
+        let found = check("radius = 1000.0", "radius = -1.0");
+        assert_eq!(found, vec![(Severity::Error, "ground.radius".to_string(), line_of("radius = 1000.0"))]);
+
+        let found = check("radius = 1000.0", "radius = 1e6");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, Severity::Warning);
+    }
+
+    #[test]
+    fn the_old_material_section_is_pointed_to_its_new_place() {
+        let found = check("[scene]\n", "[material]\nmetal_percent = 0.9\n\n[scene]\n");
+        assert_eq!(found, vec![(Severity::Warning, "material.metal_percent".to_string(), line_of("[scene]").map(|l| l + 1))]);
+    }
+
+    #[test]
+    fn unknown_names_are_located() {
+        let found = check("operator = \"clamp\"", "operator = \"clmap\"");
+        assert_eq!(found, vec![(Severity::Error, "tonemap.operator".to_string(), line_of("operator"))]);
+    }
+
+    #[test]
+    fn scene_parameters_are_located() {
+        let found = check("radius = 0.2", "radius = -1.0");
+        assert_eq!(found, vec![(Severity::Error, "scene.sea_of_balls.radius".to_string(), line_of("radius = 0.2"))]);
+
+        let found = check("density = 1.0", "density = -0.5");
+        assert_eq!(found, vec![(Severity::Error, "scene.sea_of_balls.density".to_string(), line_of("density = 1.0"))]);
+
+        let found = check_all(&[("generator = \"sea_of_balls\"", "generator = \"forest\""), ("scale_variation = 0.3", "scale_variation = 1.5")]);
+        assert_eq!(found, vec![(Severity::Error, "scene.forest.scale_variation".to_string(), line_of("scale_variation"))]);
+
+        let found = check_all(&[("generator = \"sea_of_balls\"", "generator = \"random_triangles\""), ("size = 0.6", "size = 1e-6")]);
+        assert_eq!(found, vec![(Severity::Error, "scene.random_triangles.size".to_string(), line_of("size = 0.6"))]);
+
+        let found = check_all(&[("material = \"diffuse\"", "material = \"dielectric\""), ("#transmittance_distance = 1.0", "transmittance_distance = 0.0")]);
+        assert_eq!(found, vec![(Severity::Error, "ground.dielectric.transmittance_distance".to_string(), line_of("#transmittance_distance"))]);
+    }
+}
@@ -0,0 +1,218 @@
+use std::{fs, time::Duration};
+
+use crate::{aov::Aovs, camera::{Film, PixelStats, Splat}, config::{self, Settings}, error::{Error, Result}, Color};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 1;
+
+// Where and how often the film of a running render is saved
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpointing {
+    pub(crate) path: String,
+    // None saves only the finished render
+    pub(crate) interval: Option<Duration>,
+    // checkpoints of renders with other settings can't be resumed
+    pub(crate) settings_hash: u64,
+}
+
+impl Checkpointing {
+    pub(crate) fn new(c: &Option<config::Checkpoint>, settings: &Settings) -> Option<Checkpointing> {
+        let c = c.as_ref()?;
+        Some(Checkpointing {
+            path: c.path.clone().unwrap_or("rendered.checkpoint".to_string()),
+            interval: c.interval.map(Duration::from_secs_f32),
+            settings_hash: settings_hash(settings),
+        })
+    }
+}
+
+// Hash of everything that changes the samples of a pixel. The sample count isn't part of
+// it, so a finished render can be resumed with more samples
+pub(crate) fn settings_hash(c: &Settings) -> u64 {
+    let key = format!("{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}",
+        c.width, c.max_depth, c.spectral, c.sampler, c.seed, c.filter, c.ground, c.sky, c.camera, c.scene, c.material);
+    // FNV-1a, stable between builds unlike the std hasher
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Binary layout (little endian): magic, version, settings hash, width, height, filter reach,
+// then the stats and aov sums of every pixel and the splats of every row (empty if not rendered)
+pub(crate) fn save(path: &str, settings_hash: u64, width: u16, height: u16, reach: u16, film: &Film) -> std::io::Result<()> {
+    let mut b = Vec::new();
+    b.extend_from_slice(MAGIC);
+    b.extend_from_slice(&VERSION.to_le_bytes());
+    b.extend_from_slice(&settings_hash.to_le_bytes());
+    for v in [width, height, reach] {
+        b.extend_from_slice(&v.to_le_bytes());
+    }
+    b.push(u8::from(!film.aovs.is_empty()));
+
+    let f32s = |b: &mut Vec<u8>, v: &[f32]| v.iter().for_each(|v| b.extend_from_slice(&v.to_le_bytes()));
+    for s in &film.stats {
+        b.extend_from_slice(&s.n.to_le_bytes());
+        f32s(&mut b, &[s.mean, s.m2]);
+    }
+    for a in &film.aovs {
+        f32s(&mut b, &aov_floats(a));
+        b.extend_from_slice(&a.object_id.to_le_bytes());
+        b.extend_from_slice(&a.material_id.to_le_bytes());
+    }
+    for row in &film.splats {
+        b.extend_from_slice(&(row.len() as u32).to_le_bytes());
+        for s in row {
+            f32s(&mut b, &[s.color.r, s.color.g, s.color.b, s.weight]);
+        }
+    }
+
+    // never leave a half written checkpoint behind
+    let tmp = format!("{path}.tmp");
+    fs::write(&tmp, b)?;
+    fs::rename(tmp, path)
+}
+
+pub(crate) fn load(path: &str, settings_hash: u64, width: u16, height: u16, reach: u16) -> Result<Film> {
+    let data = fs::read(path).map_err(|e| Error::io(path, e))?;
+    parse(&data, settings_hash, width, height, reach).map_err(|message| Error::Asset { file: path.to_string(), message })
+}
+
+fn parse(data: &[u8], settings_hash: u64, width: u16, height: u16, reach: u16) -> std::result::Result<Film, String> {
+    let mut r = Reader { data, pos: 0 };
+
+    if r.bytes(4)? != MAGIC || r.u32()? != VERSION {
+        return Err("not a checkpoint of this renderer version".to_string());
+    }
+    if r.u64()? != settings_hash {
+        return Err("rendered with other settings".to_string());
+    }
+    if (r.u16()?, r.u16()?, r.u16()?) != (width, height, reach) {
+        return Err("another image size or filter".to_string());
+    }
+    let has_aovs = r.bytes(1)?[0] != 0;
+
+    let pixels = usize::from(width) * usize::from(height);
+    let mut film = Film::default();
+    for _ in 0 .. pixels {
+        film.stats.push(PixelStats { n: r.u32()?, mean: r.f32()?, m2: r.f32()? });
+    }
+    if has_aovs {
+        for _ in 0 .. pixels {
+            let mut v = [0.0; 16];
+            for x in v.iter_mut() {
+                *x = r.f32()?;
+            }
+            let mut a = aov_from_floats(&v);
+            a.object_id = r.u32()?;
+            a.material_id = r.u32()?;
+            film.aovs.push(a);
+        }
+    }
+    for _ in 0 .. height {
+        let len = r.u32()? as usize;
+        let mut row = Vec::with_capacity(len);
+        for _ in 0 .. len {
+            let color = Color{r: r.f32()?, g: r.f32()?, b: r.f32()?};
+            row.push(Splat { color, weight: r.f32()? });
+        }
+        film.splats.push(row);
+    }
+    Ok(film)
+}
+
+fn aov_floats(a: &Aovs) -> [f32; 16] {
+    [
+        a.albedo.r, a.albedo.g, a.albedo.b,
+        a.normal[0], a.normal[1], a.normal[2],
+        a.depth,
+        a.position[0], a.position[1], a.position[2],
+        a.direct.r, a.direct.g, a.direct.b,
+        a.indirect.r, a.indirect.g, a.indirect.b,
+    ]
+}
+
+fn aov_from_floats(v: &[f32; 16]) -> Aovs {
+    let mut a = Aovs::default();
+    a.albedo = Color{r: v[0], g: v[1], b: v[2]};
+    a.normal = [v[3], v[4], v[5]];
+    a.depth = v[6];
+    a.position = [v[7], v[8], v[9]];
+    a.direct = Color{r: v[10], g: v[11], b: v[12]};
+    a.indirect = Color{r: v[13], g: v[14], b: v[15]};
+    a
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> std::result::Result<&[u8], String> {
+        let b = self.data.get(self.pos .. self.pos + n).ok_or("truncated")?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> std::result::Result<u64, String> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> std::result::Result<f32, String> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{camera::{Camera, CameraView, Film, PixelStats, RenderOptions, Splat}, checkpoint::{load, save, Checkpointing}, error::Error, hit_record::HittableList, progress::Progress, scene::Scene, sky::Sky, vec3d::Vec3d, Color, Point3d};
+
+    #[test]
+    fn film_round_trip() {
+        let film = Film {
+            stats: vec![PixelStats { n: 3, mean: 0.5, m2: 0.25 }; 6],
+            splats: vec![vec![], vec![Splat { color: Color{r: 1.0, g: 2.0, b: 3.0}, weight: 0.5 }; 9]],
+            aovs: vec![],
+        };
+
+        let path = std::env::temp_dir().join("raytracer_film_round_trip.checkpoint");
+        let path = path.to_str().unwrap();
+        save(path, 42, 3, 2, 1, &film).unwrap();
+
+        let loaded = load(path, 42, 3, 2, 1).unwrap();
+        assert_eq!(loaded.stats[5].n, 3);
+        assert!(loaded.splats[0].is_empty());
+        assert_eq!(loaded.splats[1][8].color.g, 2.0);
+        assert!(load(path, 43, 3, 2, 1).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_checkpoint_that_cannot_be_saved_fails_the_finished_render() {
+        let checkpoint = Checkpointing { path: "/nonexistent/render.checkpoint".to_string(), interval: None, settings_hash: 0 };
+        let options = RenderOptions { checkpoint: Some(checkpoint), ..RenderOptions::default() };
+        let view = CameraView {
+            vfov: 40.0,
+            lookfrom: Point3d::new(0.0, 0.0, 1.0),
+            lookat: Point3d::new(0.0, 0.0, 0.0),
+            vup: Vec3d::new(0.0, 1.0, 0.0),
+            defocus_angle: 0.0,
+            focus_dist: 1.0,
+        };
+        let camera = Arc::new(Camera::initialize(2.0, 8, 4, 2, view).with_options(options));
+        let scene = Scene { world: HittableList::default(), sky: Sky::Gradient };
+
+        let e = Camera::render(camera.clone(), Arc::new(scene), 2, &mut Progress::quiet()).unwrap_err();
+        assert!(matches!(&e, Error::Io { file, .. } if file == "/nonexistent/render.checkpoint"), "{e}");
+        // the image is there anyway
+        assert!(camera.pixels.lock().unwrap().iter().all(|c| c.g > 0.0));
+    }
+}
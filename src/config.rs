@@ -1,49 +1,529 @@
+//! The settings of config.toml, one struct per section. Every struct mirrors its section
+//! key by key, the renderer types are built from them (see [`Settings`]). The structs
+//! are `#[non_exhaustive]`: read them from a file or a string and change what differs
 use serde_derive::Deserialize;
-use config::{Config, ConfigError, File};
+use config::{Config, File, FileFormat};
 
+use crate::{camera::RenderOptions, error::{Error, Result}, preview, scene, sky, tonemap};
+
+/// `[ground.diffuse]`, a Lambertian ground
 #[derive(Debug, Clone, Deserialize)]
-pub(crate) struct Diffuse {
-    pub(crate) albedo: [f32; 3],
+#[non_exhaustive]
+pub struct Diffuse {
+    /// reflected fraction of every channel
+    pub albedo: [f32; 3],
 }
 
+/// `[ground.metal]`, a mirror blurred by fuzz
 #[derive(Debug, Clone, Deserialize)]
-pub(crate) struct Metal {
-    pub(crate) albedo: [f32; 3],
-    pub(crate) fuzz: f32,
+#[non_exhaustive]
+pub struct Metal {
+    /// reflected fraction of every channel
+    pub albedo: [f32; 3],
+    /// radius of the random offset of the reflected direction, 0 is a perfect mirror
+    pub fuzz: f32,
 }
 
+/// `[ground.dielectric]`, smooth glass
 #[derive(Debug, Clone, Deserialize)]
-pub(crate) struct Dielectric {
-    pub(crate) refraction: f32,
+#[non_exhaustive]
+pub struct Dielectric {
+    /// refractive index
+    pub refraction: f32,
+    /// dispersion preset for the spectral mode: "bk7", "fused_silica" or "diamond"
+    pub dispersion: Option<String>,
+    /// Cauchy coefficients `[a, b]` (b in um²) when there is no preset
+    pub cauchy: Option<[f32; 2]>,
+    /// color left after light travels `transmittance_distance` inside, clear if unset
+    pub transmittance: Option<[f32; 3]>,
+    /// distance of the transmittance color, 1 by default, has to be positive
+    pub transmittance_distance: Option<f32>,
 }
 
+/// `[ground.conductor]`, a rough GGX metal
 #[derive(Debug, Clone, Deserialize)]
-pub(crate) struct Ground {
-    pub(crate) _color: [f32; 3],
-    pub(crate) center: [f32; 3],
-    pub(crate) radius: f32,
-    pub(crate) material: String,
-    pub(crate) diffuse: Option<Diffuse>,
-    pub(crate) metal: Option<Metal>,
-    pub(crate) dielectric: Option<Dielectric>,
+#[non_exhaustive]
+pub struct Conductor {
+    /// "gold", "copper", "aluminum" or "silver", instead of eta and k
+    pub preset: Option<String>,
+    /// real part of the complex refractive index per channel
+    pub eta: Option<[f32; 3]>,
+    /// imaginary part (extinction) of the complex refractive index per channel
+    pub k: Option<[f32; 3]>,
+    /// GGX roughness from 0 (mirror) to 1
+    pub roughness: f32,
+    /// stretch of the highlight from -1 to 1, 0 is isotropic
+    pub anisotropy: Option<f32>,
 }
 
+/// `[ground.rough_dielectric]`, frosted glass
 #[derive(Debug, Clone, Deserialize)]
-pub(crate) struct Settings {
+#[non_exhaustive]
+pub struct RoughDielectric {
+    /// refractive index
+    pub refraction: f32,
+    /// GGX roughness from 0 (smooth) to 1
+    pub roughness: f32,
+    /// stretch of the highlight from -1 to 1, 0 is isotropic
+    pub anisotropy: Option<f32>,
+    /// color left after light travels `transmittance_distance` inside, clear if unset
+    pub transmittance: Option<[f32; 3]>,
+    /// distance of the transmittance color, 1 by default, has to be positive
+    pub transmittance_distance: Option<f32>,
+}
+
+/// A material parameter given either as a constant or as a procedural texture
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum TextureParam {
+    /// the same value everywhere
+    Scalar(f32),
+    /// the same color everywhere
+    Color([f32; 3]),
+    /// a 3d checker of cells `scale` wide alternating between two colors
+    Checker {
+        /// width of a cell
+        scale: f32,
+        /// color of the cells whose indices add up to an even number
+        even: [f32; 3],
+        /// color of the other cells
+        odd: [f32; 3],
+    },
+}
+
+/// `[ground.principled]`, the uber material. The unset parameters take the defaults of
+/// [`Principled`](crate::Principled)
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct Principled {
+    /// diffuse color, and the specular color of metals
+    pub base_color: TextureParam,
+    /// from dielectric (0) to metal (1)
+    pub metallic: Option<TextureParam>,
+    /// roughness of the specular and transmission lobes
+    pub roughness: Option<TextureParam>,
+    /// strength of the dielectric reflection, 0.5 is a reflectance of 4%
+    pub specular: Option<TextureParam>,
+    /// strength of a white varnish layer over everything
+    pub clearcoat: Option<TextureParam>,
+    /// roughness of the varnish layer
+    pub clearcoat_roughness: Option<f32>,
+    /// velvet-like reflection at grazing angles
+    pub sheen: Option<TextureParam>,
+    /// from opaque (0) to glass (1)
+    pub transmission: Option<TextureParam>,
+    /// refractive index of the transmission
+    pub ior: Option<f32>,
+    /// stretch of the highlight from -1 to 1, 0 is isotropic
+    pub anisotropy: Option<f32>,
+}
+
+/// `[ground]`, the big sphere the scenes stand on, and the sections of its materials
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct Ground {
+    /// unused, left for older settings
+    pub _color: [f32; 3],
+    /// center of the sphere
+    pub center: [f32; 3],
+    /// radius of the sphere
+    pub radius: f32,
+    /// the material, one of the names of the sections below
+    pub material: String,
+    /// `[ground.diffuse]`
+    pub diffuse: Option<Diffuse>,
+    /// `[ground.metal]`
+    pub metal: Option<Metal>,
+    /// `[ground.dielectric]`
+    pub dielectric: Option<Dielectric>,
+    /// `[ground.conductor]`
+    pub conductor: Option<Conductor>,
+    /// `[ground.rough_dielectric]`
+    pub rough_dielectric: Option<RoughDielectric>,
+    /// `[ground.principled]`
+    pub principled: Option<Principled>,
+}
+
+/// `[camera]`, every value set replaces that of the view of the scene
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct Camera {
+    /// position of the camera
+    pub lookfrom: Option<[f32; 3]>,
+    /// point the camera looks at
+    pub lookat: Option<[f32; 3]>,
+    /// direction that is up in the image
+    pub vup: Option<[f32; 3]>,
+    /// vertical field of view in degrees
+    pub vfov: Option<f32>,
+    /// width / height of the image, 16:9 unless the scene has another
+    pub aspect_ratio: Option<f32>,
+    /// cone angle of the rays through a pixel in degrees, 0 is a pinhole
+    pub defocus_angle: Option<f32>,
+    /// distance of the sharp plane from lookfrom
+    pub focus_dist: Option<f32>,
+}
+
+impl Camera {
+    /// These settings, the unset ones taken from `defaults`
+    pub fn or(&self, defaults: &Camera) -> Camera {
+        Camera {
+            lookfrom: self.lookfrom.or(defaults.lookfrom),
+            lookat: self.lookat.or(defaults.lookat),
+            vup: self.vup.or(defaults.vup),
+            vfov: self.vfov.or(defaults.vfov),
+            aspect_ratio: self.aspect_ratio.or(defaults.aspect_ratio),
+            defocus_angle: self.defocus_angle.or(defaults.defocus_angle),
+            focus_dist: self.focus_dist.or(defaults.focus_dist),
+        }
+    }
+
+    /// These settings as a `[camera]` section of config.toml, the unset ones left out
+    pub fn to_toml(&self) -> String {
+        let mut toml = "[camera]\n".to_string();
+        let mut line = |name: &str, value: Option<String>| if let Some(v) = value {
+            toml += &format!("{name} = {v}\n");
+        };
+        // the Debug format of f32 always has a decimal point or an exponent, as TOML floats need
+        line("lookfrom", self.lookfrom.map(|v| format!("{v:?}")));
+        line("lookat", self.lookat.map(|v| format!("{v:?}")));
+        line("vup", self.vup.map(|v| format!("{v:?}")));
+        line("vfov", self.vfov.map(|v| format!("{v:?}")));
+        line("aspect_ratio", self.aspect_ratio.map(|v| format!("{v:?}")));
+        line("defocus_angle", self.defocus_angle.map(|v| format!("{v:?}")));
+        line("focus_dist", self.focus_dist.map(|v| format!("{v:?}")));
+        toml
+    }
+}
+
+/// `[scene.sea_of_balls]`, the random spheres of the book cover
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct SeaOfBalls {
+    /// the grid of cells goes from -extent to extent on both axes
+    pub extent: Option<u16>,
+    /// radius of the small balls
+    pub radius: Option<f32>,
+    /// fraction of the cells with a ball, from 0 to 1
+    pub density: Option<f32>,
+    /// share of the diffuse balls
+    pub diffuse_percent: Option<f32>,
+    /// share of the metal balls
+    pub metal_percent: Option<f32>,
+    /// share of the glass balls
+    pub dielectric_percent: Option<f32>,
+}
+
+/// The `[material]` section of the settings from before the scene generators, the sea of
+/// balls reads the percents it leaves unset in `[scene.sea_of_balls]` from it
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct Material {
+    /// share of the diffuse balls
+    pub diffuse_percent: Option<f32>,
+    /// share of the metal balls
+    pub metal_percent: Option<f32>,
+    /// share of the glass balls
+    pub dielectric_percent: Option<f32>,
+}
+
+/// `[scene.cornell_box]`, a box of sphere walls open towards the camera
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct CornellBox {
+    /// albedo of the left wall
+    pub left: Option<[f32; 3]>,
+    /// albedo of the right wall
+    pub right: Option<[f32; 3]>,
+    /// albedo of the floor, the back wall and the box
+    pub white: Option<[f32; 3]>,
+    /// radius of the wall spheres, the bigger the flatter (up to 1e4)
+    pub wall_radius: Option<f32>,
+    /// a glass ball in the box
+    pub glass: Option<bool>,
+}
+
+/// `[scene.random_triangles]`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct RandomTriangles {
+    /// number of triangles
+    pub count: Option<u32>,
+    /// largest offset of a corner from the center of its triangle
+    pub size: Option<f32>,
+    /// the centers are between -extent and extent on the ground axes
+    pub extent: Option<f32>,
+}
+
+/// `[scene.forest]`, one tree of triangles instanced many times
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct Forest {
+    /// number of trees
+    pub trees: Option<u32>,
+    /// the trees stand between -extent and extent on the ground axes
+    pub extent: Option<f32>,
+    /// trees are scaled by up to this fraction either way, from 0 to below 1
+    pub scale_variation: Option<f32>,
+}
+
+/// `[scene.material_grid]`, a row of balls per material
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct MaterialGrid {
+    /// number of balls in a row, the roughness goes from 0 to 1 over them
+    pub columns: Option<u8>,
+    /// color of all the materials
+    pub base_color: Option<[f32; 3]>,
+}
+
+/// `[preview]`, the contact sheet of `raytracer preview`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct Preview {
+    /// ground material names, each is rendered as defined by its `[ground.<name>]` section
+    pub materials: Vec<String>,
+    /// parameter varied over the columns, every material is a row then
+    pub parameter: Option<String>,
+    /// first value of the parameter, the usual lower end of its range by default
+    pub from: Option<f32>,
+    /// last value of the parameter, the usual upper end of its range by default
+    pub to: Option<f32>,
+    /// number of cells in a row
+    pub columns: Option<u8>,
+    /// pixels of the side of a cell
+    pub size: Option<u16>,
+    /// samples per pixel of the cells
+    pub samples_per_pixel: Option<u16>,
+    /// file the sheet is written to
+    pub output: Option<String>,
+}
+
+/// `[scene]`, the procedural scene and the parameters of each generator
+#[derive(Debug, Clone, Default, Deserialize)]
+#[non_exhaustive]
+pub struct Scene {
+    /// name of the generator, see [`GENERATORS`](crate::GENERATORS)
+    pub generator: Option<String>,
+    /// `[scene.sea_of_balls]`
+    pub sea_of_balls: Option<SeaOfBalls>,
+    /// `[scene.cornell_box]`
+    pub cornell_box: Option<CornellBox>,
+    /// `[scene.random_triangles]`
+    pub random_triangles: Option<RandomTriangles>,
+    /// `[scene.forest]`
+    pub forest: Option<Forest>,
+    /// `[scene.material_grid]`
+    pub material_grid: Option<MaterialGrid>,
+}
+
+/// `[sky]`, the light of every scene
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct Sky {
+    /// "gradient", "daylight" or "uniform"
+    pub model: String,
+    /// haziness of the daylight sky, 2 is clear
+    pub turbidity: Option<f32>,
+    /// scale of the daylight sky radiance
+    pub intensity: Option<f32>,
+    /// direction towards the sun, instead of the date, time and latitude
+    pub sun_direction: Option<[f32; 3]>,
+    /// day of the year of the sun position, from 1
+    pub day_of_year: Option<u16>,
+    /// hours of the solar time of the sun position, 12 is noon
+    pub solar_time: Option<f32>,
+    /// latitude in degrees of the sun position
+    pub latitude: Option<f32>,
+    /// irradiance of the sun disk on a surface facing it
+    pub sun_irradiance: Option<f32>,
+    /// angular radius of the sun disk in degrees
+    pub sun_angular_radius: Option<f32>,
+    /// radiance of the uniform sky, white by default
+    pub radiance: Option<[f32; 3]>,
+}
+
+/// `[filter]`, the pixel reconstruction filter
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct Filter {
+    /// "box", "tent", "gaussian", "mitchell" or "lanczos"
+    pub name: String,
+    /// pixels from the center where the filter ends
+    pub radius: Option<f32>,
+    /// falloff of the gaussian
+    pub alpha: Option<f32>,
+    /// b of the Mitchell-Netravali cubic
+    pub b: Option<f32>,
+    /// c of the Mitchell-Netravali cubic
+    pub c: Option<f32>,
+    /// number of lobes of the lanczos sinc
+    pub tau: Option<f32>,
+}
+
+/// `[tonemap]`, from radiance to displayable pixels
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct ToneMapping {
+    /// "clamp", "reinhard", "extended_reinhard", "filmic" or "aces"
+    pub operator: String,
+    /// exposure in stops
+    pub exposure: Option<f32>,
+    /// radiance that becomes white for extended_reinhard and filmic
+    pub white: Option<f32>,
+    /// dither before quantizing to 8 bits
+    pub dither: Option<bool>,
+}
+
+/// `[aov]`, the extra render passes
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct Aov {
+    /// names of the passes, see [`Pass`](crate::Pass)
+    pub passes: Vec<String>,
+    /// the passes are written as `<prefix>_<pass>`
+    pub prefix: Option<String>,
+}
+
+/// `[denoise]`, the edge-aware a-trous filter
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct Denoise {
+    /// denoise the finished render
+    pub enabled: bool,
+    /// number of passes, each twice as wide as the one before
+    pub iterations: Option<u8>,
+    /// color difference the filter smooths over
+    pub sigma_color: Option<f32>,
+    /// normal difference the filter smooths over
+    pub sigma_normal: Option<f32>,
+    /// relative depth difference the filter smooths over
+    pub sigma_depth: Option<f32>,
+}
+
+/// `[checkpoint]`, saving a render to resume it
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct Checkpoint {
+    /// file of the checkpoint, rendered.checkpoint by default
+    pub path: Option<String>,
+    /// seconds between saves, unset saves only the finished render
+    pub interval: Option<f32>,
+}
+
+/// `[progress]`, the reports of a running render
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct Progress {
+    /// progress bar on the terminal
+    pub bar: bool,
+    /// file the progress and the final statistics are written to as json lines
+    pub json: Option<String>,
+}
+
+/// `[adaptive]`, variance driven sampling
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct Adaptive {
+    /// samples every pixel gets
+    pub min_samples: u16,
+    /// samples a pixel gets at most
+    pub max_samples: u16,
+    /// a pixel stops once its noise is below this
+    pub threshold: f32,
+    /// image of the sample count of every pixel
+    pub heatmap: Option<String>,
+}
+
+/// Everything in config.toml
+#[derive(Debug, Clone, Deserialize)]
+#[non_exhaustive]
+pub struct Settings {
+    /// image width in pixels, the height follows from the aspect ratio
     pub width: u16,
+    /// samples of every pixel
     pub samples_per_pixel: u16,
+    /// longest path in bounces
     pub max_depth: u8,
+    /// render on `threads` threads instead of one
     pub multithread_enabled: bool,
+    /// number of render threads
     pub threads: u8,
+    /// trace wavelengths instead of rgb, needed for dispersion
+    pub spectral: Option<bool>,
+    /// "independent", "stratified", "halton" or "sobol"
+    pub sampler: Option<String>,
+    /// seeds the scene layout and the samples
+    pub seed: Option<u32>,
+    /// `[adaptive]`
+    pub adaptive: Option<Adaptive>,
+    /// `[filter]`
+    pub filter: Option<Filter>,
+    /// `[tonemap]`
+    pub tonemap: Option<ToneMapping>,
+    /// `[aov]`
+    pub aov: Option<Aov>,
+    /// `[denoise]`
+    pub denoise: Option<Denoise>,
+    /// `[checkpoint]`
+    pub checkpoint: Option<Checkpoint>,
+    /// `[progress]`
+    pub progress: Option<Progress>,
+    /// `[ground]`
     pub ground: Ground,
+    /// `[sky]`
+    pub sky: Option<Sky>,
+    /// `[camera]`
+    pub camera: Option<Camera>,
+    /// `[scene]`
+    pub scene: Option<Scene>,
+    /// `[material]` of older settings
+    pub material: Option<Material>,
+    /// `[preview]`
+    pub preview: Option<Preview>,
+    /// the file the settings were read from, for error messages
+    #[serde(skip)]
+    pub file: Option<String>,
 } 
 
 impl Settings {
-    pub(crate) fn new() -> Result<Self, ConfigError> {
-        let s = Config::builder()
-            .add_source(File::with_name("config"))
-            .build()?;
+    /// Reads config.toml from the working directory
+    pub fn new() -> Result<Self> {
+        Self::from_file("config.toml")
+    }
+
+    /// Reads and validates the settings from a file, the format follows from the extension
+    pub fn from_file(path: &str) -> Result<Self> {
+        Self::load(File::with_name(path), path)
+    }
+
+    /// Reads and validates settings in the config.toml format from a string
+    pub fn from_toml(text: &str) -> Result<Self> {
+        Self::load(File::from_str(text, FileFormat::Toml), "<string>")
+    }
+
+    fn load<S: config::Source + Send + Sync + 'static>(source: S, file: &str) -> Result<Self> {
+        let mut s: Settings = Config::builder()
+            .add_source(source)
+            .build()
+            .and_then(|c| c.try_deserialize())
+            .map_err(|e| Error::Config { file: file.to_string(), message: e.to_string() })?;
+        s.file = Some(file.to_string());
+        s.validate().map_err(|e| e.in_file(Some(file)))?;
+        Ok(s)
+    }
 
-        s.try_deserialize()
+    // Every name and material in the settings, so a typo stops the program before the
+    // scene is built and not halfway through it
+    fn validate(&self) -> Result<()> {
+        RenderOptions::from_settings(self)?;
+        tonemap::ToneMapping::new(&self.tonemap)?;
+        sky::Sky::new(&self.sky)?;
+        scene::ground_material(&self.ground)?;
+        scene::Generator::from_settings(self)?;
+        if self.preview.is_some() {
+            preview::preview_cells(self)?;
+        }
+        Ok(())
     }
 }
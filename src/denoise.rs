@@ -0,0 +1,141 @@
+use crate::{aov::Aovs, config, Color};
+
+// B3 spline, the 1d kernel of the 5x5 a-trous filter
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Edge-avoiding a-trous wavelet filter (Dammertz et al. 2010). Every iteration blurs with
+// a twice as sparse kernel, neighbours count less the more their color, normal or depth
+// differ. The albedo is divided out first so textures stay sharp
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Denoiser {
+    pub(crate) iterations: u8,
+    pub(crate) sigma_color: f32,
+    pub(crate) sigma_normal: f32,
+    // relative to the depth of the pixel
+    pub(crate) sigma_depth: f32,
+}
+
+impl Denoiser {
+    pub(crate) fn new(c: &Option<config::Denoise>) -> Option<Denoiser> {
+        let c = c.as_ref()?;
+        if !c.enabled {
+            return None;
+        }
+        Some(Denoiser {
+            iterations: c.iterations.unwrap_or(5),
+            sigma_color: c.sigma_color.unwrap_or(0.5),
+            sigma_normal: c.sigma_normal.unwrap_or(0.1),
+            sigma_depth: c.sigma_depth.unwrap_or(0.05),
+        })
+    }
+
+    pub(crate) fn denoise(&self, pixels: &[Color], features: &[Aovs], width: usize, height: usize) -> Vec<Color> {
+        let albedo: Vec<Color> = features.iter().map(|f| demodulation(&f.albedo)).collect();
+        let mut irradiance: Vec<Color> = pixels.iter().zip(&albedo)
+            .map(|(c, a)| Color{r: c.r / a.r, g: c.g / a.g, b: c.b / a.b})
+            .collect();
+
+        for i in 0 .. self.iterations {
+            let step = 1usize << i;
+            // the color differences left after every pass are smaller
+            let sigma_color = self.sigma_color * f32::powi(0.5, i32::from(i));
+            irradiance = self.pass(&irradiance, features, width, height, step, sigma_color);
+        }
+
+        irradiance.iter().zip(&albedo).map(|(c, a)| *c * *a).collect()
+    }
+
+    fn pass(&self, input: &[Color], features: &[Aovs], width: usize, height: usize, step: usize, sigma_color: f32) -> Vec<Color> {
+        let mut output = Vec::with_capacity(input.len());
+
+        for y in 0 .. height {
+            for x in 0 .. width {
+                let p = y * width + x;
+                let (cp, fp) = (input[p], &features[p]);
+                let mut sum = Color::default();
+                let mut weight_sum = 0.0;
+
+                for (ky, hy) in KERNEL.iter().enumerate() {
+                    let Some(qy) = (y + ky * step).checked_sub(2 * step).filter(|qy| *qy < height) else {
+                        continue;
+                    };
+                    for (kx, hx) in KERNEL.iter().enumerate() {
+                        let Some(qx) = (x + kx * step).checked_sub(2 * step).filter(|qx| *qx < width) else {
+                            continue;
+                        };
+                        let q = qy * width + qx;
+                        let (cq, fq) = (input[q], &features[q]);
+
+                        let dc = cp + cq * -1.0;
+                        let color_dist = dc.r * dc.r + dc.g * dc.g + dc.b * dc.b;
+                        let normal_dist: f32 = fp.normal.iter().zip(fq.normal).map(|(a, b)| (a - b) * (a - b)).sum();
+                        let depth_dist = (fp.depth - fq.depth) / (self.sigma_depth * fp.depth.max(1e-3));
+
+                        let w = hx * hy
+                            * f32::exp(-color_dist / (sigma_color * sigma_color)
+                                - normal_dist / (self.sigma_normal * self.sigma_normal)
+                                - depth_dist * depth_dist);
+                        sum = sum + cq * w;
+                        weight_sum += w;
+                    }
+                }
+
+                // the center pixel always has a positive weight
+                output.push(sum * (1.0 / weight_sum));
+            }
+        }
+        output
+    }
+}
+
+// Albedo the color is divided by, dark albedos (and the background) are left alone
+fn demodulation(albedo: &Color) -> Color {
+    let d = |v: f32| if v > 0.01 { v } else { 1.0 };
+    Color{r: d(albedo.r), g: d(albedo.g), b: d(albedo.b)}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{aov::Aovs, denoise::Denoiser, sampler, Color};
+
+    fn denoiser() -> Denoiser {
+        Denoiser { iterations: 4, sigma_color: 0.5, sigma_normal: 0.1, sigma_depth: 0.05 }
+    }
+
+    fn flat_features(n: usize) -> Vec<Aovs> {
+        let mut f = Aovs::default();
+        f.albedo = Color{r: 0.5, g: 0.5, b: 0.5};
+        f.normal = [0.0, 1.0, 0.0];
+        f.depth = 2.0;
+        vec![f; n]
+    }
+
+    #[test]
+    fn noise_on_a_flat_surface_goes_away() {
+        let (w, h) = (32, 32);
+        let noisy: Vec<Color> = (0 .. w * h).map(|i| {
+            let v = 0.25 + 0.2 * (sampler::hash(i as u32) as f32 / u32::MAX as f32 - 0.5);
+            Color{r: v, g: v, b: v}
+        }).collect();
+        let denoised = denoiser().denoise(&noisy, &flat_features(w * h), w, h);
+
+        let deviation = |img: &[Color]| img.iter().map(|c| (c.g - 0.25).abs()).sum::<f32>() / img.len() as f32;
+        assert!(deviation(&denoised) < 0.25 * deviation(&noisy));
+    }
+
+    #[test]
+    fn geometric_edges_are_kept() {
+        let (w, h) = (16, 16);
+        let mut features = flat_features(w * h);
+        let mut pixels = vec![Color{r: 0.1, g: 0.1, b: 0.1}; w * h];
+        for i in 0 .. w * h {
+            if i % w >= w / 2 {
+                features[i].normal = [1.0, 0.0, 0.0];
+                pixels[i] = Color{r: 0.4, g: 0.4, b: 0.4};
+            }
+        }
+        let denoised = denoiser().denoise(&pixels, &features, w, h);
+        assert!((denoised[w / 2 - 1].r - 0.1).abs() < 1e-3);
+        assert!((denoised[w / 2].r - 0.4).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{fmt, io};
+
+/// Everything that can go wrong loading settings, building a scene or writing a render
+#[derive(Debug)]
+pub enum Error {
+    /// The settings can't be read: a missing file, invalid TOML or a missing or mistyped
+    /// field. The message of the parser names the line or the key
+    Config {
+        /// the settings file
+        file: String,
+        /// the error of the parser
+        message: String,
+    },
+    /// A setting has a value the renderer doesn't know, `field` is its dotted path. The file
+    /// is known for settings read by [`Settings::from_file`](crate::Settings::from_file)
+    Invalid {
+        /// the settings file, if there is one
+        file: Option<String>,
+        /// dotted path of the setting, like `ground.material`
+        field: String,
+        /// what is wrong with the value
+        message: String,
+    },
+    /// A file written by an earlier run (a checkpoint, benchmark results) can't be used
+    Asset {
+        /// the file
+        file: String,
+        /// why it can't be used
+        message: String,
+    },
+    /// Reading or writing a file failed
+    Io {
+        /// the file read or written
+        file: String,
+        /// the error of the operating system
+        source: io::Error,
+    },
+    /// A render thread panicked
+    Render(String),
+}
+
+/// The result of everything that can fail with an [`Error`]
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn invalid(field: &str, message: impl Into<String>) -> Error {
+        Error::Invalid { file: None, field: field.to_string(), message: message.into() }
+    }
+
+    pub(crate) fn io(file: &str, source: io::Error) -> Error {
+        Error::Io { file: file.to_string(), source }
+    }
+
+    // Names the file of an invalid setting, errors that have one already keep it
+    pub(crate) fn in_file(self, file: Option<&str>) -> Error {
+        match (self, file) {
+            (Error::Invalid { file: None, field, message }, Some(file)) => Error::Invalid { file: Some(file.to_string()), field, message },
+            (e, _) => e,
+        }
+    }
+}
+
+// An unknown name with the ones that would have been accepted
+pub(crate) fn unknown(field: &str, kind: &str, name: &str, known: &[&str]) -> Error {
+    Error::invalid(field, format!("unknown {kind} \"{name}\", expected one of {}", known.join(", ")))
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config { file, message } => write!(f, "{file}: {message}"),
+            Error::Invalid { file: Some(file), field, message } => write!(f, "{file}: {field}: {message}"),
+            Error::Invalid { file: None, field, message } => write!(f, "{field}: {message}"),
+            Error::Asset { file, message } => write!(f, "{file}: {message}"),
+            Error::Io { file, source } => write!(f, "{file}: {source}"),
+            Error::Render(message) => write!(f, "render failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::Settings, error::Error, scene::sea_of_balls_scene};
+
+    const CONFIG: &str = include_str!("../config.toml");
+
+    fn load(from: &str, to: &str) -> Result<Settings, Error> {
+        assert!(CONFIG.contains(from));
+        Settings::from_toml(&CONFIG.replacen(from, to, 1))
+    }
+
+    #[test]
+    fn unknown_names_are_errors_with_the_field() {
+        let e = load("material = \"diffuse\"", "material = \"difuse\"").unwrap_err();
+        assert!(matches!(&e, Error::Invalid { file: Some(_), field, .. } if field == "ground.material"), "{e}");
+        assert!(e.to_string().contains("\"difuse\", expected one of diffuse, metal"), "{e}");
+
+        let e = load("operator = \"clamp\"", "operator = \"aces2\"").unwrap_err();
+        assert!(matches!(&e, Error::Invalid { field, .. } if field == "tonemap.operator"), "{e}");
+        let e = load("sampler = \"sobol\"", "sampler = \"random\"").unwrap_err();
+        assert!(matches!(&e, Error::Invalid { field, .. } if field == "sampler"), "{e}");
+    }
+
+    #[test]
+    fn missing_material_section_is_an_error() {
+        // settings changed in code are checked again when the scene is built
+        let mut c = load("", "").unwrap();
+        c.ground.diffuse = None;
+        c.file = None;
+        let e = sea_of_balls_scene(c).err().unwrap();
+        assert_eq!(e.to_string(), "ground.diffuse: the diffuse ground material needs a [ground.diffuse] section");
+    }
+
+    #[test]
+    fn syntax_errors_name_the_line() {
+        let e = load("max_depth = 50", "max_depth = = 50").unwrap_err();
+        assert!(matches!(&e, Error::Config { .. }), "{e}");
+        assert!(e.to_string().contains("line"), "{e}");
+    }
+}
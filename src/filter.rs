@@ -0,0 +1,138 @@
+use core::f32;
+
+use crate::{config, error::{unknown, Result}};
+
+// Pixel reconstruction filters. A sample is splatted onto every pixel whose center is
+// within the radius, weighted by the filter at the offset to that center (in pixels).
+// All of them are separable, f(x, y) = f(x) * f(y)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Filter {
+    Box { radius: f32 },
+    Tent { radius: f32 },
+    Gaussian { radius: f32, alpha: f32 },
+    // b = c = 1/3 is the recommended compromise between blur and ringing
+    Mitchell { radius: f32, b: f32, c: f32 },
+    // windowed sinc, tau is the number of lobes
+    Lanczos { radius: f32, tau: f32 },
+}
+
+impl Default for Filter {
+    // A box of one pixel only averages the samples of the pixel itself
+    fn default() -> Filter {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    pub(crate) fn new(c: &Option<config::Filter>) -> Result<Filter> {
+        let Some(c) = c else {
+            return Ok(Filter::default());
+        };
+        let filter = match c.name.as_str() {
+            "box" => Filter::Box { radius: c.radius.unwrap_or(0.5) },
+            "tent" => Filter::Tent { radius: c.radius.unwrap_or(1.0) },
+            "gaussian" => Filter::Gaussian { radius: c.radius.unwrap_or(1.5), alpha: c.alpha.unwrap_or(2.0) },
+            "mitchell" => Filter::Mitchell {
+                radius: c.radius.unwrap_or(2.0),
+                b: c.b.unwrap_or(1.0 / 3.0),
+                c: c.c.unwrap_or(1.0 / 3.0),
+            },
+            "lanczos" => Filter::Lanczos { radius: c.radius.unwrap_or(3.0), tau: c.tau.unwrap_or(3.0) },
+            name => return Err(unknown("filter.name", "filter", name, &["box", "tent", "gaussian", "mitchell", "lanczos"])),
+        };
+        Ok(filter)
+    }
+
+    pub(crate) fn radius(&self) -> f32 {
+        match self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => *radius,
+        }
+    }
+
+    // Number of neighbouring pixels a sample can reach on each side
+    pub(crate) fn reach(&self) -> u16 {
+        (self.radius() - 0.5).ceil().max(0.0) as u16
+    }
+
+    pub(crate) fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        if x > self.radius() {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { radius } => radius - x,
+            Filter::Gaussian { radius, alpha } => {
+                f32::max(0.0, f32::exp(-alpha * x * x) - f32::exp(-alpha * radius * radius))
+            },
+            Filter::Mitchell { radius, b, c } => mitchell(2.0 * x / radius, b, c),
+            Filter::Lanczos { tau, .. } => sinc(x) * sinc(x / tau),
+        }
+    }
+}
+
+// Mitchell-Netravali cubic for x in [0, 2]
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    let x2 = x * x;
+    let x3 = x2 * x;
+    let v = if x < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b)
+    } else {
+        (-b - 6.0 * c) * x3 + (6.0 * b + 30.0 * c) * x2 + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+    };
+    v / 6.0
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    let px = f32::consts::PI * x;
+    f32::sin(px) / px
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::Filter;
+
+    #[test]
+    fn filters_vanish_outside_the_radius() {
+        let filters = [
+            Filter::Box { radius: 0.5 },
+            Filter::Tent { radius: 1.0 },
+            Filter::Gaussian { radius: 1.5, alpha: 2.0 },
+            Filter::Mitchell { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 },
+            Filter::Lanczos { radius: 3.0, tau: 3.0 },
+        ];
+        for f in filters {
+            assert!(f.evaluate(0.0, 0.0) > 0.0, "{f:?}");
+            assert_eq!(f.evaluate(f.radius() + 0.01, 0.0), 0.0, "{f:?}");
+            if !matches!(f, Filter::Box { .. }) {
+                assert!(f.evaluate(f.radius() - 0.01, 0.0).abs() < 0.05, "{f:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn lanczos_has_negative_lobes() {
+        let f = Filter::Lanczos { radius: 3.0, tau: 3.0 };
+        // sinc(0.5) * sinc(0.5 / 3)
+        assert!((f.evaluate(0.5, 0.0) - 0.607_93).abs() < 1e-4, "{}", f.evaluate(0.5, 0.0));
+        assert!(f.evaluate(1.3, 0.0) < -0.1);
+    }
+
+    #[test]
+    fn splat_reach() {
+        assert_eq!(Filter::default().reach(), 0);
+        assert_eq!(Filter::Tent { radius: 1.0 }.reach(), 1);
+        assert_eq!(Filter::Mitchell { radius: 2.0, b: 0.0, c: 0.5 }.reach(), 2);
+    }
+}
@@ -7,17 +7,26 @@ use crate::material::MaterialEnum;
 use crate::{vec3d::Vec3d, Point3d};
 use crate::camera::Ray;
 use crate::interval::Interval;
+use crate::stats::{self, Counter};
 
+/// Where a ray hit a surface
 #[derive(Default)]
-pub(crate) struct HitRecord {
-    pub(crate) point: Point3d,
-    pub(crate) normal: Vec3d,
-    pub(crate) t: f32,
-    pub(crate) front_face: bool
+pub struct HitRecord {
+    /// the hit point
+    pub point: Point3d,
+    /// unit normal facing against the ray
+    pub normal: Vec3d,
+    /// ray parameter of the hit point
+    pub t: f32,
+    /// true if the ray hit the outside of the surface
+    pub front_face: bool,
+    /// the object in the object id pass
+    pub object_id: u32,
 }
 
 impl HitRecord {
-    pub(crate) fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3d) {
+    /// Sets the normal against the ray from the unit outward normal
+    pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3d) {
         // See the hit record normal vector
         // Note: outward_normal is assumed to have unit length
         self.front_face = Vec3d::dot(&r.direction, &outward_normal) < 0.0;
@@ -25,11 +34,18 @@ impl HitRecord {
     }
 }
 
+/// Ray intersection, returns the closest hit within the interval and its material.
+/// Implement it for your own shapes and add them as [`Hittable::Custom`], they are put into
+/// the BVH by their bounding box like the built-in ones. Set the `object_id` of the hit
+/// record to tell the objects apart in the object id pass (0 is the background)
 pub trait Hit: Send + Sync {
+    /// The closest hit of the ray within `ray_t` and the material there
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<(HitRecord, Arc<MaterialEnum>)>;
+    /// A box around the whole shape
     fn bounding_box(&self) -> &Aabb;
 }
 
+/// A sphere, moving linearly during the exposure when made with `new_dynamic`
 #[derive(Clone)]
 pub struct Sphere {
     //center: Point3d,
@@ -37,13 +53,16 @@ pub struct Sphere {
     radius: f32,
     material: Arc<MaterialEnum>,
     bbox: Aabb,
+    id: u32,    // set when added to a list, 0 means not assigned yet
 }
 
 impl Sphere {
+    /// A sphere at rest, a negative radius counts as 0
     pub fn new(center: Point3d, radius: f32, material: Arc<MaterialEnum>) -> Sphere {
         Self::new_dynamic(center.clone(), center, radius, material)
     }
 
+    /// A sphere moving from `center` to `center2` during the exposure
     pub fn new_dynamic(center: Point3d, center2: Point3d, radius: f32, material: Arc<MaterialEnum>) -> Sphere {
         let d = center2.as_vec3d() - center.as_vec3d();
         let rvec = Vec3d::new(radius, radius, radius);
@@ -68,7 +87,8 @@ impl Sphere {
             center,
             radius: radius.max(0.0), 
             material,
-            bbox
+            bbox,
+            id: 0,
         }
     }
 
@@ -80,6 +100,7 @@ impl Hit for Sphere {
     }
 
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<(HitRecord, Arc<MaterialEnum>)> {
+        stats::count(Counter::PrimitiveTests);
         let current_center = self.center.at(r.tm);
         let oc = current_center.as_vec3d() - r.origin.as_vec3d();
         let a = r.direction.length_squared();
@@ -108,6 +129,7 @@ impl Hit for Sphere {
             point: p,
             normal: Vec3d::new(0.0, 0.0, 0.0),
             front_face: false,
+            object_id: self.id,
         };
         
         hr.set_face_normal(r, outward_normal);
@@ -116,13 +138,133 @@ impl Hit for Sphere {
     } 
 }
 
+/// A flat triangle, seen from both sides. Its normal follows the winding a, b, c
 #[derive(Clone)]
+pub struct Triangle {
+    a: Point3d,
+    ab: Vec3d,
+    ac: Vec3d,
+    normal: Vec3d,
+    material: Arc<MaterialEnum>,
+    bbox: Aabb,
+    id: u32,
+}
+
+impl Triangle {
+    /// The triangle a, b, c
+    pub fn new(a: Point3d, b: Point3d, c: Point3d, material: Arc<MaterialEnum>) -> Triangle {
+        let ab = b.as_vec3d() - a.as_vec3d();
+        let ac = c.as_vec3d() - a.as_vec3d();
+        let normal = Vec3d::cross(&ab, &ac);
+        let normal = if normal.near_zero() { normal } else { Vec3d::unit(&normal) };
+
+        // padded, the box of a triangle in an axis plane has no thickness and no ray hits it
+        let pad = Vec3d::new(1e-4, 1e-4, 1e-4);
+        let min = |u: f32, v: f32, w: f32| u.min(v).min(w);
+        let max = |u: f32, v: f32, w: f32| u.max(v).max(w);
+        let lo = Vec3d::new(min(a.0.x, b.0.x, c.0.x), min(a.0.y, b.0.y, c.0.y), min(a.0.z, b.0.z, c.0.z)) - pad.clone();
+        let hi = Vec3d::new(max(a.0.x, b.0.x, c.0.x), max(a.0.y, b.0.y, c.0.y), max(a.0.z, b.0.z, c.0.z)) + pad;
+        let bbox = Aabb::from_points(&Point3d::from_vec3d(lo), &Point3d::from_vec3d(hi));
+
+        Triangle { a, ab, ac, normal, material, bbox, id: 0 }
+    }
+
+    // No area, its corners are on a line
+    pub(crate) fn is_degenerate(&self) -> bool {
+        self.normal.near_zero()
+    }
+}
+
+impl Hit for Triangle {
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    // Möller-Trumbore, a degenerate triangle has no area and is never hit
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<(HitRecord, Arc<MaterialEnum>)> {
+        stats::count(Counter::PrimitiveTests);
+        let p = Vec3d::cross(&r.direction, &self.ac);
+        let det = Vec3d::dot(&self.ab, &p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = r.origin.as_vec3d() - self.a.as_vec3d();
+        let u = Vec3d::dot(&s, &p) * inv_det;
+        if !(0.0 ..= 1.0).contains(&u) {
+            return None;
+        }
+        let q = Vec3d::cross(&s, &self.ab);
+        let v = Vec3d::dot(&r.direction, &q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = Vec3d::dot(&self.ac, &q) * inv_det;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        let mut hr = HitRecord { point: r.at(t), t, object_id: self.id, ..HitRecord::default() };
+        hr.set_face_normal(r, self.normal.clone());
+        Some((hr, self.material.clone()))
+    }
+}
+
+/// A shared object moved and uniformly scaled, many instances of one tree cost the memory
+/// of one. The whole instance is one object in the object id pass
+#[derive(Clone)]
+pub struct Instance {
+    object: Arc<Hittable>,
+    offset: Vec3d,
+    scale: f32,
+    bbox: Aabb,
+    id: u32,
+}
+
+impl Instance {
+    /// The object moved by offset after scaling it around the origin
+    pub fn new(object: Arc<Hittable>, offset: Vec3d, scale: f32) -> Instance {
+        let b = object.bounding_box();
+        let corner = |x: f32, y: f32, z: f32| Point3d::from_vec3d(Vec3d::new(x, y, z) * scale + offset.clone());
+        let bbox = Aabb::from_points(&corner(b.x.min, b.y.min, b.z.min), &corner(b.x.max, b.y.max, b.z.max));
+        Instance { object, offset, scale, bbox, id: 0 }
+    }
+}
+
+impl Hit for Instance {
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    // The ray goes into the space of the object with its direction scaled too, so the ray
+    // parameter and the normals stay the same
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<(HitRecord, Arc<MaterialEnum>)> {
+        let local = Ray {
+            origin: Point3d::from_vec3d((r.origin.as_vec3d() - self.offset.clone()) / self.scale),
+            direction: r.direction.clone() / self.scale,
+            tm: r.tm,
+            wavelength: r.wavelength,
+        };
+        let (mut hr, material) = self.object.hit(&local, ray_t)?;
+        hr.point = Point3d::from_vec3d(hr.point.as_vec3d() * self.scale + self.offset.clone());
+        if self.id != 0 {
+            hr.object_id = self.id;
+        }
+        Some((hr, material))
+    }
+}
+
+/// A group of objects, searched one by one. Put large ones into a [`BvhNode`](crate::BvhNode)
+#[derive(Clone, Default)]
 pub struct HittableList {
+    /// the objects in the order they were added
     pub objects: Vec<Hittable>,
+    /// the box around all of them
     pub bbox: Aabb,
 }
 
 impl HittableList {
+    /// A list of one object
     pub fn new(o: Hittable) -> HittableList {
         let mut hl = HittableList{
             objects: vec![],
@@ -132,14 +274,42 @@ impl HittableList {
         return hl;
     }
     
+    /// Spheres, triangles and instances without an id get the next one, so objects keep
+    /// the same id between renders of the same scene
     pub fn add(&mut self, o: Hittable) {
+        let id = self.objects.len() as u32 + 1;
+        let o = match o {
+            Hittable::Sphere(s) if s.id == 0 => Hittable::Sphere(Sphere { id, ..s }),
+            Hittable::Triangle(t) if t.id == 0 => Hittable::Triangle(Triangle { id, ..t }),
+            Hittable::Instance(i) if i.id == 0 => Hittable::Instance(Instance { id, ..i }),
+            o => o,
+        };
         self.bbox = Aabb::from_boxes(self.bbox.clone(), o.bounding_box().clone());
         self.objects.push(o);
     }
     
+    /// The number of objects
     pub fn len(&self) -> usize {
         return self.objects.len();
     }
+
+    /// True if there are no objects
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub(crate) fn for_each_material(&self, f: &mut dyn FnMut(&Arc<MaterialEnum>)) {
+        self.objects.iter().for_each(|o| o.for_each_material(f));
+    }
+
+    pub(crate) fn replace_sphere_material(&self, pick: &dyn Fn(&Point3d, f32) -> bool, material: &Arc<MaterialEnum>) -> Option<HittableList> {
+        let replaced: Vec<Option<Hittable>> = self.objects.iter().map(|o| o.replace_sphere_material(pick, material)).collect();
+        if replaced.iter().all(Option::is_none) {
+            return None;
+        }
+        let objects = replaced.into_iter().zip(&self.objects).map(|(r, o)| r.unwrap_or_else(|| o.clone())).collect();
+        Some(HittableList { objects, bbox: self.bbox.clone() })
+    }
 }
 
 impl Hit for HittableList {
@@ -166,27 +336,76 @@ impl Hit for HittableList {
     } 
 }
 
+/// Any object a ray can hit. The built-in shapes are matched directly, user defined ones
+/// go through a trait object
 #[derive(Clone)]
 pub enum Hittable {
+    /// a sphere
     Sphere(Sphere),
+    /// a triangle
+    Triangle(Triangle),
+    /// a moved and scaled copy of an object
+    Instance(Instance),
+    /// objects searched one by one
     List(HittableList),
+    /// objects in a bounding volume hierarchy
     BvhNode(BvhNode),
+    /// a user defined shape
+    Custom(Arc<dyn Hit>),
+}
+
+impl Hittable {
+    // Calls `f` with the material of every built-in shape, in the order of the tree.
+    // Custom shapes keep theirs to themselves
+    pub(crate) fn for_each_material(&self, f: &mut dyn FnMut(&Arc<MaterialEnum>)) {
+        match self {
+            Hittable::Sphere(s) => f(&s.material),
+            Hittable::Triangle(t) => f(&t.material),
+            Hittable::Instance(i) => i.object.for_each_material(f),
+            Hittable::List(list) => list.for_each_material(f),
+            Hittable::BvhNode(node) => node.for_each_material(f),
+            Hittable::Custom(_) => (),
+        }
+    }
+
+    // A copy with the material of the static spheres `pick` chooses by center and radius
+    // replaced, sharing the parts that stay the same. None if it chooses none
+    pub(crate) fn replace_sphere_material(&self, pick: &dyn Fn(&Point3d, f32) -> bool, material: &Arc<MaterialEnum>) -> Option<Hittable> {
+        match self {
+            Hittable::Sphere(s) if s.center.direction.length_squared() == 0.0 && pick(&s.center.origin, s.radius) =>
+                Some(Hittable::Sphere(Sphere { material: material.clone(), ..s.clone() })),
+            Hittable::Instance(i) => i.object.replace_sphere_material(pick, material)
+                .map(|o| Hittable::Instance(Instance { object: Arc::new(o), ..i.clone() })),
+            Hittable::List(list) => list.replace_sphere_material(pick, material).map(Hittable::List),
+            Hittable::BvhNode(node) => node.replace_sphere_material(pick, material).map(Hittable::BvhNode),
+            _ => None,
+        }
+    }
 }
 
 impl Hit for Hittable {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<(HitRecord, Arc<MaterialEnum>)> {
         match self {
             Hittable::Sphere(sphere) => sphere.hit(r, ray_t),
+            Hittable::Triangle(triangle) => triangle.hit(r, ray_t),
+            Hittable::Instance(instance) => instance.hit(r, ray_t),
             Hittable::List(list) => list.hit(r, ray_t),
             Hittable::BvhNode(bvh_node) => bvh_node.hit(r, ray_t),
+            Hittable::Custom(custom) => {
+                stats::count(Counter::PrimitiveTests);
+                custom.hit(r, ray_t)
+            }
         }
     }
 
     fn bounding_box(&self) -> &Aabb {
          match self {
             Hittable::Sphere(sphere) => sphere.bounding_box(),
+            Hittable::Triangle(triangle) => triangle.bounding_box(),
+            Hittable::Instance(instance) => instance.bounding_box(),
             Hittable::List(list) => list.bounding_box(),
             Hittable::BvhNode(bvh_node) => bvh_node.bounding_box(),
+            Hittable::Custom(custom) => custom.bounding_box(),
         }
     }
 }
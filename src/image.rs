@@ -0,0 +1,56 @@
+use std::{fs, io::{self, Write}};
+
+use crate::{error::{Error, Result}, tonemap::ToneMapping, Color};
+
+/// A rendered image, the linear radiance of every pixel row by row from the top left
+#[derive(Debug, Clone)]
+pub struct Image {
+    /// width in pixels
+    pub width: u16,
+    /// height in pixels
+    pub height: u16,
+    /// width * height colors
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    /// The pixel at x, y from the top left
+    pub fn pixel(&self, x: u16, y: u16) -> Color {
+        self.pixels[usize::from(y) * usize::from(self.width) + usize::from(x)]
+    }
+
+    /// 8 bit sRGB pixels after the exposure and tone mapping
+    pub fn to_rgb8(&self, tm: &ToneMapping) -> Vec<[u8; 3]> {
+        let width = u32::from(self.width);
+        self.pixels.iter().enumerate()
+            .map(|(i, c)| tm.encode(c, i as u32 % width, i as u32 / width))
+            .collect()
+    }
+
+    /// Writes the tone mapped image as a plain text PPM
+    pub fn write_ppm(&self, path: &str, tm: &ToneMapping) -> Result<()> {
+        write_ppm(path, self.width, self.height, self.to_rgb8(tm)).map_err(|e| Error::io(path, e))
+    }
+}
+
+/// Writes the per-pixel sample counts of an adaptive render as a blue (few) to red (max)
+/// heat map PPM
+pub fn write_heatmap(path: &str, width: u16, height: u16, counts: &[u16], max_samples: u16) -> Result<()> {
+    let pixels = counts.iter().map(|n| {
+        let t = f32::from(*n) / f32::from(max_samples.max(1));
+        let r = (255.0 * t.clamp(0.0, 1.0)) as u8;
+        let g = (255.0 * (1.0 - (2.0 * t - 1.0).abs()).clamp(0.0, 1.0)) as u8;
+        let b = (255.0 * (1.0 - t).clamp(0.0, 1.0)) as u8;
+        [r, g, b]
+    });
+    write_ppm(path, width, height, pixels).map_err(|e| Error::io(path, e))
+}
+
+pub(crate) fn write_ppm(path: &str, width: u16, height: u16, pixels: impl IntoIterator<Item = [u8; 3]>) -> io::Result<()> {
+    let mut f = io::BufWriter::new(fs::File::create(path)?);
+    writeln!(f, "P3\n{width} {height}\n255")?;
+    for [r, g, b] in pixels {
+        writeln!(f, "{r} {g} {b}")?;
+    }
+    f.flush()
+}
@@ -1,40 +1,51 @@
+/// A closed range of ray parameters or coordinates
 #[derive(Debug, PartialEq, Clone)]
 pub struct Interval {
+    /// lower end
     pub min: f32,
+    /// upper end, below min for an empty interval
     pub max: f32
 }
 
 impl Interval {
+    /// The interval from min to max
     pub fn new(min: f32, max: f32) -> Interval {
         Interval{min, max}
     }
 
+    /// The smallest interval around both
     pub fn from_intervals(a: &Interval, b: &Interval) -> Interval {
         let min = if a.min < b.min { a.min } else { b.min };
         let max = if a.max > b.max { a.max } else { b.max };
         Interval{min, max}
     }
 
+    /// The interval of all numbers
     pub fn default() -> Interval {
         Interval::new(f32::NEG_INFINITY, f32::INFINITY)
     }
 
+    /// The interval of no numbers
     pub fn empty() -> Interval {
         Interval::new(f32::INFINITY, f32::NEG_INFINITY)
     }
 
+    /// max - min, negative when empty
     pub fn size(&self) -> f32 {
         self.max - self.min
     }
 
+    /// True if x is in the interval, the ends included
     pub fn _contains(&self, x: f32) -> bool {
         self.min <= x && x <= self.max
     }
 
+    /// True if x is in the interval, the ends excluded
     pub fn surrounds(&self, x: f32) -> bool {
         self.min < x && x < self.max
     }
 
+    /// x moved into the interval
     pub fn clamp(&self, x: f32) -> f32 {
         if x < self.min {
             self.min
@@ -45,6 +56,7 @@ impl Interval {
         }
     }
 
+    /// The interval grown by delta, half at each end
     pub fn _expand(&self, delta: f32) -> Interval {
         let padding = delta / 2.0; 
         Interval { min: self.min - padding, max: self.max + padding }
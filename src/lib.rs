@@ -0,0 +1,193 @@
+//! A Monte Carlo path tracer that grew out of _Ray Tracing in One Weekend_.
+//!
+//! A render needs a [`Scene`] (the objects, usually behind a [`BvhNode`], and a [`Sky`])
+//! and a [`Camera`]. [`render`] traces it on a number of threads and returns the linear
+//! radiance as an [`Image`], which [`ToneMapping`] turns into displayable pixels:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use raytracer::{render, Camera, CameraView, Color, Hittable, HittableList, Lambertian, MaterialEnum, Point3d, Scene, Sky, Sphere, ToneMapping, Vec3d};
+//!
+//! let mut world = HittableList::default();
+//! let red = Arc::new(MaterialEnum::Lambertian(Lambertian { albedo: Color { r: 0.7, g: 0.2, b: 0.1 } }));
+//! world.add(Hittable::Sphere(Sphere::new(Point3d::new(0.0, 0.0, 0.0), 0.5, red)));
+//!
+//! let view = CameraView {
+//!     vfov: 30.0,
+//!     lookfrom: Point3d::new(0.0, 0.5, 4.0),
+//!     lookat: Point3d::new(0.0, 0.0, 0.0),
+//!     vup: Vec3d::new(0.0, 1.0, 0.0),
+//!     defocus_angle: 0.0,
+//!     focus_dist: 4.0,
+//! };
+//! let camera = Camera::initialize(16.0 / 9.0, 320, 10, 16, view);
+//! let image = render(camera, Scene { world, sky: Sky::Gradient }, 4)?;
+//! image.write_ppm("sphere.ppm", &ToneMapping::default())?;
+//! # Ok::<(), raytracer::Error>(())
+//! ```
+//!
+//! Everything the binary reads from `config.toml` can be set through [`Settings`] as well,
+//! [`RenderOptions::from_settings`] and the scene [`Generator`] they name turn them into a
+//! render.
+//! Loading, rendering and writing report failures as an [`Error`] naming the file and the
+//! setting involved instead of panicking. [`check_file`] goes further and lists settings
+//! that load but would render nothing or something broken.
+#![warn(missing_docs)]
+
+mod aabb;
+mod aov;
+#[doc(hidden)]
+pub mod bench;
+mod bhv;
+#[cfg(test)]
+mod bsdf_tests;
+mod camera;
+mod check;
+mod checkpoint;
+pub mod config;
+mod denoise;
+mod error;
+mod filter;
+mod hit_record;
+mod image;
+mod interval;
+mod material;
+mod microfacet;
+mod preview;
+mod principled;
+mod progress;
+#[cfg(test)]
+mod reference;
+mod sampler;
+mod scene;
+mod sky;
+mod spectrum;
+mod stats;
+mod texture;
+mod tonemap;
+mod vec3d;
+
+use std::{ops::{Add, Index, Mul}, sync::Arc};
+
+pub use aabb::Aabb;
+pub use aov::Pass;
+pub use bhv::BvhNode;
+pub use camera::{Camera, CameraView, Ray, RenderOptions};
+pub use check::{check_file, lint, Finding, Severity};
+pub use config::Settings;
+pub use error::{Error, Result};
+pub use hit_record::{Hit, HitRecord, Hittable, HittableList, Instance, Sphere, Triangle};
+pub use image::{write_heatmap, Image};
+pub use interval::Interval;
+pub use material::{Dielectric, Lambertian, Material, MaterialEnum, Metal, RoughConductor, RoughDielectric};
+pub use microfacet::Ggx;
+pub use preview::{preview_cells, render_preview, Cell, ContactSheet};
+pub use principled::Principled;
+pub use progress::Progress;
+pub use scene::{replace_ground_material, sea_of_balls_scene, Generator, Scene, GENERATORS};
+pub use sky::{Daylight, Sky};
+pub use stats::RenderStats;
+pub use texture::Texture;
+pub use tonemap::{Operator, ToneMapping};
+pub use vec3d::Vec3d;
+
+/// Renders the scene on the given number of threads (at least one) and returns the
+/// averaged linear radiance of every pixel. Prints nothing, use [`Camera::render`] for
+/// progress reports, checkpoints or the render passes
+pub fn render(camera: Camera, scene: Scene, threads: u8) -> Result<Image> {
+    let camera = Arc::new(camera);
+    Camera::render(camera.clone(), Arc::new(scene), threads.max(1), &mut Progress::quiet())?;
+    Ok(camera.image())
+}
+
+/// A position in the scene
+#[derive(Debug, Default, Clone)]
+pub struct Point3d(pub Vec3d);
+
+// Panics on an axis past 2 like Vec3d, use .0.get() for axes that come from data
+impl Index<usize> for Point3d {
+    type Output = f32;
+
+    fn index(&self, i:usize) -> &f32 {
+        self.0.get(i).unwrap_or_else(|| panic!("Axis {i} out of range for Point3d, should be in range 0..=2"))
+    }
+}
+
+impl Point3d {
+    /// The point x, y, z
+    pub fn new(x: f32, y: f32, z: f32) -> Point3d {
+        Point3d(Vec3d{x, y, z})
+    }
+
+    /// The point at the end of v from the origin
+    pub fn from_vec3d(v: Vec3d) -> Point3d {
+        Point3d(v)
+    }
+
+    fn _origin() -> Point3d {
+        Point3d::new(0.0, 0.0, 0.0)
+    }
+
+    /// The vector from the origin to the point
+    pub fn as_vec3d(&self) -> Vec3d {
+        Vec3d::new(self.0.x, self.0.y, self.0.z)
+    }
+
+    /// The difference of the coordinates
+    pub fn sub(&self, p: Point3d) -> Point3d {
+        Point3d::new(
+            self.0.x - p.0.x,
+            self.0.y - p.0.y,
+            self.0.z - p.0.z,
+        )
+    }
+    //fn as_vec3d(p: &Point3d) -> Vec3d {
+    //    Vec3d{x: p.0.x, y: p.0.y, z: p.0.z}
+    //}
+}
+
+/// Linear RGB radiance or reflectance
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Color {
+    /// red
+    pub r: f32,
+    /// green
+    pub g: f32,
+    /// blue
+    pub b: f32,
+}
+
+impl Add for Color {
+    type Output = Color;
+
+    fn add(self, other: Color) -> Color {
+        Color{r: self.r + other.r, g: self.g + other.g, b: self.b + other.b}
+    }
+}
+
+impl Mul for Color {
+    type Output = Color;
+
+    fn mul(self, other: Color) -> Color {
+        Color{r: self.r * other.r, g: self.g * other.g, b: self.b * other.b}
+    }
+}
+
+impl Mul<f32> for Color {
+    type Output = Color;
+
+    fn mul(self, other: f32) -> Color {
+        Color{r: self.r * other, g: self.g * other, b: self.b * other}
+    }
+}
+
+impl Color {
+    /*fn as_vec3d(c: &Color) -> Vec3d {
+        Vec3d{x: c.r, y: c.g, z: c.b}
+    }*/
+
+    /// Relative luminance of linear sRGB
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+}
@@ -1,193 +1,202 @@
-#![allow(dead_code, unused_variables, unused_imports)]
-mod config;
-mod camera;
-mod vec3d;
-mod hit_record;
-mod material;
-mod interval;
-mod aabb;
-mod bhv;
-mod scene;
-
-use std::{ops::Index, sync::Arc};
-use std::fs;
-use std::io::Write;
-
-use camera::{Camera, CameraView};
-
-use config::Settings;
-use hit_record::{HittableList, Sphere};
-use interval::Interval;
-use material::{Dielectric, Lambertian, Metal};
-use rand::Rng;
-
-use crate::aabb::Aabb;
-use crate::bhv::BvhNode;
-use crate::{hit_record::Hittable, material::MaterialEnum, vec3d::Vec3d};
-
-#[derive(Debug, Default, Clone)]
-struct Point3d(Vec3d);
-
-impl Index<usize> for Point3d {
-    type Output = f32;
-
-    fn index(&self, i:usize) -> &f32 {
-        match i {
-            0 => &self.0.x,
-            1 => &self.0.y,
-            2 => &self.0.z,
-            _ => panic!("Index out of bound for Point3d, should be in range 0..2")
+mod navigation;
+mod viewer;
+
+use std::{process::ExitCode, sync::Arc, time::Instant};
+
+use raylib::prelude::*;
+use raytracer::{bench, check_file, preview_cells, render_preview, write_heatmap, BvhNode, Camera, CameraView, Error, Finding, Generator, Hittable, HittableList, Pass, Progress, RenderOptions, Result, Scene, Settings, Severity, Sky, ToneMapping, GENERATORS};
+
+const CONFIG: &str = "config.toml";
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
         }
     }
 }
 
-impl Point3d {
-    fn new(x: f32, y: f32, z: f32) -> Point3d {
-        Point3d(Vec3d{x, y, z})
+// False if the benchmarks found a regression or the settings have errors
+fn run() -> Result<bool> {
+
+    // raytracer --bench [baseline.json [tolerance]] runs the benchmark scenes instead of rendering
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|a| a == "--bench") {
+        let tolerance = match args.get(i + 2) {
+            Some(t) => Some(t.parse().map_err(|_| Error::Invalid {
+                file: None,
+                field: "--bench tolerance".to_string(),
+                message: format!("\"{t}\" is not a number, 0.1 is 10%"),
+            })?),
+            None => None,
+        };
+        return bench::run("bench.json", args.get(i + 1).map(String::as_str), tolerance);
     }
 
-    fn from_vec3d(v: Vec3d) -> Point3d {
-        Point3d(v)
+    // raytracer check [scene.toml] lists what is wrong with the settings without rendering
+    if args.get(1).is_some_and(|a| a == "check") {
+        let path = args.get(2).map_or(CONFIG, String::as_str);
+        return Ok(report(path, &check_file(path)?, true));
     }
 
-    fn _origin() -> Point3d {
-        Point3d::new(0.0, 0.0, 0.0)
+    // raytracer scenes lists the scene generators
+    if args.get(1).is_some_and(|a| a == "scenes") {
+        GENERATORS.iter().for_each(|g| println!("{:<18}{}", g.name, g.description));
+        return Ok(true);
     }
 
-    fn clone(&self) -> Point3d {
-        Point3d(Vec3d{x: self.0.x, y: self.0.y, z: self.0.z})
+    if !report(CONFIG, &check_file(CONFIG)?, false) {
+        return Ok(false);
     }
-
-    fn as_vec3d(&self) -> Vec3d {
-        Vec3d::new(self.0.x, self.0.y, self.0.z)
-    }
-
-    fn sub(&self, p: Point3d) -> Point3d {
-        Point3d::new(
-            self.0.x - p.0.x,
-            self.0.y - p.0.y,
-            self.0.z - p.0.z,
-        )
+    let mut c = Settings::from_file(CONFIG)?;
+
+    // raytracer preview renders the materials of [preview] into a contact sheet
+    if args.get(1).is_some_and(|a| a == "preview") {
+        let path = c.preview.as_ref().and_then(|p| p.output.clone()).unwrap_or("preview.ppm".to_string());
+        let (cells, _) = preview_cells(&c)?;
+        println!("Rendering {} cells: {}", cells.len(), cells.iter().map(|cell| cell.label.as_str()).collect::<Vec<_>>().join(", "));
+        let sheet = render_preview(&c, if c.multithread_enabled { c.threads } else { 1 })?;
+        sheet.write_ppm(&path)?;
+        println!("Saved {}x{} contact sheet to {path}", sheet.width, sheet.height);
+        return Ok(true);
     }
-    //fn as_vec3d(p: &Point3d) -> Vec3d {
-    //    Vec3d{x: p.0.x, y: p.0.y, z: p.0.z}
-    //}
-}
-
-#[derive(Copy, Clone, Debug)]
-struct Color{r: f32, g: f32, b: f32}
 
-/*impl Color {
-    fn as_vec3d(c: &Color) -> Vec3d {
-        Vec3d{x: c.r, y: c.g, z: c.b}
+    // raytracer --scene <name> renders another generator than the one in the settings
+    let scene_name = match args.iter().position(|a| a == "--scene") {
+        Some(i) => Some(Generator::find(args.get(i + 1).map_or("", String::as_str))?.name.to_string()),
+        None => None,
+    };
+    if let Some(name) = &scene_name {
+        c.scene.get_or_insert_with(Default::default).generator = Some(name.clone());
     }
-}*/
-
-const INTENSITY: Interval = Interval {
-    min: 0.000,
-    max: 0.999,
-};
 
-fn linear_to_gamma(l: f32) -> f32 {
-    if l < 0.0 {
-        0.0
-    } else {
-        f32::sqrt(l)
+    // raytracer --interactive renders into a window until it is closed and picks up changes
+    // to the settings, nothing is saved
+    if args.iter().any(|a| a == "--interactive") {
+        return viewer::run(CONFIG, c, scene_name);
     }
-}
-
-fn write_color(f: &mut fs::File, c: &Color) {
-
-    // apply a linear to gamma transform for gamma 2
-    let r = linear_to_gamma(c.r);
-    let g = linear_to_gamma(c.g);
-    let b = linear_to_gamma(c.b);
-
-    // translate the [0, 1] component values to the byte (color) range [0, 255]
-    let ir = (256.0 * INTENSITY.clamp(r)) as u8;
-    let ig = (256.0 * INTENSITY.clamp(g)) as u8;
-    let ib = (256.0 * INTENSITY.clamp(b)) as u8;
-
-    writeln!(f, "{ir} {ig} {ib}").expect("Cannot write to file");
-}
-
-use raylib::prelude::*;
-
-fn main() {
-
-    let c = Settings::new().unwrap();
+    let generator = Generator::from_settings(&c)?;
 
     // World
-    let world = scene::sea_of_balls_scene(c.clone());
+    let build_start = Instant::now();
+    let mut objects = generator.generate(&c)?;
+    println!("Rendering World with {} hittable objects - using bounding box optimization", objects.len());
+    let world = Scene {
+        world: HittableList::new(Hittable::BvhNode(BvhNode::new(&mut objects))),
+        sky: Sky::new(&c.sky)?,
+    };
+    let build_time = build_start.elapsed();
 
     // Camera
-    let cv = CameraView {
-        vfov: 20.0,
-        lookfrom: Point3d::new(13.0, 2.0, 3.0),
-        lookat: Point3d::new(0.0, 0.0, 0.0),
-        vup: Vec3d::new(0.0, 1.0, 0.0),
-        defocus_angle: 0.6,
-        focus_dist: 10.0,
-    };
-    
-    let camera = Arc::new(Camera::initialize(16.0 / 9.0, c.width, c.max_depth, c.samples_per_pixel, cv));
+    let view = generator.camera(&c);
+    let aspect_ratio = view.aspect_ratio.unwrap_or(16.0 / 9.0);
+    let cv = CameraView::new(&Some(view));
+
+    let camera = Camera::initialize(aspect_ratio, c.width, c.max_depth, c.samples_per_pixel, cv)
+        .with_options(RenderOptions::from_settings(&c)?);
+    let camera = Arc::new(camera);
+
+    // a render started over would overwrite the checkpoint with its first save
+    if args.iter().any(|a| a == "--resume") {
+        if let Err(e) = camera.resume() {
+            eprintln!("Cannot resume, run without --resume to start over");
+            return Err(e);
+        }
+        println!("Resuming render from checkpoint");
+    }
 
     // Render
-    use std::time::Instant;
     let now = Instant::now();
 
     let thread_num = if c.multithread_enabled { c.threads } else { 1 };
 
     println!("Running renderer with {thread_num} threads");
-    println!("Rendering image {}x{}, depth {} and {} samples per pixel",
-        camera.image_width, camera.image_height,
-        c.max_depth, c.samples_per_pixel);
+    match &c.adaptive {
+        Some(a) => println!("Rendering image {}x{}, depth {} and {} to {} samples per pixel",
+            camera.image_width(), camera.image_height(),
+            c.max_depth, a.min_samples, a.max_samples),
+        None => println!("Rendering image {}x{}, depth {} and {} samples per pixel",
+            camera.image_width(), camera.image_height(),
+            c.max_depth, c.samples_per_pixel),
+    }
 
-    Camera::render(camera.clone(), Arc::new(world), thread_num);
+    let mut progress = Progress::new(&c.progress)?;
+    // a checkpoint that couldn't be saved doesn't spoil the image, it is reported once the
+    // image is saved
+    let checkpoint_error = match Camera::render(camera.clone(), Arc::new(world), thread_num, &mut progress) {
+        Err(e @ Error::Io { .. }) => Some(e),
+        rendered => rendered.map(|()| None)?,
+    };
+    progress.finish(&camera.stats(build_time, now.elapsed()))?;
 
-    let mut elapsed = now.elapsed();
-    println!("Calculated in: {:.2?}", elapsed);
     println!("Saving image to file...");
 
-    let mut f = fs::File::create("rendered.ppm").expect("Cannot create rendered image file");
-    writeln!(f, "P3\n{} {}\n255", camera.image_width, camera.image_height).expect("Cannot write to file");
-    
-    let pixels = camera.pixels.lock().unwrap();
+    let image = camera.image();
+    let tm = ToneMapping::new(&c.tonemap)?;
+    image.write_ppm("rendered.ppm", &tm)?;
+
+    if let Some(aov) = &c.aov {
+        let passes = Pass::from_settings(&c.aov)?;
+        let prefix = aov.prefix.as_deref().unwrap_or("rendered");
+        println!("Saving {} aov passes...", passes.len());
+        camera.write_passes(prefix, &passes)?;
+    }
+
+    if let Some(heatmap) = c.adaptive.as_ref().and_then(|a| a.heatmap.as_deref()) {
+        let counts = camera.sample_counts();
+        let total: u64 = counts.iter().map(|n| u64::from(*n)).sum();
+        println!("Average samples per pixel: {:.1}", total as f64 / counts.len().max(1) as f64);
+        write_heatmap(heatmap, image.width, image.height, &counts, c.adaptive.as_ref().map_or(1, |a| a.max_samples))?;
+    }
 
-    pixels.iter().for_each(|c| write_color(&mut f, c));
+    println!("Total elapsed: {:.2?}", build_start.elapsed());
+    if let Some(e) = checkpoint_error {
+        return Err(e);
+    }
 
-    elapsed = now.elapsed();
-    println!("Total elapsed: {:.2?}", elapsed);
-    
     let (mut rl, thread) = raylib::init()
-        .size(camera.image_width as i32, camera.image_height as i32)
+        .size(i32::from(image.width), i32::from(image.height))
         .title("Hello, World")
         .build();
 
     rl.set_target_fps(1);
 
+    let pixels = image.to_rgb8(&tm);
     while !rl.window_should_close() {
         let mut d = rl.begin_drawing(&thread);
 
         d.clear_background(raylib::color::Color::BLACK);
         //d.draw_text("Hello, world!", 12, 12, 20, raylib::color::Color::BLACK);
-        pixels.iter().enumerate().for_each(|(i, c)| {
-            // apply a linear to gamma transform for gamma 2
-            let r = linear_to_gamma(c.r);
-            let g = linear_to_gamma(c.g);
-            let b = linear_to_gamma(c.b);
-
-            // translate the [0, 1] component values to the byte (color) range [0, 255]
-            let ir = (256.0 * INTENSITY.clamp(r)) as u8;
-            let ig = (256.0 * INTENSITY.clamp(g)) as u8;
-            let ib = (256.0 * INTENSITY.clamp(b)) as u8;
-
-            let x = i as i32 / camera.image_width as i32;
-            let y = i as i32 % camera.image_width as i32;
+        pixels.iter().enumerate().for_each(|(i, [ir, ig, ib])| {
+            let x = i as i32 / i32::from(image.width);
+            let y = i as i32 % i32::from(image.width);
 
-            d.draw_pixel(y, x, raylib::color::Color::new(ir, ig, ib, 255));
+            d.draw_pixel(y, x, raylib::color::Color::new(*ir, *ig, *ib, 255));
        });
     }
+    Ok(true)
 }
 
-
+// Prints the findings as file:line: severity: field: message, warnings only when verbose.
+// False if there are errors
+fn report(path: &str, findings: &[Finding], verbose: bool) -> bool {
+    let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+    let warnings = findings.len() - errors;
+    for f in findings.iter().filter(|f| verbose || f.severity == Severity::Error) {
+        match f.line {
+            Some(line) => eprintln!("{path}:{line}: {f}"),
+            None => eprintln!("{path}: {f}"),
+        }
+    }
+    if verbose {
+        println!("{path}: {errors} errors, {warnings} warnings");
+    } else if errors > 0 {
+        eprintln!("{path}: {errors} errors, not rendering");
+    } else if warnings > 0 {
+        println!("{path}: {warnings} warnings, raytracer check {path} lists them");
+    }
+    errors == 0
+}
@@ -1,12 +1,39 @@
-use rand::Rng;
+use core::f32;
+use std::sync::Arc;
 
-use crate::{camera::Ray, hit_record::HitRecord, vec3d::Vec3d, Color};
+use crate::{camera::Ray, hit_record::HitRecord, microfacet::{fresnel_conductor, fresnel_dielectric, Frame, Ggx}, principled::Principled, sampler, spectrum::Dispersion, vec3d::Vec3d, Color};
 
+const BLACK_COLOR: Color = Color{r: 0.0, g: 0.0, b: 0.0};
+const WHITE_COLOR: Color = Color{r: 1.0, g: 1.0, b: 1.0};
+
+/// How light scatters off a surface. Implement it for your own BSDFs and use them as
+/// [`MaterialEnum::Custom`]
 pub trait Material: Send + Sync {
+    /// The scattered ray, its throughput (BSDF times cosine over the pdf) and false if the
+    /// ray is absorbed
     fn scatter(&self, ray_in: &Ray, hr: &HitRecord) -> (Ray, Color, bool);
+
+    /// BSDF times cosine for the given scattered direction, used to sample lights directly.
+    /// Materials that return None are never light sampled and see lights only by scattering
+    fn eval(&self, _ray_in: &Ray, _hr: &HitRecord, _direction: &Vec3d) -> Option<Color> {
+        None
+    }
+
+    /// Density (per solid angle) of scatter() choosing the given direction, 0 for materials
+    /// scattering into discrete directions only
+    fn pdf(&self, _ray_in: &Ray, _hr: &HitRecord, _direction: &Vec3d) -> f32 {
+        0.0
+    }
+
+    /// Overall reflectance color of the surface, written to the albedo pass
+    fn albedo(&self, _hr: &HitRecord) -> Color {
+        WHITE_COLOR
+    }
 }
 
+/// Ideal diffuse reflection
 pub struct Lambertian {
+    /// reflected fraction of every channel
     pub albedo: Color,
 }
 
@@ -21,10 +48,25 @@ impl Material for Lambertian {
         (scattered, attenuation, true)
     }
 
+    fn eval(&self, _ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> Option<Color> {
+        let cosine = Vec3d::dot(&hr.normal, &Vec3d::unit(direction)).max(0.0);
+        Some(self.albedo * (cosine / f32::consts::PI))
+    }
+
+    fn pdf(&self, _ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> f32 {
+        Vec3d::dot(&hr.normal, &Vec3d::unit(direction)).max(0.0) / f32::consts::PI
+    }
+
+    fn albedo(&self, _hr: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
+/// A mirror, blurred by a random offset of the reflected direction
 pub struct Metal {
+    /// reflected fraction of every channel
     pub albedo: Color,
+    /// radius of the offset, 0 is a perfect mirror
     pub fuzz: f32,
 }
 
@@ -38,15 +80,29 @@ impl Material for Metal {
         (scattered, attenuation, is_scattered)
     }
 
+
+    fn albedo(&self, _hr: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
+/// Smooth glass, optionally colored and dispersive
 pub struct Dielectric {
-    // Refractive index in vacuum or air, or the ratio of the material's refractive index over
-    // the refractive index of the enclosing media
-    pub refraction_index: f32
+    /// Refractive index in vacuum or air, or the ratio of the material's refractive index over
+    /// the refractive index of the enclosing media
+    pub refraction_index: f32,
+    /// Beer-Lambert absorption coefficient per unit of length travelled inside the medium
+    pub absorption: Color,
+    // Wavelength dependent index used instead of refraction_index in the spectral mode
+    pub(crate) dispersion: Option<Dispersion>,
 }
 
 impl Dielectric {
+    /// Clear glass without dispersion
+    pub fn new(refraction_index: f32) -> Dielectric {
+        Dielectric{refraction_index, absorption: BLACK_COLOR, dispersion: None}
+    }
+
     fn reflectance(&self, cosine: f32, refraction_index: f32) -> f32 {
         let r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
         let r0 = r0 * r0;
@@ -56,7 +112,11 @@ impl Dielectric {
 
 impl Material for Dielectric {
     fn scatter(&self, ray_in: &Ray, hr: &HitRecord) -> (Ray, Color, bool) {
-        let ri = if hr.front_face { 1.0 / self.refraction_index } else { self.refraction_index };
+        let refraction_index = match (&self.dispersion, ray_in.wavelength) {
+            (Some(dispersion), Some(lambda)) => dispersion.ior(lambda),
+            _ => self.refraction_index,
+        };
+        let ri = if hr.front_face { 1.0 / refraction_index } else { refraction_index };
         let unit_dir = Vec3d::unit(&ray_in.direction);
 
         let cos_theta = f32::min(Vec3d::dot(&Vec3d::mul(&unit_dir, -1.0), &hr.normal), 1.0);
@@ -64,22 +124,195 @@ impl Material for Dielectric {
 
         let cannot_refract = ri * sin_theta > 1.0;
 
-        let cannot_refract = cannot_refract || (self.reflectance(cos_theta, ri) > rand::rng().random_range(0.0..1.0));
+        let cannot_refract = cannot_refract || (self.reflectance(cos_theta, ri) > sampler::random());
 
         let direction = if cannot_refract {Vec3d::reflect(&unit_dir, &hr.normal)} else { Vec3d::refract(unit_dir, hr.normal.clone(), ri) };
 
         let scattered = Ray::new(hr.point.clone(), direction, Some(ray_in.tm));
-        let attenuation = Color{r: 1.0, g: 1.0, b: 1.0};
+        let attenuation = medium_transmittance(self.absorption, ray_in, hr);
         let is_scattered = true; 
         (scattered, attenuation, is_scattered)
     }
 
 }
 
+/// Absorption coefficient giving the transmittance color after travelling the distance,
+/// which has to be positive
+pub fn absorption_from_transmittance(transmittance: Color, distance: f32) -> Color {
+    let channel = |t: f32| if t <= 0.0 { f32::INFINITY } else { -f32::ln(t.min(1.0)) / distance };
+    Color{r: channel(transmittance.r), g: channel(transmittance.g), b: channel(transmittance.b)}
+}
+
+// A ray hitting the back face of a dielectric travelled inside it since its last scattering,
+// so it's attenuated by the medium along that path (Beer-Lambert law)
+fn medium_transmittance(absorption: Color, ray_in: &Ray, hr: &HitRecord) -> Color {
+    if hr.front_face {
+        return WHITE_COLOR;
+    }
+    let distance = hr.t * ray_in.direction.length();
+    Color{
+        r: f32::exp(-absorption.r * distance),
+        g: f32::exp(-absorption.g * distance),
+        b: f32::exp(-absorption.b * distance),
+    }
+}
+
+/// Metal with GGX microfacet roughness and the Fresnel reflectance of a complex index of refraction
+pub struct RoughConductor {
+    /// real part of the index of refraction per channel
+    pub eta: Color,
+    /// imaginary part (extinction) of the index of refraction per channel
+    pub k: Color,
+    /// the microfacets
+    pub distribution: Ggx,
+}
+
+impl Material for RoughConductor {
+    fn scatter(&self, ray_in: &Ray, hr: &HitRecord) -> (Ray, Color, bool) {
+        let frame = Frame::from_normal(&hr.normal);
+        let wo = frame.to_local(&Vec3d::mul(&Vec3d::unit(&ray_in.direction), -1.0));
+
+        let h = self.distribution.sample_visible(&wo);
+        let wi = Vec3d::reflect(&Vec3d::mul(&wo, -1.0), &h);
+
+        let scattered = Ray::new(hr.point.clone(), frame.to_world(&wi), Some(ray_in.tm));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return (scattered, BLACK_COLOR, false);
+        }
+
+        let f = fresnel_conductor(Vec3d::dot(&wo, &h), self.eta, self.k);
+        let attenuation = f * (self.distribution.g2(&wo, &wi) / self.distribution.g1(&wo));
+        (scattered, attenuation, true)
+    }
+
+    fn eval(&self, ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> Option<Color> {
+        let frame = Frame::from_normal(&hr.normal);
+        let wo = frame.to_local(&Vec3d::mul(&Vec3d::unit(&ray_in.direction), -1.0));
+        let wi = frame.to_local(&Vec3d::unit(direction));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Some(BLACK_COLOR);
+        }
+
+        let h = Vec3d::unit(&(wo.clone() + wi.clone()));
+        let f = fresnel_conductor(Vec3d::dot(&wo, &h), self.eta, self.k);
+        let d = self.distribution.d(&h);
+        let g = self.distribution.g2(&wo, &wi);
+        Some(f * (d * g / (4.0 * wo.z)))
+    }
+
+    fn pdf(&self, ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> f32 {
+        let frame = Frame::from_normal(&hr.normal);
+        let wo = frame.to_local(&Vec3d::mul(&Vec3d::unit(&ray_in.direction), -1.0));
+        let wi = frame.to_local(&Vec3d::unit(direction));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        let h = Vec3d::unit(&(wo.clone() + wi));
+        self.distribution.pdf_visible(&wo, &h) / (4.0 * Vec3d::dot(&wo, &h))
+    }
+
+    fn albedo(&self, _hr: &HitRecord) -> Color {
+        fresnel_conductor(1.0, self.eta, self.k)
+    }
+}
+
+/// Glass with GGX microfacet roughness (frosted glass), Walter et al. 2007
+pub struct RoughDielectric {
+    /// refractive index relative to the outside
+    pub refraction_index: f32,
+    /// the microfacets
+    pub distribution: Ggx,
+    /// Beer-Lambert absorption coefficient per unit of length travelled inside
+    pub absorption: Color,
+}
+
+impl RoughDielectric {
+    // ratio of the index on the far side of the surface over the index on the ray side
+    fn eta(&self, hr: &HitRecord) -> f32 {
+        if hr.front_face { self.refraction_index } else { 1.0 / self.refraction_index }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, ray_in: &Ray, hr: &HitRecord) -> (Ray, Color, bool) {
+        let eta = self.eta(hr);
+        let frame = Frame::from_normal(&hr.normal);
+        let wo = frame.to_local(&Vec3d::mul(&Vec3d::unit(&ray_in.direction), -1.0));
+
+        let h = self.distribution.sample_visible(&wo);
+        let cos_i = Vec3d::dot(&wo, &h);
+        let f = fresnel_dielectric(cos_i, eta);
+
+        let wi = if f > sampler::random() {
+            Vec3d::reflect(&Vec3d::mul(&wo, -1.0), &h)
+        } else {
+            let cos_t = f32::sqrt(f32::max(0.0, 1.0 - (1.0 - cos_i * cos_i) / (eta * eta)));
+            Vec3d::mul(&wo, -1.0 / eta) + Vec3d::mul(&h, cos_i / eta - cos_t)
+        };
+
+        let scattered = Ray::new(hr.point.clone(), frame.to_world(&wi), Some(ray_in.tm));
+        // a reflection has to stay above the surface and a refraction has to go below it
+        let reflected = Vec3d::dot(&wi, &h) > 0.0;
+        if wo.z <= 0.0 || reflected != (wi.z > 0.0) {
+            return (scattered, BLACK_COLOR, false);
+        }
+
+        let attenuation = medium_transmittance(self.absorption, ray_in, hr) * (self.distribution.g2(&wo, &wi) / self.distribution.g1(&wo));
+        (scattered, attenuation, true)
+    }
+
+    fn pdf(&self, ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> f32 {
+        let eta = self.eta(hr);
+        let frame = Frame::from_normal(&hr.normal);
+        let wo = frame.to_local(&Vec3d::mul(&Vec3d::unit(&ray_in.direction), -1.0));
+        let wi = frame.to_local(&Vec3d::unit(direction));
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        if wi.z > 0.0 {
+            let h = Vec3d::unit(&(wo.clone() + wi));
+            let f = fresnel_dielectric(Vec3d::dot(&wo, &h), eta);
+            return f * self.distribution.pdf_visible(&wo, &h) / (4.0 * Vec3d::dot(&wo, &h));
+        }
+
+        let h = Vec3d::unit(&(wo.clone() + Vec3d::mul(&wi, eta)));
+        let h = if h.z < 0.0 { Vec3d::mul(&h, -1.0) } else { h };
+        let wo_h = Vec3d::dot(&wo, &h);
+        let wi_h = Vec3d::dot(&wi, &h);
+        if wo_h <= 0.0 || wi_h >= 0.0 {
+            return 0.0;
+        }
+
+        let f = fresnel_dielectric(wo_h, eta);
+        let denom = wo_h + eta * wi_h;
+        (1.0 - f) * self.distribution.pdf_visible(&wo, &h) * eta * eta * wi_h.abs() / (denom * denom)
+    }
+}
+
+/// Every material an object can have
 pub enum MaterialEnum {
+    /// see [`Lambertian`]
     Lambertian(Lambertian),
+    /// see [`Metal`]
     Metal(Metal),
+    /// see [`Dielectric`]
     Dielectric(Dielectric),
+    /// see [`RoughConductor`]
+    RoughConductor(RoughConductor),
+    /// see [`RoughDielectric`]
+    RoughDielectric(RoughDielectric),
+    /// see [`Principled`]
+    Principled(Box<Principled>),
+    /// a user defined material
+    Custom(Arc<dyn Material>),
+}
+
+impl MaterialEnum {
+    pub(crate) fn is_dispersive(&self) -> bool {
+        matches!(self, MaterialEnum::Dielectric(Dielectric{dispersion: Some(_), ..}))
+    }
 }
 
 impl Material for MaterialEnum {
@@ -88,7 +321,46 @@ impl Material for MaterialEnum {
             MaterialEnum::Lambertian(lambertian) => lambertian.scatter(ray_in, hr),
             MaterialEnum::Metal(metal) => metal.scatter(ray_in, hr),
             MaterialEnum::Dielectric(dielectric) => dielectric.scatter(ray_in, hr),
+            MaterialEnum::RoughConductor(conductor) => conductor.scatter(ray_in, hr),
+            MaterialEnum::RoughDielectric(dielectric) => dielectric.scatter(ray_in, hr),
+            MaterialEnum::Principled(principled) => principled.scatter(ray_in, hr),
+            MaterialEnum::Custom(custom) => custom.scatter(ray_in, hr),
+        }
+    }
+
+    fn eval(&self, ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> Option<Color> {
+        match self {
+            MaterialEnum::Lambertian(lambertian) => lambertian.eval(ray_in, hr, direction),
+            MaterialEnum::Metal(metal) => metal.eval(ray_in, hr, direction),
+            MaterialEnum::Dielectric(dielectric) => dielectric.eval(ray_in, hr, direction),
+            MaterialEnum::RoughConductor(conductor) => conductor.eval(ray_in, hr, direction),
+            MaterialEnum::RoughDielectric(dielectric) => dielectric.eval(ray_in, hr, direction),
+            MaterialEnum::Principled(principled) => principled.eval(ray_in, hr, direction),
+            MaterialEnum::Custom(custom) => custom.eval(ray_in, hr, direction),
+        }
+    }
+
+    fn pdf(&self, ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> f32 {
+        match self {
+            MaterialEnum::Lambertian(lambertian) => lambertian.pdf(ray_in, hr, direction),
+            MaterialEnum::Metal(metal) => metal.pdf(ray_in, hr, direction),
+            MaterialEnum::Dielectric(dielectric) => dielectric.pdf(ray_in, hr, direction),
+            MaterialEnum::RoughConductor(conductor) => conductor.pdf(ray_in, hr, direction),
+            MaterialEnum::RoughDielectric(dielectric) => dielectric.pdf(ray_in, hr, direction),
+            MaterialEnum::Principled(principled) => principled.pdf(ray_in, hr, direction),
+            MaterialEnum::Custom(custom) => custom.pdf(ray_in, hr, direction),
         }
     }
-}
 
+    fn albedo(&self, hr: &HitRecord) -> Color {
+        match self {
+            MaterialEnum::Lambertian(lambertian) => lambertian.albedo(hr),
+            MaterialEnum::Metal(metal) => metal.albedo(hr),
+            MaterialEnum::Dielectric(dielectric) => dielectric.albedo(hr),
+            MaterialEnum::RoughConductor(conductor) => conductor.albedo(hr),
+            MaterialEnum::RoughDielectric(dielectric) => dielectric.albedo(hr),
+            MaterialEnum::Principled(principled) => principled.albedo(hr),
+            MaterialEnum::Custom(custom) => custom.albedo(hr),
+        }
+    }
+}
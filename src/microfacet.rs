@@ -0,0 +1,207 @@
+use core::f32;
+
+use crate::{sampler, vec3d::Vec3d, Color};
+
+// Smallest alpha used, a perfectly smooth surface would make the distribution a delta function
+const MIN_ALPHA: f32 = 1e-3;
+
+// Local shading frame, z is the surface normal and x follows the tangent
+pub(crate) struct Frame {
+    t: Vec3d,
+    b: Vec3d,
+    n: Vec3d,
+}
+
+impl Frame {
+    // Tangent runs along the "latitude" around the world y axis, so the anisotropic
+    // highlights on spheres are continuous (like a metal brushed on a lathe)
+    pub(crate) fn from_normal(n: &Vec3d) -> Frame {
+        let c = Vec3d::cross(&Vec3d::new(0.0, 1.0, 0.0), n);
+        let (t, b) = if c.length_squared() > 1e-6 {
+            let t = Vec3d::unit(&c);
+            let b = Vec3d::cross(n, &t);
+            (t, b)
+        } else {
+            Vec3d::basis(n)
+        };
+        Frame { t, b, n: n.clone() }
+    }
+
+    pub(crate) fn to_local(&self, v: &Vec3d) -> Vec3d {
+        Vec3d::new(Vec3d::dot(v, &self.t), Vec3d::dot(v, &self.b), Vec3d::dot(v, &self.n))
+    }
+
+    pub(crate) fn to_world(&self, v: &Vec3d) -> Vec3d {
+        Vec3d::mul(&self.t, v.x) + Vec3d::mul(&self.b, v.y) + Vec3d::mul(&self.n, v.z)
+    }
+}
+
+/// Trowbridge-Reitz (GGX) distribution of microfacet normals with anisotropic roughness,
+/// sampled through the distribution of visible normals (Heitz 2018)
+#[derive(Clone, Debug)]
+pub struct Ggx {
+    /// roughness along the tangent
+    pub alpha_x: f32,
+    /// roughness along the bitangent
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    /// Perceptual roughness in [0, 1] is squared, anisotropy in [-1, 1] stretches the
+    /// highlight along the tangent (positive) or the bitangent (negative)
+    pub fn new(roughness: f32, anisotropy: f32) -> Ggx {
+        let r = roughness.clamp(0.0, 1.0);
+        let aspect = f32::sqrt(1.0 - 0.9 * anisotropy.clamp(-1.0, 1.0).abs());
+        let (ax, ay) = if anisotropy >= 0.0 {
+            (r * r / aspect, r * r * aspect)
+        } else {
+            (r * r * aspect, r * r / aspect)
+        };
+        Ggx {
+            alpha_x: ax.max(MIN_ALPHA),
+            alpha_y: ay.max(MIN_ALPHA),
+        }
+    }
+
+    pub(crate) fn d(&self, h: &Vec3d) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let e = (h.x * h.x) / (self.alpha_x * self.alpha_x)
+            + (h.y * h.y) / (self.alpha_y * self.alpha_y)
+            + h.z * h.z;
+        1.0 / (f32::consts::PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    pub(crate) fn lambda(&self, w: &Vec3d) -> f32 {
+        if w.z == 0.0 {
+            return f32::INFINITY;
+        }
+        let a2 = (self.alpha_x * self.alpha_x * w.x * w.x + self.alpha_y * self.alpha_y * w.y * w.y) / (w.z * w.z);
+        (-1.0 + f32::sqrt(1.0 + a2)) / 2.0
+    }
+
+    pub(crate) fn g1(&self, w: &Vec3d) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub(crate) fn g2(&self, wo: &Vec3d, wi: &Vec3d) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the visible normal h seen from wo (wo.z must be positive)
+    pub(crate) fn pdf_visible(&self, wo: &Vec3d, h: &Vec3d) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * f32::max(0.0, Vec3d::dot(wo, h)) * self.d(h) / wo.z
+    }
+
+    pub(crate) fn sample_visible(&self, wo: &Vec3d) -> Vec3d {
+        let (u1, u2) = sampler::random_2d();
+
+        // stretch the view vector so the distribution becomes the isotropic unit one
+        let vh = Vec3d::unit(&Vec3d::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z));
+
+        let lensq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if lensq > 0.0 {
+            Vec3d::new(-vh.y, vh.x, 0.0) / f32::sqrt(lensq)
+        } else {
+            Vec3d::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3d::cross(&vh, &t1);
+
+        let r = f32::sqrt(u1);
+        let phi = 2.0 * f32::consts::PI * u2;
+        let p1 = r * f32::cos(phi);
+        let p2 = r * f32::sin(phi);
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * f32::sqrt(1.0 - p1 * p1) + s * p2;
+
+        let nh = t1 * p1 + t2 * p2 + vh * f32::sqrt(f32::max(0.0, 1.0 - p1 * p1 - p2 * p2));
+
+        Vec3d::unit(&Vec3d::new(self.alpha_x * nh.x, self.alpha_y * nh.y, f32::max(1e-6, nh.z)))
+    }
+}
+
+// Fresnel reflectance of a dielectric interface, eta is the ratio of the transmitted side
+// index over the incident side index
+pub(crate) fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+// Fresnel reflectance of a conductor with the complex index of refraction eta + i*k per channel
+pub(crate) fn fresnel_conductor(cos_i: f32, eta: Color, k: Color) -> Color {
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_i * cos_i;
+        let sin2 = 1.0 - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2b2 = f32::sqrt(t0 * t0 + 4.0 * eta * eta * k * k);
+        let t1 = a2b2 + cos2;
+        let a = f32::sqrt(f32::max(0.0, 0.5 * (a2b2 + t0)));
+        let t2 = 2.0 * cos_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rs + rp)
+    };
+    Color {
+        r: channel(eta.r, k.r),
+        g: channel(eta.g, k.g),
+        b: channel(eta.b, k.b),
+    }
+}
+
+pub(crate) const CONDUCTOR_PRESETS: &[&str] = &["gold", "copper", "aluminum", "silver"];
+
+// Complex index of refraction (eta, k) of common metals sampled at the r, g, b wavelengths
+pub(crate) fn conductor_preset(name: &str) -> Option<(Color, Color)> {
+    match name {
+        "gold" => Some((Color{r: 0.143, g: 0.374, b: 1.442}, Color{r: 3.983, g: 2.385, b: 1.603})),
+        "copper" => Some((Color{r: 0.200, g: 0.924, b: 1.102}, Color{r: 3.912, g: 2.452, b: 2.142})),
+        "aluminum" => Some((Color{r: 1.657, g: 0.880, b: 0.521}, Color{r: 9.224, g: 6.270, b: 4.837})),
+        "silver" => Some((Color{r: 0.155, g: 0.117, b: 0.138}, Color{r: 4.828, g: 3.122, b: 2.147})),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::microfacet::{conductor_preset, fresnel_conductor, fresnel_dielectric, Ggx};
+    use crate::vec3d::Vec3d;
+
+    #[test]
+    fn dielectric_fresnel_limits() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-4);
+        assert_eq!(fresnel_dielectric(0.1, 1.0 / 1.5), 1.0);
+    }
+
+    #[test]
+    fn gold_reflects_more_red_than_blue() {
+        let (eta, k) = conductor_preset("gold").unwrap();
+        let f = fresnel_conductor(1.0, eta, k);
+        assert!(f.r > f.b);
+        assert!(f.r < 1.0);
+    }
+
+    #[test]
+    fn visible_normals_face_the_viewer() {
+        let ggx = Ggx::new(0.5, 0.5);
+        let wo = Vec3d::unit(&Vec3d::new(0.3, -0.2, 0.8));
+        for _ in 0 .. 100 {
+            let h = ggx.sample_visible(&wo);
+            assert!(h.z > 0.0);
+            assert!(Vec3d::dot(&wo, &h) >= 0.0);
+            assert!(ggx.pdf_visible(&wo, &h) >= 0.0);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use raylib::prelude::*;
+use raytracer::{CameraView, Point3d, Vec3d};
+
+// Radians of orbit per pixel the mouse moves
+const ORBIT_SPEED: f32 = 0.01;
+// Part of the distance to lookat flown per second, shift flies faster
+const FLY_SPEED: f32 = 0.5;
+const FAST: f32 = 4.0;
+
+pub(crate) const HELP: &str = "\
+left drag: orbit around lookat     right or middle drag: pan     wheel: zoom
+W A S D: fly, Q E: down and up, shift: faster
+[ ]: field of view     - =: aperture (defocus angle)     , .: focus distance     F: focus on lookat
+P: print the camera and save it to camera.toml     R: back to the camera of the settings";
+
+/// Moves the view by the mouse and the keys of the last frame, false if it didn't move.
+/// `height` is the window height in pixels, a pan moves what is at lookat with the mouse
+pub(crate) fn navigate(rl: &RaylibHandle, v: &mut CameraView, height: i32) -> bool {
+    let offset = v.lookfrom.as_vec3d() - v.lookat.as_vec3d();
+    let distance = offset.length();
+    let forward = Vec3d::unit(&(offset.clone() * -1.0));
+    let right = Vec3d::unit(&Vec3d::cross(&forward, &v.vup));
+    let up = Vec3d::cross(&right, &forward);
+    let mouse = rl.get_mouse_delta();
+    let moved = mouse.x != 0.0 || mouse.y != 0.0;
+    let mut changed = false;
+
+    if moved && rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT) {
+        let axis = Vec3d::unit(&v.vup);
+        let mut offset = rotate(&offset, &axis, -mouse.x * ORBIT_SPEED);
+        // no pitch over the pole, vup and the view direction can't be parallel
+        let pitched = rotate(&offset, &Vec3d::unit(&Vec3d::cross(&axis, &offset)), mouse.y * ORBIT_SPEED);
+        if Vec3d::dot(&Vec3d::unit(&pitched), &axis).abs() < 0.99 {
+            offset = pitched;
+        }
+        v.lookfrom = Point3d::from_vec3d(v.lookat.as_vec3d() + offset);
+        changed = true;
+    } else if moved && (rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_RIGHT) || rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_MIDDLE)) {
+        let per_pixel = 2.0 * distance * (v.vfov.to_radians() / 2.0).tan() / height.max(1) as f32;
+        let shift = (right.clone() * -mouse.x + up.clone() * mouse.y) * per_pixel;
+        v.lookfrom = Point3d::from_vec3d(v.lookfrom.as_vec3d() + shift.clone());
+        v.lookat = Point3d::from_vec3d(v.lookat.as_vec3d() + shift);
+        changed = true;
+    }
+
+    // zooming moves lookfrom, the focus distance goes along so lookat stays sharp
+    let wheel = rl.get_mouse_wheel_move();
+    if wheel != 0.0 {
+        let scale = 0.9f32.powf(wheel);
+        if distance * scale > 1e-3 {
+            v.lookfrom = Point3d::from_vec3d(v.lookat.as_vec3d() + offset * scale);
+            v.focus_dist *= scale;
+            changed = true;
+        }
+    }
+
+    let keys = [
+        (KeyboardKey::KEY_W, forward.clone()),
+        (KeyboardKey::KEY_S, forward * -1.0),
+        (KeyboardKey::KEY_D, right.clone()),
+        (KeyboardKey::KEY_A, right * -1.0),
+        (KeyboardKey::KEY_E, up.clone()),
+        (KeyboardKey::KEY_Q, up * -1.0),
+    ];
+    let speed = distance * FLY_SPEED * rl.get_frame_time() * if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) { FAST } else { 1.0 };
+    for (key, direction) in keys {
+        if rl.is_key_down(key) {
+            v.lookfrom = Point3d::from_vec3d(v.lookfrom.as_vec3d() + direction.clone() * speed);
+            v.lookat = Point3d::from_vec3d(v.lookat.as_vec3d() + direction * speed);
+            changed = true;
+        }
+    }
+
+    let pressed = |key| rl.is_key_pressed(key);
+    if pressed(KeyboardKey::KEY_LEFT_BRACKET) || pressed(KeyboardKey::KEY_RIGHT_BRACKET) {
+        let step = if pressed(KeyboardKey::KEY_LEFT_BRACKET) { -2.0 } else { 2.0 };
+        v.vfov = (v.vfov + step).clamp(1.0, 170.0);
+        changed = true;
+    }
+    if pressed(KeyboardKey::KEY_MINUS) || pressed(KeyboardKey::KEY_EQUAL) {
+        let step = if pressed(KeyboardKey::KEY_MINUS) { -0.1 } else { 0.1 };
+        v.defocus_angle = (v.defocus_angle + step).max(0.0);
+        changed = true;
+    }
+    if pressed(KeyboardKey::KEY_COMMA) || pressed(KeyboardKey::KEY_PERIOD) {
+        v.focus_dist *= if pressed(KeyboardKey::KEY_COMMA) { 0.95 } else { 1.05 };
+        changed = true;
+    }
+    if pressed(KeyboardKey::KEY_F) {
+        v.focus_dist = (v.lookfrom.as_vec3d() - v.lookat.as_vec3d()).length();
+        changed = true;
+    }
+    changed
+}
+
+// Rodrigues' rotation of `v` by `angle` radians around the unit vector `axis`
+fn rotate(v: &Vec3d, axis: &Vec3d, angle: f32) -> Vec3d {
+    let (sin, cos) = angle.sin_cos();
+    v.clone() * cos + Vec3d::cross(axis, v) * sin + axis.clone() * (Vec3d::dot(axis, v) * (1.0 - cos))
+}
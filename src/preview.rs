@@ -0,0 +1,295 @@
+// Material previews: each material of the [preview] settings on a ball over a neutral
+// checker floor under the same sky, one cell per value of a swept parameter, put together
+// into one contact sheet with a label under every cell
+use std::sync::Arc;
+
+use crate::{camera::{Camera, CameraView, RenderOptions}, config::{Ground, Settings, TextureParam}, error::{unknown, Error, Result}, hit_record::{Hittable, HittableList, Sphere}, image, material::MaterialEnum, principled::Principled, render, scene::{self, Scene, MATERIALS}, sky::Sky, texture::Texture, tonemap::ToneMapping, vec3d::Vec3d, Color, Point3d};
+
+const SIZE: u16 = 160;
+const COLUMNS: u8 = 5;
+const SAMPLES: u16 = 64;
+const MAX_DEPTH: u8 = 16;
+
+// The parameters that can be swept and their usual range, by material
+const PARAMETERS: &[(&str, &str, f32, f32)] = &[
+    ("metal", "fuzz", 0.0, 1.0),
+    ("dielectric", "refraction", 1.0, 2.4),
+    ("conductor", "roughness", 0.0, 1.0),
+    ("conductor", "anisotropy", -1.0, 1.0),
+    ("rough_dielectric", "refraction", 1.0, 2.4),
+    ("rough_dielectric", "roughness", 0.0, 1.0),
+    ("rough_dielectric", "anisotropy", -1.0, 1.0),
+    ("principled", "metallic", 0.0, 1.0),
+    ("principled", "roughness", 0.0, 1.0),
+    ("principled", "specular", 0.0, 1.0),
+    ("principled", "clearcoat", 0.0, 1.0),
+    ("principled", "sheen", 0.0, 1.0),
+    ("principled", "transmission", 0.0, 1.0),
+    ("principled", "ior", 1.0, 2.4),
+];
+
+/// A material of a contact sheet with its label
+pub struct Cell {
+    /// the material name and the parameter value, if there is one
+    pub label: String,
+    /// the material of the ball
+    pub material: Arc<MaterialEnum>,
+}
+
+/// 8 bit sRGB pixels of the cells and their labels, row by row from the top left
+pub struct ContactSheet {
+    /// width in pixels
+    pub width: u16,
+    /// height in pixels
+    pub height: u16,
+    /// width * height pixels
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl ContactSheet {
+    /// Writes the sheet as a binary ppm
+    pub fn write_ppm(&self, path: &str) -> Result<()> {
+        image::write_ppm(path, self.width, self.height, self.pixels.iter().copied()).map_err(|e| Error::io(path, e))
+    }
+}
+
+/// The cells of the `[preview]` settings row by row, and the number of columns. With a
+/// parameter every material is a row with the parameter going from `from` to `to` over the
+/// columns, without one the materials just fill the rows. The materials are defined by
+/// their `[ground.<name>]` sections
+pub fn preview_cells(c: &Settings) -> Result<(Vec<Cell>, usize)> {
+    let p = c.preview.as_ref().ok_or_else(|| Error::invalid("preview", "needs a [preview] section naming the materials"))?;
+    if p.materials.is_empty() {
+        return Err(Error::invalid("preview.materials", "names no material"));
+    }
+    let columns = usize::from(p.columns.unwrap_or(COLUMNS).max(1));
+
+    let mut cells = vec![];
+    for name in &p.materials {
+        if !MATERIALS.contains(&name.as_str()) {
+            return Err(unknown("preview.materials", "material", name, MATERIALS));
+        }
+        let mut g = Ground { material: name.clone(), ..c.ground.clone() };
+        let Some(parameter) = &p.parameter else {
+            cells.push(Cell { label: name.clone(), material: scene::ground_material(&g)? });
+            continue;
+        };
+
+        let (_, _, from, to) = PARAMETERS.iter().find(|(m, n, _, _)| m == name && n == parameter).ok_or_else(|| {
+            let known: Vec<&str> = PARAMETERS.iter().filter(|(m, ..)| m == name).map(|(_, n, ..)| *n).collect();
+            match known.as_slice() {
+                [] => Error::invalid("preview.parameter", format!("{name} has no parameter to sweep")),
+                known => unknown("preview.parameter", &format!("{name} parameter"), parameter, known),
+            }
+        })?;
+        let (from, to) = (p.from.unwrap_or(*from), p.to.unwrap_or(*to));
+        for i in 0 .. columns {
+            let v = from + (to - from) * i as f32 / (columns - 1).max(1) as f32;
+            set_parameter(&mut g, parameter, v);
+            cells.push(Cell { label: format!("{name} {parameter} {v:.2}"), material: scene::ground_material(&g)? });
+        }
+    }
+    let columns = if p.parameter.is_some() { columns } else { columns.min(cells.len()) };
+    Ok((cells, columns))
+}
+
+// The names are those of PARAMETERS, the material sections are checked when the material
+// is made
+fn set_parameter(g: &mut Ground, parameter: &str, v: f32) {
+    let scalar = || Some(TextureParam::Scalar(v));
+    match (g.material.as_str(), parameter) {
+        ("metal", "fuzz") => g.metal.iter_mut().for_each(|m| m.fuzz = v),
+        ("dielectric", "refraction") => g.dielectric.iter_mut().for_each(|d| d.refraction = v),
+        ("conductor", "roughness") => g.conductor.iter_mut().for_each(|m| m.roughness = v),
+        ("conductor", "anisotropy") => g.conductor.iter_mut().for_each(|m| m.anisotropy = Some(v)),
+        ("rough_dielectric", "refraction") => g.rough_dielectric.iter_mut().for_each(|d| d.refraction = v),
+        ("rough_dielectric", "roughness") => g.rough_dielectric.iter_mut().for_each(|d| d.roughness = v),
+        ("rough_dielectric", "anisotropy") => g.rough_dielectric.iter_mut().for_each(|d| d.anisotropy = Some(v)),
+        ("principled", "metallic") => g.principled.iter_mut().for_each(|m| m.metallic = scalar()),
+        ("principled", "roughness") => g.principled.iter_mut().for_each(|m| m.roughness = scalar()),
+        ("principled", "specular") => g.principled.iter_mut().for_each(|m| m.specular = scalar()),
+        ("principled", "clearcoat") => g.principled.iter_mut().for_each(|m| m.clearcoat = scalar()),
+        ("principled", "sheen") => g.principled.iter_mut().for_each(|m| m.sheen = scalar()),
+        ("principled", "transmission") => g.principled.iter_mut().for_each(|m| m.transmission = scalar()),
+        ("principled", "ior") => g.principled.iter_mut().for_each(|m| m.ior = Some(v)),
+        _ => {},
+    }
+}
+
+// The ball of radius 1 on a gray checker floor, lit by the gradient sky
+fn cell_scene(material: Arc<MaterialEnum>) -> Scene {
+    let gray = |v: f32| Color{r: v, g: v, b: v};
+    let floor = MaterialEnum::Principled(Box::new(Principled{
+        base_color: Texture::Checker { scale: 0.5, even: gray(0.3), odd: gray(0.6) },
+        metallic: Texture::constant(0.0),
+        roughness: Texture::constant(1.0),
+        specular: Texture::constant(0.0),
+        clearcoat: Texture::constant(0.0),
+        clearcoat_roughness: 0.1,
+        sheen: Texture::constant(0.0),
+        transmission: Texture::constant(0.0),
+        ior: 1.5,
+        anisotropy: 0.0,
+    }));
+    let mut world = HittableList::default();
+    world.add(Hittable::Sphere(Sphere::new(Point3d::new(0.0, -1000.0, 0.0), 1000.0, Arc::new(floor))));
+    world.add(Hittable::Sphere(Sphere::new(Point3d::new(0.0, 1.0, 0.0), 1.0, material)));
+    Scene { world, sky: Sky::Gradient }
+}
+
+/// Renders every cell with the sampler, filter and tone mapping of the settings. Prints
+/// nothing, [`preview_cells`] lists the cells beforehand
+pub fn render_preview(c: &Settings, threads: u8) -> Result<ContactSheet> {
+    let (cells, columns) = preview_cells(c)?;
+    let p = c.preview.clone().unwrap_or_default();
+    let size = p.size.unwrap_or(SIZE).max(16);
+    let samples = p.samples_per_pixel.unwrap_or(SAMPLES);
+    let tm = ToneMapping::new(&c.tonemap)?;
+    // passes and checkpoints are for the main render
+    let options = RenderOptions { aovs: vec![], checkpoint: None, ..RenderOptions::from_settings(c)? };
+
+    let scale = if size >= 240 { 2 } else { 1 };
+    // two lines of text
+    let label_height = (2 * GLYPH_HEIGHT + 8) * scale;
+    let rows = cells.len().div_ceil(columns);
+    let (width, height) = (columns * usize::from(size), rows * (usize::from(size) + label_height));
+    let mut pixels = vec![[24, 24, 24]; width * height];
+
+    for (i, cell) in cells.into_iter().enumerate() {
+        let view = CameraView {
+            vfov: 30.0,
+            lookfrom: Point3d::new(0.0, 1.8, 6.5),
+            lookat: Point3d::new(0.0, 1.0, 0.0),
+            vup: Vec3d::new(0.0, 1.0, 0.0),
+            defocus_angle: 0.0,
+            focus_dist: 6.5,
+        };
+        let camera = Camera::initialize(1.0, size, MAX_DEPTH, samples, view).with_options(options.clone());
+        let image = render(camera, cell_scene(cell.material), threads)?;
+
+        let (x0, y0) = ((i % columns) * usize::from(size), (i / columns) * (usize::from(size) + label_height));
+        for (j, rgb) in image.to_rgb8(&tm).into_iter().enumerate() {
+            let (x, y) = (j % usize::from(image.width), j / usize::from(image.width));
+            pixels[(y0 + y) * width + x0 + x] = rgb;
+        }
+        let text_y = y0 + usize::from(size) + 3 * scale;
+        draw_text(&mut pixels, width, (x0 + 3 * scale, x0 + usize::from(size) - 3 * scale), text_y, &cell.label, scale);
+    }
+    let too_large = |_| Error::invalid("preview", "the contact sheet is larger than 65535 pixels, use fewer or smaller cells");
+    Ok(ContactSheet { width: u16::try_from(width).map_err(too_large)?, height: u16::try_from(height).map_err(too_large)?, pixels })
+}
+
+const GLYPH_HEIGHT: usize = 7;
+
+// 5x7 bitmaps of the characters of the labels, a row per byte with the leftmost pixel in
+// bit 4. Lower case letters are drawn as upper case ones, others as spaces
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        'A' => [0x0e, 0x11, 0x11, 0x11, 0x1f, 0x11, 0x11],
+        'B' => [0x1e, 0x11, 0x11, 0x1e, 0x11, 0x11, 0x1e],
+        'C' => [0x0e, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0e],
+        'D' => [0x1c, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1c],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0c],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'M' => [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'P' => [0x1e, 0x11, 0x11, 0x1e, 0x10, 0x10, 0x10],
+        'Q' => [0x0e, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0d],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'S' => [0x0f, 0x10, 0x10, 0x0e, 0x01, 0x01, 0x1e],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0a],
+        'X' => [0x11, 0x11, 0x0a, 0x04, 0x0a, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0a, 0x04, 0x04, 0x04],
+        'Z' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f],
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f],
+        '=' => [0x00, 0x00, 0x1f, 0x00, 0x1f, 0x00, 0x00],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+// White text from x, y, the words that don't fit before the end of the cell (xs.1) go on
+// the next line, a word too long for a line is cut off
+fn draw_text(pixels: &mut [[u8; 3]], width: usize, xs: (usize, usize), y: usize, text: &str, scale: usize) {
+    let line_length = (xs.1 - xs.0) / (6 * scale);
+    let (mut line, mut n) = (0, 0);
+    for word in text.split(' ') {
+        if n > 0 && n + 1 + word.len() > line_length {
+            (line, n) = (line + 1, 0);
+        } else if n > 0 {
+            n += 1;
+        }
+        for c in word.chars() {
+            let top = y + line * (GLYPH_HEIGHT + 2) * scale;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in (0 .. 5).filter(|column| bits & (0x10 >> column) != 0) {
+                    for (dx, dy) in (0 .. scale * scale).map(|k| (k % scale, k / scale)) {
+                        let x = xs.0 + (n * 6 + column) * scale + dx;
+                        let i = (top + row * scale + dy) * width + x;
+                        if x < xs.1 && i < pixels.len() {
+                            pixels[i] = [230, 230, 230];
+                        }
+                    }
+                }
+            }
+            n += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::Settings, preview::{preview_cells, render_preview}};
+
+    const CONFIG: &str = include_str!("../config.toml");
+
+    fn load(from: &str, to: &str) -> Settings {
+        assert!(CONFIG.contains(from));
+        Settings::from_toml(&CONFIG.replacen(from, to, 1)).unwrap()
+    }
+
+    #[test]
+    fn materials_are_rows_and_the_parameter_sweeps_the_columns() {
+        let c = load("", "");
+        let (cells, columns) = preview_cells(&c).unwrap();
+        let p = c.preview.as_ref().unwrap();
+        assert_eq!(cells.len(), p.materials.len() * columns);
+        assert_eq!(cells[0].label, format!("{} roughness 0.00", p.materials[0]));
+        assert_eq!(cells[columns - 1].label, format!("{} roughness 1.00", p.materials[0]));
+
+        let mut c = load("size = 160", "size = 16");
+        c.preview.as_mut().unwrap().samples_per_pixel = Some(1);
+        let sheet = render_preview(&c, 2).unwrap();
+        assert_eq!(usize::from(sheet.width), 16 * columns);
+        assert_eq!(sheet.pixels.len(), usize::from(sheet.width) * usize::from(sheet.height));
+        // the label strip under the first cell has some text in it
+        assert!(sheet.pixels[16 * usize::from(sheet.width) ..][.. 22 * usize::from(sheet.width)].contains(&[230, 230, 230]));
+    }
+
+    #[test]
+    fn unknown_parameters_are_errors() {
+        let e = Settings::from_toml(&CONFIG.replacen("parameter = \"roughness\"", "parameter = \"fuzz\"", 1)).unwrap_err();
+        assert!(e.to_string().contains("preview.parameter: unknown conductor parameter \"fuzz\", expected one of roughness, anisotropy"), "{e}");
+    }
+}
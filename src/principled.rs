@@ -0,0 +1,239 @@
+use core::f32;
+
+use crate::{camera::Ray, hit_record::HitRecord, material::Material, microfacet::{fresnel_dielectric, Frame, Ggx}, sampler, texture::Texture, vec3d::Vec3d, Color};
+
+const BLACK_COLOR: Color = Color{r: 0.0, g: 0.0, b: 0.0};
+const WHITE_COLOR: Color = Color{r: 1.0, g: 1.0, b: 1.0};
+
+/// Disney style "principled" uber material (Burley 2012, 2015) - a mix of a diffuse, sheen,
+/// specular GGX, clearcoat GGX and rough transmission lobes controlled by artist friendly parameters
+pub struct Principled {
+    /// diffuse color, and the specular color of metals
+    pub base_color: Texture,
+    /// from dielectric (0) to metal (1)
+    pub metallic: Texture,
+    /// roughness of the specular and transmission lobes
+    pub roughness: Texture,
+    /// dielectric specular reflectance, 0.5 is 4% at normal incidence (ior 1.5)
+    pub specular: Texture,
+    /// strength of a white varnish layer over everything
+    pub clearcoat: Texture,
+    /// roughness of the varnish layer
+    pub clearcoat_roughness: f32,
+    /// velvet-like reflection at grazing angles
+    pub sheen: Texture,
+    /// from opaque (0) to glass (1)
+    pub transmission: Texture,
+    /// refractive index of the transmission
+    pub ior: f32,
+    /// stretch of the highlight from -1 to 1, 0 is isotropic
+    pub anisotropy: f32,
+}
+
+// Material parameters resolved at a hit point, and the probabilities of sampling each lobe
+struct Lobes {
+    base: Color,
+    metallic: f32,
+    specular: f32,
+    clearcoat: f32,
+    sheen: f32,
+    transmission: f32,
+    eta: f32,
+    spec_dist: Ggx,
+    clearcoat_dist: Ggx,
+    // diffuse, specular, clearcoat, transmission
+    probs: [f32; 4],
+}
+
+impl Principled {
+    fn lobes(&self, hr: &HitRecord) -> Lobes {
+        let base = self.base_color.value(hr);
+        let metallic = self.metallic.scalar(hr).clamp(0.0, 1.0);
+        let transmission = self.transmission.scalar(hr).clamp(0.0, 1.0);
+        let clearcoat = self.clearcoat.scalar(hr).clamp(0.0, 1.0);
+
+        let w_diffuse = (1.0 - metallic) * (1.0 - transmission);
+        // the transmission lobe reflects by itself, the dielectric specular is left to the rest
+        let w_specular = metallic + (1.0 - metallic) * (1.0 - transmission) * 0.25;
+        let w_clearcoat = 0.25 * clearcoat;
+        let w_transmission = (1.0 - metallic) * transmission;
+        let total = w_diffuse + w_specular + w_clearcoat + w_transmission;
+
+        Lobes {
+            base,
+            metallic,
+            specular: self.specular.scalar(hr).max(0.0),
+            clearcoat,
+            sheen: self.sheen.scalar(hr).max(0.0),
+            transmission,
+            eta: if hr.front_face { self.ior } else { 1.0 / self.ior },
+            spec_dist: Ggx::new(self.roughness.scalar(hr), self.anisotropy),
+            clearcoat_dist: Ggx::new(self.clearcoat_roughness, 0.0),
+            probs: [w_diffuse / total, w_specular / total, w_clearcoat / total, w_transmission / total],
+        }
+    }
+
+    // BSDF times |cos| for local directions
+    fn eval_local(l: &Lobes, wo: &Vec3d, wi: &Vec3d) -> Color {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return BLACK_COLOR;
+        }
+
+        if wi.z > 0.0 {
+            let h = Vec3d::unit(&(wo.clone() + wi.clone()));
+            let wo_h = Vec3d::dot(wo, &h);
+
+            // the diffuse base only gets the light the specular layer lets through on the way
+            // in and out, normalized by the average (Kelemen and Szirmay-Kalos 2001). Reciprocal,
+            // and a white surface under a smooth coat neither gains nor loses energy
+            let diffuse_w = (1.0 - l.metallic) * (1.0 - l.transmission);
+            let f0 = (0.08 * l.specular).min(1.0);
+            let through = |cos: f32| 1.0 - (f0 + (1.0 - f0) * schlick_weight(cos));
+            // average of the Schlick Fresnel over the cosine weighted hemisphere
+            let average = f0 + (1.0 - f0) / 21.0;
+            let diffuse = l.base * (diffuse_w * through(wo.z) * through(wi.z) / ((1.0 - average).max(1e-3) * f32::consts::PI));
+            let sheen = WHITE_COLOR * (l.sheen * (1.0 - l.metallic) * schlick_weight(Vec3d::dot(wi, &h)) / f32::consts::PI);
+
+            let dielectric_f0 = WHITE_COLOR * (0.08 * l.specular);
+            let f0 = dielectric_f0 * (1.0 - l.metallic) + l.base * l.metallic;
+            let transmission_w = (1.0 - l.metallic) * l.transmission;
+            let f = schlick(f0, wo_h) * (1.0 - transmission_w);
+            let spec = f * (l.spec_dist.d(&h) * l.spec_dist.g2(wo, wi) / (4.0 * wo.z * wi.z));
+            // the transmissive part is a rough dielectric, it reflects what it doesn't refract
+            // (all of it under total internal reflection)
+            let inner = transmission_w * fresnel_dielectric(wo_h, l.eta) * l.spec_dist.d(&h) * l.spec_dist.g2(wo, wi) / (4.0 * wo.z * wi.z);
+
+            let fc = 0.04 + 0.96 * schlick_weight(wo_h);
+            let coat = 0.25 * l.clearcoat * fc * l.clearcoat_dist.d(&h) * l.clearcoat_dist.g2(wo, wi) / (4.0 * wo.z * wi.z);
+
+            (diffuse + sheen + spec + WHITE_COLOR * (coat + inner)) * wi.z
+        } else {
+            let transmission_w = (1.0 - l.metallic) * l.transmission;
+            if transmission_w <= 0.0 {
+                return BLACK_COLOR;
+            }
+            let Some((h, wo_h, wi_h, denom)) = refraction_half_vector(wo, wi, l.eta) else {
+                return BLACK_COLOR;
+            };
+            let f = fresnel_dielectric(wo_h, l.eta);
+            let d = l.spec_dist.d(&h);
+            let g = l.spec_dist.g2(wo, wi);
+            let ft = (1.0 - f) * d * g * l.eta * l.eta * wo_h * wi_h.abs() / (wo.z * denom * denom);
+            l.base * (transmission_w * ft)
+        }
+    }
+
+    fn pdf_local(l: &Lobes, wo: &Vec3d, wi: &Vec3d) -> f32 {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        if wi.z > 0.0 {
+            let h = Vec3d::unit(&(wo.clone() + wi.clone()));
+            let wo_h = Vec3d::dot(wo, &h);
+            let diffuse = wi.z / f32::consts::PI;
+            let spec = l.spec_dist.pdf_visible(wo, &h) / (4.0 * wo_h);
+            let coat = l.clearcoat_dist.pdf_visible(wo, &h) / (4.0 * wo_h);
+            let inner = fresnel_dielectric(wo_h, l.eta) * spec;
+            l.probs[0] * diffuse + l.probs[1] * spec + l.probs[2] * coat + l.probs[3] * inner
+        } else {
+            let Some((h, wo_h, wi_h, denom)) = refraction_half_vector(wo, wi, l.eta) else {
+                return 0.0;
+            };
+            let f = fresnel_dielectric(wo_h, l.eta);
+            l.probs[3] * (1.0 - f) * l.spec_dist.pdf_visible(wo, &h) * l.eta * l.eta * wi_h.abs() / (denom * denom)
+        }
+    }
+
+    fn sample_local(l: &Lobes, wo: &Vec3d) -> Option<Vec3d> {
+        let u = sampler::random();
+
+        if u < l.probs[0] {
+            let d = Vec3d::new(0.0, 0.0, 1.0) + Vec3d::random_unit();
+            return if d.near_zero() { Some(Vec3d::new(0.0, 0.0, 1.0)) } else { Some(Vec3d::unit(&d)) };
+        }
+
+        if u < l.probs[0] + l.probs[1] + l.probs[2] {
+            let dist = if u < l.probs[0] + l.probs[1] { &l.spec_dist } else { &l.clearcoat_dist };
+            let h = dist.sample_visible(wo);
+            let wi = Vec3d::reflect(&Vec3d::mul(wo, -1.0), &h);
+            return if wi.z > 0.0 { Some(wi) } else { None };
+        }
+
+        // transmission, reflected in the Fresnel fraction like a rough dielectric and always
+        // under total internal reflection
+        let h = l.spec_dist.sample_visible(wo);
+        let cos_i = Vec3d::dot(wo, &h);
+        if fresnel_dielectric(cos_i, l.eta) > sampler::random() {
+            let wi = Vec3d::reflect(&Vec3d::mul(wo, -1.0), &h);
+            return if wi.z > 0.0 { Some(wi) } else { None };
+        }
+        let cos_t = f32::sqrt(f32::max(0.0, 1.0 - (1.0 - cos_i * cos_i) / (l.eta * l.eta)));
+        let wi = Vec3d::mul(wo, -1.0 / l.eta) + Vec3d::mul(&h, cos_i / l.eta - cos_t);
+        if wi.z < 0.0 { Some(wi) } else { None }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, ray_in: &Ray, hr: &HitRecord) -> (Ray, Color, bool) {
+        let lobes = self.lobes(hr);
+        let frame = Frame::from_normal(&hr.normal);
+        let wo = frame.to_local(&Vec3d::mul(&Vec3d::unit(&ray_in.direction), -1.0));
+
+        let wi = Self::sample_local(&lobes, &wo);
+        let Some(wi) = wi else {
+            return (Ray::new(hr.point.clone(), hr.normal.clone(), Some(ray_in.tm)), BLACK_COLOR, false);
+        };
+
+        let scattered = Ray::new(hr.point.clone(), frame.to_world(&wi), Some(ray_in.tm));
+        let pdf = Self::pdf_local(&lobes, &wo, &wi);
+        if pdf <= 0.0 {
+            return (scattered, BLACK_COLOR, false);
+        }
+
+        let attenuation = Self::eval_local(&lobes, &wo, &wi) * (1.0 / pdf);
+        (scattered, attenuation, true)
+    }
+
+    fn eval(&self, ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> Option<Color> {
+        let lobes = self.lobes(hr);
+        let frame = Frame::from_normal(&hr.normal);
+        let wo = frame.to_local(&Vec3d::mul(&Vec3d::unit(&ray_in.direction), -1.0));
+        let wi = frame.to_local(&Vec3d::unit(direction));
+        Some(Self::eval_local(&lobes, &wo, &wi))
+    }
+
+    fn pdf(&self, ray_in: &Ray, hr: &HitRecord, direction: &Vec3d) -> f32 {
+        let lobes = self.lobes(hr);
+        let frame = Frame::from_normal(&hr.normal);
+        let wo = frame.to_local(&Vec3d::mul(&Vec3d::unit(&ray_in.direction), -1.0));
+        let wi = frame.to_local(&Vec3d::unit(direction));
+        Self::pdf_local(&lobes, &wo, &wi)
+    }
+
+    fn albedo(&self, hr: &HitRecord) -> Color {
+        self.base_color.value(hr)
+    }
+}
+
+fn schlick_weight(cos: f32) -> f32 {
+    f32::powi((1.0 - cos).clamp(0.0, 1.0), 5)
+}
+
+fn schlick(f0: Color, cos: f32) -> Color {
+    let w = schlick_weight(cos);
+    f0 * (1.0 - w) + WHITE_COLOR * w
+}
+
+// Half vector of a refraction from wo (above) to wi (below), oriented to the outside, with
+// the cosines wo.h, wi.h and the (wo.h + eta * wi.h) term of the Jacobian
+fn refraction_half_vector(wo: &Vec3d, wi: &Vec3d, eta: f32) -> Option<(Vec3d, f32, f32, f32)> {
+    let h = Vec3d::unit(&(wo.clone() + Vec3d::mul(wi, eta)));
+    let h = if h.z < 0.0 { Vec3d::mul(&h, -1.0) } else { h };
+    let wo_h = Vec3d::dot(wo, &h);
+    let wi_h = Vec3d::dot(wi, &h);
+    if wo_h <= 0.0 || wi_h >= 0.0 {
+        return None;
+    }
+    Some((h, wo_h, wi_h, wo_h + eta * wi_h))
+}
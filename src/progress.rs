@@ -0,0 +1,69 @@
+use std::{fs, io::Write, time::Instant};
+
+use crate::{config, error::{Error, Result}, stats::RenderStats};
+
+const BAR_WIDTH: usize = 30;
+
+/// Reports a running render as a progress bar on the terminal and/or as JSON lines
+/// (one object per update and a final one with the statistics) for other tools
+pub struct Progress {
+    bar: bool,
+    json: Option<(String, fs::File)>,
+    start: Instant,
+}
+
+impl Progress {
+    /// The reports of the `[progress]` settings, creating the json file if there is one
+    pub fn new(c: &Option<config::Progress>) -> Result<Progress> {
+        let json = match c.as_ref().and_then(|c| c.json.as_ref()) {
+            Some(path) => Some((path.clone(), fs::File::create(path).map_err(|e| Error::io(path, e))?)),
+            None => None,
+        };
+        Ok(Progress {
+            bar: c.as_ref().is_none_or(|c| c.bar),
+            json,
+            start: Instant::now(),
+        })
+    }
+
+    /// Reports nothing, for renders run by other code
+    pub fn quiet() -> Progress {
+        Progress { bar: false, json: None, start: Instant::now() }
+    }
+
+    pub(crate) fn update(&mut self, rows_done: u32, rows: u32, samples: u64) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let fraction = f64::from(rows_done) / f64::from(rows.max(1));
+        let eta = if rows_done > 0 { elapsed * (1.0 - fraction) / fraction } else { f64::NAN };
+
+        if self.bar {
+            let filled = (fraction * BAR_WIDTH as f64) as usize;
+            print!("\r[{}{}] {:5.1}%  rows {rows_done}/{rows}  {samples} samples  elapsed {elapsed:.1}s  ETA {}   ",
+                "#".repeat(filled), ".".repeat(BAR_WIDTH - filled), 100.0 * fraction,
+                if eta.is_finite() { format!("{eta:.1}s") } else { "-".to_string() });
+            // the bar is cosmetic, a closed terminal shouldn't stop the render
+            let _ = std::io::stdout().flush();
+        }
+
+        let eta = if eta.is_finite() { format!("{eta:.3}") } else { "null".to_string() };
+        // progress lines are best effort, only the final one reports a failed write
+        let _ = self.json_line(&format!(
+            "{{\"event\":\"progress\",\"rows_done\":{rows_done},\"rows\":{rows},\"samples\":{samples},\"elapsed_seconds\":{elapsed:.3},\"eta_seconds\":{eta}}}"));
+    }
+
+    /// Ends the bar and reports the statistics of the finished render
+    pub fn finish(&mut self, stats: &RenderStats) -> Result<()> {
+        if self.bar {
+            println!();
+        }
+        println!("{stats}");
+        self.json_line(&stats.json())
+    }
+
+    fn json_line(&mut self, line: &str) -> Result<()> {
+        if let Some((path, f)) = &mut self.json {
+            writeln!(f, "{line}").map_err(|e| Error::io(path, e))?;
+        }
+        Ok(())
+    }
+}
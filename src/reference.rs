@@ -0,0 +1,254 @@
+// Image regression tests: small canonical scenes rendered at a fixed seed and compared with
+// the reference images in tests/reference. The comparison allows for small numeric
+// differences (other platforms, reordered float math) but not for visible changes. On a
+// failure the render and a difference image are written to target/reference_diffs.
+// Run with UPDATE_REFERENCES=1 to replace the references by the current renders
+use std::{fs, path::PathBuf, sync::Arc};
+
+use crate::{camera::{Camera, CameraView, RenderOptions}, hit_record::{Hittable, HittableList, Sphere}, material::{Dielectric, Lambertian, MaterialEnum, Metal}, progress::Progress, scene::Scene, sky::Sky, tonemap::ToneMapping, vec3d::Vec3d, Color, Point3d};
+
+const SIZE: u16 = 64;
+const SAMPLES: u16 = 128;
+const MAX_DEPTH: u8 = 8;
+
+// mean absolute difference of the 8 bit channels, as a fraction of 255
+const MAX_MEAN_ERROR: f64 = 0.01;
+// CIELAB difference of the slightly blurred images (the blur forgives noise that moved
+// a little, the eye does too): the mean and the worst percentile of the pixels. Rendering
+// the spheres with another seed gives a mean of about 0.3, taking a tenth off the albedo
+// of the diffuse sphere about 0.7
+const MAX_MEAN_DELTA_E: f64 = 0.5;
+const MAX_P99_DELTA_E: f64 = 6.0;
+
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+fn lambertian(r: f32, g: f32, b: f32) -> Arc<MaterialEnum> {
+    Arc::new(MaterialEnum::Lambertian(Lambertian{albedo: Color{r, g, b}}))
+}
+
+fn ground() -> Hittable {
+    Hittable::Sphere(Sphere::new(Point3d::new(0.0, -1000.5, 0.0), 1000.0, lambertian(0.5, 0.5, 0.5)))
+}
+
+fn list(objects: Vec<Hittable>) -> HittableList {
+    let mut l = HittableList::default();
+    objects.into_iter().for_each(|o| l.add(o));
+    l
+}
+
+fn view(lookfrom: Point3d, lookat: Point3d, vfov: f32) -> CameraView {
+    CameraView { vfov, lookfrom, lookat, vup: Vec3d::new(0.0, 1.0, 0.0), defocus_angle: 0.0, focus_dist: 1.0 }
+}
+
+fn sphere_view() -> CameraView {
+    view(Point3d::new(0.0, 0.5, 4.0), Point3d::new(0.0, 0.0, 0.0), 30.0)
+}
+
+fn diffuse_sphere() -> (HittableList, CameraView) {
+    let sphere = Sphere::new(Point3d::new(0.0, 0.0, 0.0), 0.5, lambertian(0.7, 0.3, 0.2));
+    (list(vec![ground(), Hittable::Sphere(sphere)]), sphere_view())
+}
+
+fn glass_sphere() -> (HittableList, CameraView) {
+    let glass = Sphere::new(Point3d::new(0.0, 0.0, 0.0), 0.5, Arc::new(MaterialEnum::Dielectric(Dielectric::new(1.5))));
+    // something to see through the glass
+    let behind = Sphere::new(Point3d::new(0.4, 0.0, -1.5), 0.5, lambertian(0.2, 0.3, 0.8));
+    (list(vec![ground(), Hittable::Sphere(glass), Hittable::Sphere(behind)]), sphere_view())
+}
+
+fn metal_sphere() -> (HittableList, CameraView) {
+    let metal = Arc::new(MaterialEnum::Metal(Metal{albedo: Color{r: 0.8, g: 0.8, b: 0.8}, fuzz: 0.1}));
+    let mirror = Sphere::new(Point3d::new(0.0, 0.0, 0.0), 0.5, metal);
+    let reflected = Sphere::new(Point3d::new(-1.0, 0.0, 1.0), 0.5, lambertian(0.2, 0.7, 0.2));
+    (list(vec![ground(), Hittable::Sphere(mirror), Hittable::Sphere(reflected)]), sphere_view())
+}
+
+// The walls are big spheres as in smallpt (not as big, f32 intersections of huge spheres
+// are too imprecise). There are no area lights, so the box is open towards the camera and
+// lit by the sky through that side and the missing ceiling
+fn cornell_box() -> (HittableList, CameraView) {
+    let wall = |x: f32, y: f32, z: f32, m: Arc<MaterialEnum>| Hittable::Sphere(Sphere::new(Point3d::new(x, y, z), 100.0, m));
+    let white = lambertian(0.73, 0.73, 0.73);
+    let objects = vec![
+        wall(-101.0, 0.0, 0.0, lambertian(0.65, 0.05, 0.05)),
+        wall(101.0, 0.0, 0.0, lambertian(0.12, 0.45, 0.15)),
+        wall(0.0, -101.0, 0.0, white.clone()),
+        wall(0.0, 0.0, -101.0, white.clone()),
+        Hittable::Sphere(Sphere::new(Point3d::new(-0.4, -0.6, -0.3), 0.4, Arc::new(MaterialEnum::Dielectric(Dielectric::new(1.5))))),
+        Hittable::Sphere(Sphere::new(Point3d::new(0.45, -0.65, 0.1), 0.35, white)),
+    ];
+    (list(objects), view(Point3d::new(0.0, 0.0, 3.5), Point3d::new(0.0, 0.0, 0.0), 40.0))
+}
+
+fn render((world, cv): (HittableList, CameraView), exposure: f32) -> Image {
+    let options = RenderOptions { seed: 1, ..RenderOptions::default() };
+    let camera = Arc::new(Camera::initialize(1.0, SIZE, MAX_DEPTH, SAMPLES, cv).with_options(options));
+    let scene = Arc::new(Scene { world, sky: Sky::Gradient });
+    let mut progress = Progress::quiet();
+    Camera::render(camera.clone(), scene, 2, &mut progress).unwrap();
+
+    let tm = ToneMapping { exposure, ..ToneMapping::default() };
+    let width = usize::from(camera.image_width);
+    let pixels = camera.pixels.lock().unwrap().iter().enumerate()
+        .map(|(i, c)| tm.encode(c, (i % width) as u32, (i / width) as u32))
+        .collect();
+    Image { width, height: usize::from(camera.image_height), pixels }
+}
+
+fn write_ppm(path: &PathBuf, image: &Image) {
+    let mut data = format!("P6\n{} {}\n255\n", image.width, image.height).into_bytes();
+    image.pixels.iter().for_each(|p| data.extend_from_slice(p));
+    fs::write(path, data).expect("Cannot write image");
+}
+
+fn read_ppm(path: &PathBuf) -> Option<Image> {
+    let data = fs::read(path).ok()?;
+    // P6 header: magic, width, height and max value separated by whitespace, then one
+    // whitespace byte before the pixels
+    let mut fields = vec![];
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while data.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while !data.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        fields.push(std::str::from_utf8(&data[start .. pos]).ok()?);
+    }
+    let (width, height): (usize, usize) = (fields[1].parse().ok()?, fields[2].parse().ok()?);
+    if fields[0] != "P6" || fields[3] != "255" {
+        return None;
+    }
+    let pixels = data.get(pos + 1 .. pos + 1 + width * height * 3)?.chunks(3).map(|p| [p[0], p[1], p[2]]).collect();
+    Some(Image { width, height, pixels })
+}
+
+fn lab(p: [f32; 3]) -> [f32; 3] {
+    // the pixels are sRGB encoded, undo the transfer function first
+    let linear = p.map(|v| {
+        let v = v / 255.0;
+        if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+    });
+    let [r, g, b] = linear;
+    // D65 white
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.9505;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.089;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    [116.0 * f(y) - 16.0, 500.0 * (f(x) - f(y)), 200.0 * (f(y) - f(z))]
+}
+
+// 3x3 box blur, returned as floats
+fn blur(image: &Image) -> Vec<[f32; 3]> {
+    let (w, h) = (image.width as isize, image.height as isize);
+    (0 .. h).flat_map(|y| (0 .. w).map(move |x| (x, y))).map(|(x, y)| {
+        let mut sum = [0.0; 3];
+        let mut n = 0.0;
+        for (dx, dy) in (-1 ..= 1).flat_map(|dx| (-1 ..= 1).map(move |dy| (dx, dy))) {
+            let (qx, qy) = (x + dx, y + dy);
+            if qx >= 0 && qx < w && qy >= 0 && qy < h {
+                let p = image.pixels[(qy * w + qx) as usize];
+                (0 .. 3).for_each(|c| sum[c] += f32::from(p[c]));
+                n += 1.0;
+            }
+        }
+        sum.map(|s| s / n)
+    }).collect()
+}
+
+struct Difference {
+    mean_error: f64,
+    mean_delta_e: f64,
+    p99_delta_e: f64,
+}
+
+fn difference(a: &Image, b: &Image) -> Difference {
+    let abs_sum: u64 = a.pixels.iter().zip(&b.pixels)
+        .flat_map(|(p, q)| (0 .. 3).map(move |c| u64::from(p[c].abs_diff(q[c]))))
+        .sum();
+
+    let mut delta_e: Vec<f64> = blur(a).into_iter().zip(blur(b))
+        .map(|(p, q)| {
+            let (p, q) = (lab(p), lab(q));
+            f64::from((0 .. 3).map(|c| (p[c] - q[c]) * (p[c] - q[c])).sum::<f32>().sqrt())
+        })
+        .collect();
+    delta_e.sort_by(f64::total_cmp);
+
+    Difference {
+        mean_error: abs_sum as f64 / (a.pixels.len() * 3 * 255) as f64,
+        mean_delta_e: delta_e.iter().sum::<f64>() / delta_e.len() as f64,
+        p99_delta_e: delta_e[(delta_e.len() - 1) * 99 / 100],
+    }
+}
+
+// Per pixel difference scaled up 8 times, so small changes are still visible
+fn difference_image(a: &Image, b: &Image) -> Image {
+    let pixels = a.pixels.iter().zip(&b.pixels)
+        .map(|(p, q)| [0, 1, 2].map(|c| p[c].abs_diff(q[c]).saturating_mul(8)))
+        .collect();
+    Image { width: a.width, height: a.height, pixels }
+}
+
+// exposure (in stops) brightens the dim scenes, so their references aren't mostly black
+fn check(name: &str, scene: (HittableList, CameraView), exposure: f32) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let reference_path = root.join("tests/reference").join(format!("{name}.ppm"));
+    let rendered = render(scene, exposure);
+
+    if std::env::var_os("UPDATE_REFERENCES").is_some() {
+        fs::create_dir_all(reference_path.parent().unwrap()).unwrap();
+        write_ppm(&reference_path, &rendered);
+        return;
+    }
+
+    let reference = read_ppm(&reference_path)
+        .unwrap_or_else(|| panic!("Missing reference {}, create it with UPDATE_REFERENCES=1", reference_path.display()));
+    assert_eq!((reference.width, reference.height), (rendered.width, rendered.height), "{name} changed size");
+
+    let d = difference(&rendered, &reference);
+    if d.mean_error > MAX_MEAN_ERROR || d.mean_delta_e > MAX_MEAN_DELTA_E || d.p99_delta_e > MAX_P99_DELTA_E {
+        let diffs = root.join("target/reference_diffs");
+        fs::create_dir_all(&diffs).unwrap();
+        write_ppm(&diffs.join(format!("{name}_rendered.ppm")), &rendered);
+        write_ppm(&diffs.join(format!("{name}_diff.ppm")), &difference_image(&rendered, &reference));
+        panic!("{name} differs from its reference: mean error {:.4}, mean delta E {:.2}, 99th percentile delta E {:.2}, see {}",
+            d.mean_error, d.mean_delta_e, d.p99_delta_e, diffs.display());
+    }
+}
+
+#[test]
+fn diffuse_sphere_matches_reference() {
+    check("diffuse_sphere", diffuse_sphere(), 0.0);
+}
+
+#[test]
+fn glass_sphere_matches_reference() {
+    check("glass_sphere", glass_sphere(), 0.0);
+}
+
+#[test]
+fn metal_sphere_matches_reference() {
+    check("metal_sphere", metal_sphere(), 0.0);
+}
+
+#[test]
+fn cornell_box_matches_reference() {
+    check("cornell_box", cornell_box(), 1.5);
+}
+
+#[test]
+fn difference_ignores_equal_images_and_sees_changed_ones() {
+    let a = Image { width: 4, height: 4, pixels: vec![[100, 150, 200]; 16] };
+    let mut b = Image { width: 4, height: 4, pixels: a.pixels.clone() };
+    assert_eq!(difference(&a, &b).mean_delta_e, 0.0);
+    b.pixels[5] = [255, 0, 0];
+    let d = difference(&a, &b);
+    assert!(d.mean_error > 0.0 && d.p99_delta_e > MAX_P99_DELTA_E);
+}
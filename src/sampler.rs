@@ -0,0 +1,328 @@
+use std::{cell::RefCell, ops::Range};
+
+// Every random number used while tracing a camera sample comes from here. The sample is
+// split into dimensions: the camera ones (pixel offset, lens, time, wavelength) and a fixed
+// block per bounce, so a material drawing more numbers doesn't shift the next bounces.
+// Draws past the end of a block fall back to a pseudo random stream. All of it is derived
+// from the seed, the pixel and the sample index only, so an image doesn't depend on the
+// thread count or on the order the rows are rendered in
+const CAMERA_DIMENSIONS: u32 = 4;
+const BOUNCE_DIMENSIONS: u32 = 6;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum SamplerKind {
+    #[default]
+    Independent,
+    // jittered strata, every dimension is permuted independently per pixel
+    Stratified,
+    // Halton sequence with a random rotation per pixel and dimension
+    Halton,
+    // shuffled Owen-scrambled Sobol (Burley 2020)
+    Sobol,
+}
+
+impl SamplerKind {
+    pub(crate) fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    kind: SamplerKind,
+    samples_per_pixel: u32,
+    pixel_seed: u32,
+    sample_index: u32,
+    // pcg32 state of the fallback stream
+    stream: u64,
+    bounce: u32,
+    dimension: u32,
+    dimension_end: u32,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+// Starts a new camera sample of the pixel i, j
+pub(crate) fn start_sample(kind: SamplerKind, seed: u32, samples_per_pixel: u16, i: u16, j: u16, sample_index: u32) {
+    STATE.with_borrow_mut(|s| {
+        s.kind = kind;
+        s.samples_per_pixel = u32::from(samples_per_pixel).max(1);
+        s.pixel_seed = hash((u32::from(i) | (u32::from(j) << 16)) ^ hash(seed));
+        s.sample_index = sample_index;
+        s.stream = (u64::from(s.pixel_seed) << 32) | u64::from(hash(sample_index ^ hash(s.pixel_seed)));
+        // the first output hardly depends on the low half of the state, one step mixes it in
+        pcg32(&mut s.stream);
+        s.bounce = 0;
+        s.dimension = 0;
+        s.dimension_end = CAMERA_DIMENSIONS;
+    });
+}
+
+// Moves to the block of dimensions of the next path vertex
+pub(crate) fn next_bounce() {
+    STATE.with_borrow_mut(|s| {
+        s.dimension = CAMERA_DIMENSIONS + s.bounce * BOUNCE_DIMENSIONS;
+        s.dimension_end = s.dimension + BOUNCE_DIMENSIONS;
+        s.bounce += 1;
+    });
+}
+
+pub(crate) fn random() -> f32 {
+    random_2d().0
+}
+
+// Next two dimensional point of the current sample, both values in [0, 1)
+pub(crate) fn random_2d() -> (f32, f32) {
+    STATE.with_borrow_mut(|s| {
+        // Halton dimensions past the prime table would repeat the bases of the first ones
+        let past_primes = s.kind == SamplerKind::Halton && 2 * s.dimension as usize + 1 >= PRIMES.len();
+        if s.kind == SamplerKind::Independent || s.dimension >= s.dimension_end || past_primes {
+            return (to_unit(pcg32(&mut s.stream)), to_unit(pcg32(&mut s.stream)));
+        }
+        let d = s.dimension;
+        s.dimension += 1;
+        let seed = hash(s.pixel_seed ^ hash(d));
+        match s.kind {
+            SamplerKind::Stratified => stratified_2d(s.sample_index, s.samples_per_pixel, seed),
+            SamplerKind::Halton => halton_2d(s.sample_index, d, seed),
+            _ => sobol_2d(s.sample_index, seed),
+        }
+    })
+}
+
+// Permuted O'Neill pcg32 (XSH RR)
+fn pcg32(state: &mut u64) -> u32 {
+    let old = *state;
+    *state = old.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+    xorshifted.rotate_right((old >> 59) as u32)
+}
+
+// Seeded stream for the scene layouts. The algorithm and the float conversion are fixed
+// here, unlike the generators of rand, so a layout can't change with a dependency update
+pub(crate) struct Pcg32 {
+    state: u64,
+}
+
+impl Pcg32 {
+    pub(crate) fn new(seed: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: seed ^ 0x853c_49e6_748f_ea9b };
+        pcg32(&mut rng.state);
+        rng
+    }
+
+    // in [0, 1)
+    pub(crate) fn random(&mut self) -> f32 {
+        to_unit(pcg32(&mut self.state))
+    }
+
+    pub(crate) fn random_range(&mut self, range: Range<f32>) -> f32 {
+        range.start + (range.end - range.start) * self.random()
+    }
+}
+
+fn stratified_2d(index: u32, spp: u32, seed: u32) -> (f32, f32) {
+    let nx = (spp as f32).sqrt().ceil() as u32;
+    let ny = spp.div_ceil(nx);
+    let stratum = permute(index % (nx * ny), nx * ny, seed);
+    let jx = to_unit(hash(seed ^ hash(index)));
+    let jy = to_unit(hash(hash(seed ^ hash(index))));
+    (
+        ((stratum % nx) as f32 + jx) / nx as f32,
+        ((stratum / nx) as f32 + jy) / ny as f32,
+    )
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+fn halton_2d(index: u32, dimension: u32, seed: u32) -> (f32, f32) {
+    let b = 2 * dimension as usize;
+    let shift_x = seed as f32 / 4294967296.0;
+    let shift_y = hash(seed) as f32 / 4294967296.0;
+    (
+        (radical_inverse(index, PRIMES[b]) + shift_x).fract(),
+        (radical_inverse(index, PRIMES[b + 1]) + shift_y).fract(),
+    )
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f64 * inv;
+        index /= base;
+        inv *= inv_base;
+    }
+    (result as f32).min(ONE_MINUS_EPSILON)
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+// The first two Sobol dimensions, the index is shuffled and the values Owen-scrambled
+// per pixel and dimension, which decorrelates the padded 2d points
+fn sobol_2d(index: u32, seed: u32) -> (f32, f32) {
+    let index = nested_uniform_scramble(index, seed);
+    let x = nested_uniform_scramble(index.reverse_bits(), hash(seed ^ 0x9e37_79b9));
+    let y = nested_uniform_scramble(sobol_dimension_1(index), hash(seed ^ 0x7f4a_7c15));
+    (to_unit(x), to_unit(y))
+}
+
+fn sobol_dimension_1(index: u32) -> u32 {
+    // direction numbers of the polynomial x + 1, the m values are 1, 3, 5, 15, 17...
+    let mut result = 0;
+    let mut v: u32 = 1 << 31;
+    let mut i = index;
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn to_unit(x: u32) -> f32 {
+    ((x >> 8) as f32 / 16777216.0).min(ONE_MINUS_EPSILON)
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Random permutation of i in [0, len) (Kensler 2013)
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    i.wrapping_add(seed) % len
+}
+
+pub(crate) fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sampler::{next_bounce, permute, Pcg32, random_2d, sobol_2d, start_sample, stratified_2d, SamplerKind};
+
+    #[test]
+    fn permutation_is_a_bijection() {
+        let mut seen = [false; 10];
+        for i in 0 .. 10 {
+            seen[permute(i, 10, 1234) as usize] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+    }
+
+    #[test]
+    fn sobol_points_are_stratified() {
+        // every one of the 4x4 cells gets exactly one of the first 16 points
+        let mut cells = [0; 16];
+        for i in 0 .. 16 {
+            let (x, y) = sobol_2d(i, 42);
+            cells[(x * 4.0) as usize + 4 * (y * 4.0) as usize] += 1;
+        }
+        assert!(cells.iter().all(|c| *c == 1), "{cells:?}");
+    }
+
+    #[test]
+    fn stratified_points_cover_the_grid() {
+        let mut cells = [0; 9];
+        for i in 0 .. 9 {
+            let (x, y) = stratified_2d(i, 9, 7);
+            cells[(x * 3.0) as usize + 3 * (y * 3.0) as usize] += 1;
+        }
+        assert!(cells.iter().all(|c| *c == 1), "{cells:?}");
+    }
+
+    #[test]
+    fn distant_halton_dimensions_are_independent() {
+        // dimension 16 is the first of the third bounce, 16 apart from the pixel offset
+        let mut cells = [0; 16];
+        for index in 0 .. 256 {
+            start_sample(SamplerKind::Halton, 1, 256, 4, 5, index);
+            let (x, _) = random_2d();
+            for _ in 0 .. 3 {
+                next_bounce();
+            }
+            let (y, _) = random_2d();
+            cells[(x * 4.0) as usize + 4 * (y * 4.0) as usize] += 1;
+        }
+        // correlated dimensions put all points on a line through a few of the cells
+        assert!(cells.iter().all(|c| *c > 4), "{cells:?}");
+    }
+
+    #[test]
+    fn scene_stream_is_fixed() {
+        let mut rng = Pcg32::new(0);
+        let v = [rng.random(), rng.random(), rng.random_range(-2.0 .. 2.0)];
+        // the layouts of the scenes must not change between machines or builds
+        assert_eq!(v, [0.245_771_41, 0.750_259_2, 0.708_957_2]);
+    }
+
+    #[test]
+    fn samples_only_depend_on_seed_pixel_and_index() {
+        let draw = |seed: u32, i: u16| {
+            start_sample(SamplerKind::Independent, seed, 16, i, 3, 5);
+            [random_2d(), random_2d()]
+        };
+        let a = draw(1, 7);
+        draw(2, 8);
+        assert_eq!(a, draw(1, 7));
+        assert_ne!(a, draw(2, 7));
+        assert_ne!(a, draw(1, 8));
+    }
+}
@@ -1,148 +1,517 @@
-use std::sync::Arc;
+use std::{f32::consts::PI, sync::Arc};
 
-use rand::Rng;
 
-use crate::{aabb::Aabb, bhv::BvhNode, config::Settings, hit_record::{Hittable, HittableList, Sphere}, material::{Dielectric, Lambertian, MaterialEnum, Metal}, Color, Point3d};
+use crate::{bhv::BvhNode, config::{self, Ground, Settings, TextureParam}, error::{unknown, Error, Result}, hit_record::{Hittable, HittableList, Instance, Sphere, Triangle}, material::{absorption_from_transmittance, Dielectric, Lambertian, MaterialEnum, Metal, RoughConductor, RoughDielectric}, microfacet::{conductor_preset, Ggx, CONDUCTOR_PRESETS}, principled::Principled, sampler::Pcg32, sky::Sky, spectrum::Dispersion, texture::Texture, vec3d::Vec3d, Color, Point3d};
 
-pub(crate) fn sea_of_balls_scene(c: Settings) -> HittableList {
-    let mut world = HittableList {
-        objects: vec![],
-        bbox: Aabb::default(),
-    };
+/// Everything a camera can see: the objects and the sky lighting them
+pub struct Scene {
+    /// the objects, usually one [`BvhNode`]
+    pub world: HittableList,
+    /// the light
+    pub sky: Sky,
+}
+
+fn texture(t: TextureParam) -> Texture {
+    let color = |c: [f32; 3]| Color{r: c[0], g: c[1], b: c[2]};
+    match t {
+        TextureParam::Scalar(v) => Texture::constant(v),
+        TextureParam::Color(c) => Texture::Solid(color(c)),
+        TextureParam::Checker { scale, even, odd } => Texture::Checker { scale, even: color(even), odd: color(odd) },
+    }
+}
+
+// Clear glass unless a transmittance color after the given distance (1 by default) is set
+fn absorption(section: &str, transmittance: Option<[f32; 3]>, distance: Option<f32>) -> Result<Color> {
+    let distance = positive(&format!("{section}.transmittance_distance"), distance.unwrap_or(1.0))?;
+    Ok(match transmittance {
+        Some(t) => absorption_from_transmittance(Color{r: t[0], g: t[1], b: t[2]}, distance),
+        None => Color{r: 0.0, g: 0.0, b: 0.0},
+    })
+}
+
+// Material names of the ground, each reads its parameters from the section of that name
+pub(crate) const MATERIALS: &[&str] = &["diffuse", "metal", "dielectric", "conductor", "rough_dielectric", "principled"];
 
-    let ground_material: Arc<MaterialEnum> = match c.ground.material.as_str() {
+pub(crate) fn ground_material(g: &Ground) -> Result<Arc<MaterialEnum>> {
+    let missing = |name: &str| Error::invalid(&format!("ground.{name}"), format!("the {name} ground material needs a [ground.{name}] section"));
+    let material = match g.material.as_str() {
         "diffuse" => {
-            let diffuse = c.ground.diffuse.expect("Ground diffuse params missing");
+            let diffuse = g.diffuse.as_ref().ok_or_else(|| missing("diffuse"))?;
             let l = Lambertian{albedo: Color{r: diffuse.albedo[0], g: diffuse.albedo[1], b: diffuse.albedo[2]}};
             Arc::new(MaterialEnum::Lambertian(l))
         },
         "metal" => {
-            let metal = c.ground.metal.expect("Ground metal params missing");
+            let metal = g.metal.as_ref().ok_or_else(|| missing("metal"))?;
             let m = Metal{albedo: Color{r: metal.albedo[0], g: metal.albedo[1], b: metal.albedo[2]}, fuzz: metal.fuzz};
             Arc::new(MaterialEnum::Metal(m))
         },
         "dielectric" => {
-            let dielectric = c.ground.dielectric.expect("Ground dielectric params missing");
-            let d = Dielectric{refraction_index: dielectric.refraction};
+            let dielectric = g.dielectric.as_ref().ok_or_else(|| missing("dielectric"))?;
+            let absorption = absorption("ground.dielectric", dielectric.transmittance, dielectric.transmittance_distance)?;
+            let dispersion = match (&dielectric.dispersion, dielectric.cauchy) {
+                (Some(preset), _) => Some(Dispersion::preset(preset).ok_or_else(|| unknown("ground.dielectric.dispersion", "dispersion preset", preset, Dispersion::PRESETS))?),
+                (None, Some([a, b])) => Some(Dispersion::Cauchy { a, b }),
+                _ => None,
+            };
+            let d = Dielectric{refraction_index: dielectric.refraction, absorption, dispersion};
             Arc::new(MaterialEnum::Dielectric(d))
         },
-        _ => Arc::new(MaterialEnum::Lambertian(Lambertian{albedo: Color{r: 0.5, g: 0.5, b: 0.5}}))
+        "conductor" => {
+            let conductor = g.conductor.as_ref().ok_or_else(|| missing("conductor"))?;
+            let (eta, k) = match (&conductor.preset, conductor.eta, conductor.k) {
+                (Some(preset), _, _) => conductor_preset(preset).ok_or_else(|| unknown("ground.conductor.preset", "conductor preset", preset, CONDUCTOR_PRESETS))?,
+                (None, Some(eta), Some(k)) => (
+                    Color{r: eta[0], g: eta[1], b: eta[2]},
+                    Color{r: k[0], g: k[1], b: k[2]},
+                ),
+                _ => return Err(Error::invalid("ground.conductor", "needs either a preset or both eta and k")),
+            };
+            let distribution = Ggx::new(conductor.roughness, conductor.anisotropy.unwrap_or(0.0));
+            Arc::new(MaterialEnum::RoughConductor(RoughConductor{eta, k, distribution}))
+        },
+        "rough_dielectric" => {
+            let dielectric = g.rough_dielectric.as_ref().ok_or_else(|| missing("rough_dielectric"))?;
+            let distribution = Ggx::new(dielectric.roughness, dielectric.anisotropy.unwrap_or(0.0));
+            let absorption = absorption("ground.rough_dielectric", dielectric.transmittance, dielectric.transmittance_distance)?;
+            Arc::new(MaterialEnum::RoughDielectric(RoughDielectric{refraction_index: dielectric.refraction, distribution, absorption}))
+        },
+        "principled" => {
+            let p = g.principled.clone().ok_or_else(|| missing("principled"))?;
+            let param = |t: Option<TextureParam>, default: f32| t.map(texture).unwrap_or(Texture::constant(default));
+            Arc::new(MaterialEnum::Principled(Box::new(Principled{
+                base_color: texture(p.base_color),
+                metallic: param(p.metallic, 0.0),
+                roughness: param(p.roughness, 0.5),
+                specular: param(p.specular, 0.5),
+                clearcoat: param(p.clearcoat, 0.0),
+                clearcoat_roughness: p.clearcoat_roughness.unwrap_or(0.1),
+                sheen: param(p.sheen, 0.0),
+                transmission: param(p.transmission, 0.0),
+                ior: p.ior.unwrap_or(1.5),
+                anisotropy: p.anisotropy.unwrap_or(0.0),
+            })))
+        },
+        name => return Err(unknown("ground.material", "material", name, MATERIALS)),
     };
+    Ok(material)
+
+}
+
+/// A named procedural scene. The settings pick one by `generator` in `[scene]`, it reads
+/// its parameters from the `[scene.<name>]` section and has a view of its own, which the
+/// `[camera]` settings override
+pub struct Generator {
+    /// the name in the settings
+    pub name: &'static str,
+    /// what it makes, for `raytracer scenes`
+    pub description: &'static str,
+    view: fn(&Settings) -> config::Camera,
+    objects: fn(&Settings) -> Result<HittableList>,
+}
+
+/// Every generator, the first is the default
+pub const GENERATORS: &[Generator] = &[
+    Generator { name: "sea_of_balls", description: "the random spheres of the book cover", view: cover_view, objects: sea_of_balls },
+    Generator { name: "cornell_box", description: "a box of red, green and white walls with two spheres", view: cornell_box_view, objects: cornell_box },
+    Generator { name: "random_triangles", description: "triangles of random size, color and orientation", view: random_triangles_view, objects: random_triangles },
+    Generator { name: "forest", description: "one tree of triangles instanced many times", view: forest_view, objects: forest },
+    Generator { name: "material_grid", description: "a sphere per material and roughness", view: material_grid_view, objects: material_grid },
+];
+
+impl Generator {
+    /// The generator named in the settings, the sea of balls if there is none
+    pub fn from_settings(c: &Settings) -> Result<&'static Generator> {
+        let name = c.scene.as_ref().and_then(|s| s.generator.as_deref()).unwrap_or(GENERATORS[0].name);
+        Generator::find(name)
+    }
+
+    /// The generator of that name
+    pub fn find(name: &str) -> Result<&'static Generator> {
+        GENERATORS.iter().find(|g| g.name == name).ok_or_else(|| {
+            let names: Vec<&str> = GENERATORS.iter().map(|g| g.name).collect();
+            unknown("scene.generator", "scene generator", name, &names)
+        })
+    }
+
+    /// The objects of the scene, one by one
+    pub fn generate(&self, c: &Settings) -> Result<HittableList> {
+        (self.objects)(c).map_err(|e| e.in_file(c.file.as_deref()))
+    }
+
+    /// The objects of the scene behind a BVH
+    pub fn build(&self, c: &Settings) -> Result<HittableList> {
+        Ok(HittableList::new(Hittable::BvhNode(BvhNode::new(&mut self.generate(c)?))))
+    }
+
+    /// The view of the scene with the `[camera]` settings applied
+    pub fn camera(&self, c: &Settings) -> config::Camera {
+        c.camera.clone().unwrap_or_default().or(&(self.view)(c))
+    }
+}
+
+/// The random spheres scene of the book, with the ground and seed from the settings
+pub fn sea_of_balls_scene(c: Settings) -> Result<HittableList> {
+    GENERATORS[0].build(&c)
+}
+
+/// A built world with the material of its ground sphere, the one at `g.center` of radius
+/// `g.radius`, made from `g` again. Keeps the geometry and the BVH, for settings that
+/// changed in the ground material only
+pub fn replace_ground_material(world: &HittableList, g: &Ground) -> Result<HittableList> {
+    let material = ground_material(g)?;
+    let center = Point3d::new(g.center[0], g.center[1], g.center[2]);
+    let pick = |c: &Point3d, radius: f32| c.as_vec3d() == center.as_vec3d() && radius == g.radius;
+    Ok(world.replace_sphere_material(&pick, &material).unwrap_or_else(|| world.clone()))
+}
+
+fn rng(c: &Settings) -> Pcg32 {
+    Pcg32::new(u64::from(c.seed.unwrap_or(0)))
+}
+
+fn ground(c: &Settings) -> Result<Hittable> {
+    let center = Point3d::new(c.ground.center[0], c.ground.center[1], c.ground.center[2]);
+    Ok(Hittable::Sphere(Sphere::new(center, c.ground.radius, ground_material(&c.ground)?)))
+}
+
+fn lambertian(c: [f32; 3]) -> Arc<MaterialEnum> {
+    Arc::new(MaterialEnum::Lambertian(Lambertian{albedo: Color{r: c[0], g: c[1], b: c[2]}}))
+}
+
+fn view(lookfrom: [f32; 3], lookat: [f32; 3], vfov: f32) -> config::Camera {
+    let focus_dist = (0 .. 3).map(|i| (lookfrom[i] - lookat[i]).powi(2)).sum::<f32>().sqrt();
+    config::Camera {
+        lookfrom: Some(lookfrom),
+        lookat: Some(lookat),
+        vup: Some([0.0, 1.0, 0.0]),
+        vfov: Some(vfov),
+        aspect_ratio: None,
+        defocus_angle: Some(0.0),
+        focus_dist: Some(focus_dist),
+    }
+}
+
+fn positive(field: &str, v: f32) -> Result<f32> {
+    if v.is_finite() && v > 0.0 {
+        Ok(v)
+    } else {
+        Err(Error::invalid(field, format!("{v} has to be positive")))
+    }
+}
 
-    let ground_point = Point3d::new(c.ground.center[0], c.ground.center[1], c.ground.center[2]);
+fn cover_view(_: &Settings) -> config::Camera {
+    config::Camera { defocus_angle: Some(0.6), focus_dist: Some(10.0), ..view([13.0, 2.0, 3.0], [0.0, 0.0, 0.0], 20.0) }
+}
 
-    world.add(Hittable::Sphere(Sphere::new(ground_point, c.ground.radius, ground_material)));
+// Small balls on a grid of cells from -extent to extent, each moved randomly inside its
+// cell, around three big ones
+fn sea_of_balls(c: &Settings) -> Result<HittableList> {
+    let p = c.scene.as_ref().and_then(|s| s.sea_of_balls.clone()).unwrap_or_default();
+    let extent = i32::from(p.extent.unwrap_or(11));
+    let radius = positive("scene.sea_of_balls.radius", p.radius.unwrap_or(0.2))?;
+    let density = p.density.unwrap_or(1.0);
+    if !(0.0 ..= 1.0).contains(&density) {
+        return Err(Error::invalid("scene.sea_of_balls.density", format!("{density} is outside 0 ..= 1")));
+    }
+    // shares of the materials, they don't have to add up to 1. Older settings have them
+    // in [material]
+    let m = c.material.clone().unwrap_or_default();
+    let shares = [
+        p.diffuse_percent.or(m.diffuse_percent).unwrap_or(0.8),
+        p.metal_percent.or(m.metal_percent).unwrap_or(0.15),
+        p.dielectric_percent.or(m.dielectric_percent).unwrap_or(0.05),
+    ];
+    let total: f32 = shares.iter().sum();
+    if shares.iter().any(|s| *s < 0.0) || !(total.is_finite() && total > 0.0) {
+        return Err(Error::invalid("scene.sea_of_balls", "the material percents can't be negative or all 0"));
+    }
+    let (diffuse, metal) = (shares[0] / total, (shares[0] + shares[1]) / total);
+
+    let mut rng = rng(c);
+    let mut world = HittableList::default();
+    world.add(ground(c)?);
 
-    for a in (-110 .. 110).step_by(10) {
-        for b in (-110 .. 110).step_by(10) {
-            // generate only 20% of objects
-            if rand::rng().random::<f32>() < 0.0 {
+    for a in -extent .. extent {
+        for b in -extent .. extent {
+            // a ball in this fraction of the cells
+            if rng.random() >= density {
                 continue;
             }
-            let choose_mat: f32 = rand::rng().random();
+            let choose_mat: f32 = rng.random();
             let center = Point3d::new (
-                a as f32 * 0.1 + 0.9 * rand::rng().random::<f32>(),
-                0.2,
-                b as f32 * 0.1 + 0.9 * rand::rng().random::<f32>(),
+                a as f32 + 0.9 * rng.random(),
+                radius,
+                b as f32 + 0.9 * rng.random(),
             );
-            
-            let t = center.sub(Point3d::new(4.0, 0.2, 0.0)).as_vec3d();
+
+            // keep clear of the metal ball
+            let t = center.sub(Point3d::new(4.0, radius, 0.0)).as_vec3d();
             if t.length() > 0.9 {
-                match choose_mat {
-                    0.0 .. 0.8 => {
-                        // diffuse
-                        let albedo = Color{
-                            r: rand::rng().random::<f32>() * rand::rng().random::<f32>(),
-                            g: rand::rng().random::<f32>() * rand::rng().random::<f32>(),
-                            b: rand::rng().random::<f32>() * rand::rng().random::<f32>(),
-                        };
-                        
-                       /* 
-                        let c2 = Vec3d::new(
-                            0.0,
-                            rand::rng().random_range(0.0 .. 0.5),
-                            0.0,
-                        );
-                        
-                        let center2 = Point3d::from_vec3d(center.as_vec3d() + c2);
-                       */ 
-                        
-                        world.add(
-                            Hittable::Sphere(
-                                //Sphere::new_dynamic(center, center2, 0.2, Arc::new(MaterialEnum::Lambertian(Lambertian{albedo})))
-                                Sphere::new(center, 0.2, Arc::new(MaterialEnum::Lambertian(Lambertian{albedo})))
-                            )
-                        );
-                   
-                    },
-                    
-                    0.8 .. 0.95 => {
-                        // metal
-                        let albedo = Color{
-                            r: rand::rng().random_range(0.5 .. 1.0),
-                            g: rand::rng().random_range(0.5 .. 1.0),
-                            b: rand::rng().random_range(0.5 .. 1.0),
-                        };
-                        let fuzz = rand::rng().random_range(0.0 .. 0.5);
-                        
-                        world.add(
-                            Hittable::Sphere(
-                                Sphere::new(center, 0.2, Arc::new(MaterialEnum::Metal(Metal{albedo, fuzz})))
-                            )
-                        );
-                    },
-                    
-                    _ => {
-                        world.add(
-                            Hittable::Sphere(
-                                Sphere::new(center, 0.2, Arc::new(MaterialEnum::Dielectric(Dielectric{refraction_index: 1.5})))
-                            )
-                        );
-                    }
-                }
+                let material = if choose_mat < diffuse {
+                    let albedo = Color{
+                        r: rng.random() * rng.random(),
+                        g: rng.random() * rng.random(),
+                        b: rng.random() * rng.random(),
+                    };
+                    MaterialEnum::Lambertian(Lambertian{albedo})
+                } else if choose_mat < metal {
+                    let albedo = Color{
+                        r: rng.random_range(0.5 .. 1.0),
+                        g: rng.random_range(0.5 .. 1.0),
+                        b: rng.random_range(0.5 .. 1.0),
+                    };
+                    let fuzz = rng.random_range(0.0 .. 0.5);
+                    MaterialEnum::Metal(Metal{albedo, fuzz})
+                } else {
+                    MaterialEnum::Dielectric(Dielectric::new(1.5))
+                };
+                world.add(Hittable::Sphere(Sphere::new(center, radius, Arc::new(material))));
             }
         }
     }
-    
-    world.add(
-        Hittable::Sphere(
-            Sphere::new(Point3d::new(0.0, 1.0, 0.0), 1.0, Arc::new(MaterialEnum::Dielectric(Dielectric{refraction_index: 1.5})))
-        )
-    );
-    
-    world.add(
-        Hittable::Sphere(
-            Sphere::new(Point3d::new(-4.0, 1.0, 0.0), 1.0, Arc::new(MaterialEnum::Lambertian(Lambertian{albedo: Color{r: 0.4, g: 0.2, b: 0.1}})))
-        )
-    );
-  
-    world.add(
-        Hittable::Sphere(
-            Sphere::new(Point3d::new(4.0, 1.0, 0.0), 1.0, Arc::new(MaterialEnum::Metal(Metal{albedo: Color{r: 0.7, g: 0.6, b: 0.5}, fuzz: 0.0})))
-        )
-    );
-
-/*
-    let material_ground = Arc::new(Lambertian{albedo: Color{r: 0.8, g: 0.8, b: 0.0,}});
-    let material_center = Arc::new(Lambertian{albedo: Color{r: 0.1, g: 0.2, b: 0.5,}});
-    let material_left = Arc::new(Dielectric{refraction_index: 1.5});
-    let material_bubble = Arc::new(Dielectric{refraction_index: 1.5});
-    let material_right = Arc::new(Metal{albedo: Color{r: 0.8, g: 0.6, b: 0.2,}, fuzz: 1.0});
-
-    world.add(Sphere::new(Point3d::new(0.0, 0.0, -1.2), 0.5, material_center.clone()));
-
-    world.add(Sphere::new(Point3d::new(-1.0, 0.6, -2.0), 0.5, material_center.clone()));
-    world.add(Sphere::new(Point3d::new(5.0, 0.6, -5.0), 1.0, material_center.clone()));
-    
-    world.add(Sphere::new(Point3d::new(-1.0, 0.0, -1.0), 0.5, material_left.clone()));
-    world.add(Sphere::new(Point3d::new(3.0, 0.0, -1.0), 0.4, material_bubble.clone()));
-    
-   world.add(Sphere::new(Point3d::new(1.0, 0.0, -1.0), 0.5, material_right.clone()));
-    
-    world.add(Sphere::new(Point3d::new(0.0, -100.5, -1.0), 100.0, material_ground.clone()));
-*/
-
-    println!("Rendering World with {} hittable objects - using bounding box optimization", world.len());
-    
-    HittableList::new(Hittable::BvhNode(BvhNode::new(&mut world)))
-} 
+
+    world.add(Hittable::Sphere(Sphere::new(Point3d::new(0.0, 1.0, 0.0), 1.0, Arc::new(MaterialEnum::Dielectric(Dielectric::new(1.5))))));
+    world.add(Hittable::Sphere(Sphere::new(Point3d::new(-4.0, 1.0, 0.0), 1.0, lambertian([0.4, 0.2, 0.1]))));
+    world.add(Hittable::Sphere(Sphere::new(Point3d::new(4.0, 1.0, 0.0), 1.0, Arc::new(MaterialEnum::Metal(Metal{albedo: Color{r: 0.7, g: 0.6, b: 0.5}, fuzz: 0.0})))));
+    Ok(world)
+}
+
+fn cornell_box_view(_: &Settings) -> config::Camera {
+    config::Camera { aspect_ratio: Some(1.0), ..view([0.0, 0.0, 3.5], [0.0, 0.0, 0.0], 40.0) }
+}
+
+// The walls of the box from -1 to 1 are big spheres as in smallpt, flatter the bigger they
+// are. There are no area lights, the sky lights the box through the open front and top
+fn cornell_box(c: &Settings) -> Result<HittableList> {
+    let p = c.scene.as_ref().and_then(|s| s.cornell_box.clone()).unwrap_or_default();
+    let r = positive("scene.cornell_box.wall_radius", p.wall_radius.unwrap_or(100.0))?;
+    let white = lambertian(p.white.unwrap_or([0.73, 0.73, 0.73]));
+    let wall = |x: f32, y: f32, z: f32, m: Arc<MaterialEnum>| Hittable::Sphere(Sphere::new(Point3d::new(x * (r + 1.0), y * (r + 1.0), z * (r + 1.0)), r, m));
+
+    let mut world = HittableList::default();
+    world.add(wall(-1.0, 0.0, 0.0, lambertian(p.left.unwrap_or([0.65, 0.05, 0.05]))));
+    world.add(wall(1.0, 0.0, 0.0, lambertian(p.right.unwrap_or([0.12, 0.45, 0.15]))));
+    world.add(wall(0.0, -1.0, 0.0, white.clone()));
+    world.add(wall(0.0, 0.0, -1.0, white.clone()));
+    let left_ball = if p.glass.unwrap_or(true) { Arc::new(MaterialEnum::Dielectric(Dielectric::new(1.5))) } else { white.clone() };
+    world.add(Hittable::Sphere(Sphere::new(Point3d::new(-0.4, -0.6, -0.3), 0.4, left_ball)));
+    world.add(Hittable::Sphere(Sphere::new(Point3d::new(0.45, -0.65, 0.1), 0.35, white)));
+    Ok(world)
+}
+
+fn random_triangles_view(c: &Settings) -> config::Camera {
+    let e = c.scene.as_ref().and_then(|s| s.random_triangles.as_ref()).and_then(|p| p.extent).unwrap_or(4.0);
+    view([0.0, 0.5 * e + 1.0, 3.5 * e], [0.0, 1.2, 0.0], 30.0)
+}
+
+// Triangles with corners up to size from their center, the centers in a box from -extent
+// to extent across and up to 3 above the ground
+fn random_triangles(c: &Settings) -> Result<HittableList> {
+    let p = c.scene.as_ref().and_then(|s| s.random_triangles.clone()).unwrap_or_default();
+    let size = positive("scene.random_triangles.size", p.size.unwrap_or(0.6))?;
+    let extent = positive("scene.random_triangles.extent", p.extent.unwrap_or(4.0))?;
+
+    let mut rng = rng(c);
+    let mut world = HittableList::default();
+    world.add(ground(c)?);
+    for _ in 0 .. p.count.unwrap_or(200) {
+        let center = Vec3d::new(rng.random_range(-extent .. extent), rng.random_range(0.3 .. 3.0), rng.random_range(-extent .. extent));
+        let mut corner = || Point3d::from_vec3d(center.clone() + Vec3d::new(
+            rng.random_range(-size .. size),
+            rng.random_range(-size .. size),
+            rng.random_range(-size .. size),
+        ));
+        let (a, b, t) = (corner(), corner(), corner());
+        let material = if rng.random() < 0.8 {
+            lambertian([rng.random(), rng.random(), rng.random()])
+        } else {
+            let albedo = Color{r: rng.random_range(0.5 .. 1.0), g: rng.random_range(0.5 .. 1.0), b: rng.random_range(0.5 .. 1.0)};
+            Arc::new(MaterialEnum::Metal(Metal{albedo, fuzz: rng.random_range(0.0 .. 0.3)}))
+        };
+        let triangle = Triangle::new(a, b, t, material);
+        if triangle.is_degenerate() {
+            return Err(Error::invalid("scene.random_triangles.size", format!("{size} is too small, the triangles have no area")));
+        }
+        world.add(Hittable::Triangle(triangle));
+    }
+    Ok(world)
+}
+
+fn forest_view(c: &Settings) -> config::Camera {
+    let e = c.scene.as_ref().and_then(|s| s.forest.as_ref()).and_then(|p| p.extent).unwrap_or(10.0);
+    view([0.0, 0.3 * e + 1.0, 2.2 * e], [0.0, 1.0, 0.0], 40.0)
+}
+
+// A fir of two cones of triangles on a square trunk, 3.4 high, standing on the origin
+fn tree() -> Hittable {
+    const SIDES: usize = 10;
+    let mut tree = HittableList::default();
+    let bark = lambertian([0.3, 0.18, 0.08]);
+    let needles = lambertian([0.08, 0.3, 0.1]);
+    let point = |x: f32, y: f32, z: f32| Point3d::new(x, y, z);
+
+    let w = 0.12;
+    let corners = [(-w, -w), (w, -w), (w, w), (-w, w)];
+    for i in 0 .. 4 {
+        let ((x0, z0), (x1, z1)) = (corners[i], corners[(i + 1) % 4]);
+        tree.add(Hittable::Triangle(Triangle::new(point(x0, 0.0, z0), point(x1, 0.0, z1), point(x1, 1.2, z1), bark.clone())));
+        tree.add(Hittable::Triangle(Triangle::new(point(x0, 0.0, z0), point(x1, 1.2, z1), point(x0, 1.2, z0), bark.clone())));
+    }
+    for (base, top, radius) in [(0.8, 2.6, 1.0), (1.8, 3.4, 0.7)] {
+        for i in 0 .. SIDES {
+            let angle = |i: usize| i as f32 / SIDES as f32 * 2.0 * PI;
+            let (a0, a1) = (angle(i), angle(i + 1));
+            tree.add(Hittable::Triangle(Triangle::new(
+                point(radius * a0.cos(), base, radius * a0.sin()),
+                point(0.0, top, 0.0),
+                point(radius * a1.cos(), base, radius * a1.sin()),
+                needles.clone(),
+            )));
+        }
+    }
+    Hittable::BvhNode(BvhNode::new(&mut tree))
+}
+
+// Trees at random places from -extent to extent, scaled by up to scale_variation either way
+fn forest(c: &Settings) -> Result<HittableList> {
+    let p = c.scene.as_ref().and_then(|s| s.forest.clone()).unwrap_or_default();
+    let extent = positive("scene.forest.extent", p.extent.unwrap_or(10.0))?;
+    let variation = p.scale_variation.unwrap_or(0.3);
+    if !(0.0 .. 1.0).contains(&variation) {
+        return Err(Error::invalid("scene.forest.scale_variation", format!("{variation} is outside 0 .. 1")));
+    }
+
+    let mut rng = rng(c);
+    let mut world = HittableList::default();
+    world.add(ground(c)?);
+    let tree = Arc::new(tree());
+    for _ in 0 .. p.trees.unwrap_or(100) {
+        let offset = Vec3d::new(rng.random_range(-extent .. extent), 0.0, rng.random_range(-extent .. extent));
+        let scale = 1.0 + variation * (2.0 * rng.random() - 1.0);
+        world.add(Hittable::Instance(Instance::new(tree.clone(), offset, scale)));
+    }
+    Ok(world)
+}
+
+fn material_grid_columns(c: &Settings) -> u8 {
+    c.scene.as_ref().and_then(|s| s.material_grid.as_ref()).and_then(|p| p.columns).unwrap_or(5).max(1)
+}
+
+fn material_grid_view(c: &Settings) -> config::Camera {
+    // far enough for all columns in a 16:9 image
+    let distance = ((f32::from(material_grid_columns(c)) / 2.0 + 1.0) / 0.47).max(12.0);
+    view([0.0, 2.5, distance], [0.0, 2.5, 0.0], 30.0)
+}
+
+// A row per material, roughness (or fuzz) from 0 on the left to 1 on the right
+fn material_grid(c: &Settings) -> Result<HittableList> {
+    let p = c.scene.as_ref().and_then(|s| s.material_grid.clone()).unwrap_or_default();
+    let columns = material_grid_columns(c);
+    let [r, g, b] = p.base_color.unwrap_or([0.8, 0.3, 0.2]);
+    let base_color = Color{r, g, b};
+    let (eta, k) = conductor_preset("gold").unwrap_or_default();
+
+    let principled = |metallic: f32, roughness: f32| MaterialEnum::Principled(Box::new(Principled{
+        base_color: Texture::Solid(base_color),
+        metallic: Texture::constant(metallic),
+        roughness: Texture::constant(roughness),
+        specular: Texture::constant(0.5),
+        clearcoat: Texture::constant(0.0),
+        clearcoat_roughness: 0.1,
+        sheen: Texture::constant(0.0),
+        transmission: Texture::constant(0.0),
+        ior: 1.5,
+        anisotropy: 0.0,
+    }));
+    let rows: [&dyn Fn(f32) -> MaterialEnum; 5] = [
+        &|t| principled(0.0, t),
+        &|t| principled(1.0, t),
+        &|t| MaterialEnum::Metal(Metal{albedo: base_color, fuzz: t}),
+        &|t| MaterialEnum::RoughConductor(RoughConductor{eta, k, distribution: Ggx::new(t, 0.0)}),
+        &|t| MaterialEnum::RoughDielectric(RoughDielectric{refraction_index: 1.5, distribution: Ggx::new(t, 0.0), absorption: Color::default()}),
+    ];
+
+    let mut world = HittableList::default();
+    world.add(ground(c)?);
+    for (row, material) in rows.iter().enumerate() {
+        for column in 0 .. columns {
+            let t = f32::from(column) / f32::from(columns - 1).max(1.0);
+            let center = Point3d::new(f32::from(column) - f32::from(columns - 1) / 2.0, 0.5 + row as f32, 0.0);
+            world.add(Hittable::Sphere(Sphere::new(center, 0.45, Arc::new(material(t)))));
+        }
+    }
+    Ok(world)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{camera::Ray, check::lint, config::Settings, hit_record::Hit, interval::Interval, material::MaterialEnum, scene::{absorption, replace_ground_material, Generator, GENERATORS}, vec3d::Vec3d, Point3d};
+
+    const CONFIG: &str = include_str!("../config.toml");
+
+    fn load(from: &str, to: &str) -> Settings {
+        assert!(CONFIG.contains(from));
+        Settings::from_toml(&CONFIG.replacen(from, to, 1)).unwrap()
+    }
+
+    #[test]
+    fn absorption_falls_with_the_transmittance_distance() {
+        let t = Some([0.5, 0.8, 1.0]);
+        let near = absorption("ground.dielectric", t, Some(1.0)).unwrap();
+        let far = absorption("ground.dielectric", t, Some(2.0)).unwrap();
+        assert!((2.0 * far.r - near.r).abs() < 1e-6 && (2.0 * far.g - near.g).abs() < 1e-6);
+        assert_eq!(far.b, 0.0);
+        for d in [0.0, -1.0] {
+            let e = absorption("ground.dielectric", t, Some(d)).unwrap_err();
+            assert!(e.to_string().starts_with("ground.dielectric.transmittance_distance:"), "{e}");
+        }
+    }
+
+    #[test]
+    fn every_generator_builds_with_a_valid_view() {
+        let c = load("", "");
+        for g in GENERATORS {
+            let mut c = c.clone();
+            c.scene.as_mut().unwrap().generator = Some(g.name.to_string());
+            assert!((g.objects)(&c).unwrap().len() > 1, "{}", g.name);
+            // only the unused ground materials
+            assert!(lint(&c).iter().all(|f| f.message.starts_with("unused")), "{}: {:?}", g.name, lint(&c));
+        }
+        assert!(Generator::find("sea of balls").is_err());
+    }
+
+    #[test]
+    fn sea_of_balls_reads_its_parameters() {
+        // the ground and the three big balls are left
+        let c = load("density = 1.0", "density = 0.0");
+        assert_eq!((GENERATORS[0].objects)(&c).unwrap().len(), 4);
+
+        let c = load("extent = 11", "extent = 2");
+        assert!((GENERATORS[0].objects)(&c).unwrap().len() <= 4 + 16);
+
+        let c = load("# diffuse_percent = 0.8", "diffuse_percent = -0.8");
+        let e = (GENERATORS[0].objects)(&c).err().unwrap();
+        assert!(e.to_string().starts_with("scene.sea_of_balls:"), "{e}");
+    }
+
+    #[test]
+    fn the_old_material_section_still_mixes_the_balls() {
+        // only metal balls, and the three big ones
+        let c = load("[scene]\n", "[material]\ndiffuse_percent = 0.0\nmetal_percent = 1.0\ndielectric_percent = 0.0\n\n[scene]\n");
+        let world = GENERATORS[0].build(&c).unwrap();
+        let mut metals = 0;
+        world.for_each_material(&mut |m| metals += usize::from(matches!(**m, MaterialEnum::Metal(_))));
+        assert!(metals > 400, "{metals}");
+    }
+
+    #[test]
+    fn the_ground_material_is_replaced_in_the_built_world() {
+        let c = load("density = 1.0", "density = 0.0");
+        let world = GENERATORS[0].build(&c).unwrap();
+        let metal = load("material = \"diffuse\"", "material = \"metal\"");
+        let world = replace_ground_material(&world, &metal.ground).unwrap();
+
+        let down = Ray::new(Point3d::new(0.0, 10.0, 20.0), Vec3d::new(0.0, -1.0, 0.0), None);
+        let (_, material) = world.hit(&down, Interval::new(0.001, f32::INFINITY)).unwrap();
+        assert!(matches!(*material, MaterialEnum::Metal(_)));
+    }
+}
@@ -0,0 +1,300 @@
+use core::f32;
+
+use crate::{config, error::{unknown, Result}, sampler, vec3d::Vec3d, Color};
+
+const SOMECOLOR_VEC: Vec3d = Vec3d{x: 0.5, y: 0.7, z: 1.0};
+const BLACK_VEC: Vec3d = Vec3d{x: 0.0, y: 0.0, z: 0.0};
+
+// Wavelengths (in micrometers) used to attenuate the sun color for the r, g and b channels
+const SUN_WAVELENGTHS: [f32; 3] = [0.680, 0.550, 0.440];
+
+/// Radiance arriving from directions that hit nothing
+pub enum Sky {
+    /// the blue to white gradient of the book
+    Gradient,
+    /// the Preetham sky and sun
+    Daylight(Daylight),
+    /// the same radiance from every direction, a white one is the furnace of energy tests
+    Uniform(Color),
+}
+
+impl Sky {
+    /// The configured sky, the blue gradient of the book if none is set
+    pub fn new(c: &Option<config::Sky>) -> Result<Sky> {
+        let Some(c) = c else {
+            return Ok(Sky::Gradient);
+        };
+
+        let sky = match c.model.as_str() {
+            "daylight" => {
+                let sun_direction = match c.sun_direction {
+                    Some(d) => Vec3d::unit(&Vec3d::new(d[0], d[1], d[2])),
+                    None => sun_direction_from_date(
+                        c.day_of_year.unwrap_or(172),
+                        c.solar_time.unwrap_or(12.0),
+                        c.latitude.unwrap_or(45.0),
+                    ),
+                };
+
+                Sky::Daylight(Daylight::new(
+                    sun_direction,
+                    c.turbidity.unwrap_or(3.0),
+                    c.intensity.unwrap_or(0.05),
+                    c.sun_irradiance.unwrap_or(3.0),
+                    c.sun_angular_radius.unwrap_or(0.2666),
+                ))
+            },
+            "uniform" => {
+                let r = c.radiance.unwrap_or([1.0, 1.0, 1.0]);
+                Sky::Uniform(Color{r: r[0], g: r[1], b: r[2]})
+            },
+            "gradient" => Sky::Gradient,
+            model => return Err(unknown("sky.model", "sky model", model, &["gradient", "daylight", "uniform"])),
+        };
+        Ok(sky)
+    }
+
+    // Radiance arriving from the given direction. The sun disk is scaled by sun_weight,
+    // which is below 1 when the sun was also sampled directly as a light
+    pub(crate) fn radiance(&self, direction: &Vec3d, sun_weight: f32) -> Color {
+        match self {
+            Sky::Gradient => {
+                let unit_direction = Vec3d::unit(direction);
+                let a = 0.5 * (unit_direction.y + 1.0);
+
+                let cv = BLACK_VEC * (1.0 - a) + SOMECOLOR_VEC * a;
+
+                Color{r: cv.x, g: cv.y, b: cv.z}
+            },
+            Sky::Daylight(daylight) => {
+                let sky = daylight.sky_radiance(direction);
+                if sun_weight > 0.0 && daylight.in_sun_disk(direction) {
+                    sky + daylight.sun_radiance * sun_weight
+                } else {
+                    sky
+                }
+            },
+            Sky::Uniform(radiance) => *radiance,
+        }
+    }
+
+    pub(crate) fn sun(&self) -> Option<&Daylight> {
+        match self {
+            Sky::Gradient | Sky::Uniform(_) => None,
+            Sky::Daylight(daylight) => Some(daylight),
+        }
+    }
+}
+
+/// Preetham, Shirley, Smits - "A Practical Analytic Model for Daylight" (1999).
+/// The scene is y-up, sun azimuth is measured in the xz plane starting from the x axis
+pub struct Daylight {
+    sun_direction: Vec3d,
+    sun_cos_max: f32,
+    sun_radiance: Color,
+    // Perez distribution coefficients for Y, x and y
+    perez_y: [f32; 5],
+    perez_cx: [f32; 5],
+    perez_cy: [f32; 5],
+    // values at the zenith divided by the Perez function at the zenith
+    zenith_y: f32,
+    zenith_cx: f32,
+    zenith_cy: f32,
+    intensity: f32,
+}
+
+impl Daylight {
+    /// The sky for a sun in the given direction, `sun_angular_radius` in degrees
+    pub fn new(sun_direction: Vec3d, turbidity: f32, intensity: f32, sun_irradiance: f32, sun_angular_radius: f32) -> Daylight {
+        let t = turbidity;
+        let theta_s = f32::acos(sun_direction.y.clamp(0.0, 1.0));
+
+        let perez_y = [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703];
+        let perez_cx = [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452];
+        let perez_cy = [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (f32::consts::PI - 2.0 * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * f32::tan(chi) - 0.2155 * t + 2.4192;
+
+        let th = [theta_s * theta_s * theta_s, theta_s * theta_s, theta_s, 1.0];
+        let poly = |k: [[f32; 4]; 3]| -> f32 {
+            let row = |r: [f32; 4]| r[0] * th[0] + r[1] * th[1] + r[2] * th[2] + r[3] * th[3];
+            t * t * row(k[0]) + t * row(k[1]) + row(k[2])
+        };
+        let zenith_cx = poly([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_cy = poly([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let sun_cos_max = f32::cos(sun_angular_radius.to_radians());
+        let sun_solid_angle = 2.0 * f32::consts::PI * (1.0 - sun_cos_max);
+        let sun_color = sun_transmittance(theta_s, turbidity);
+        let sun_radiance = sun_color * (sun_irradiance / sun_solid_angle);
+
+        Daylight {
+            sun_direction,
+            sun_cos_max,
+            sun_radiance,
+            zenith_y: zenith_y / perez(&perez_y, 0.0, theta_s),
+            zenith_cx: zenith_cx / perez(&perez_cx, 0.0, theta_s),
+            zenith_cy: zenith_cy / perez(&perez_cy, 0.0, theta_s),
+            perez_y,
+            perez_cx,
+            perez_cy,
+            intensity,
+        }
+    }
+
+    pub(crate) fn sky_radiance(&self, direction: &Vec3d) -> Color {
+        let d = Vec3d::unit(direction);
+        // the model is undefined below the horizon, so the horizon color is extended downwards
+        let cos_theta = d.y.max(0.01);
+        let cos_gamma = Vec3d::dot(&d, &self.sun_direction).clamp(-1.0, 1.0);
+        let theta = f32::acos(cos_theta);
+        let gamma = f32::acos(cos_gamma);
+
+        let lum = self.zenith_y * perez(&self.perez_y, theta, gamma);
+        let cx = self.zenith_cx * perez(&self.perez_cx, theta, gamma);
+        let cy = self.zenith_cy * perez(&self.perez_cy, theta, gamma);
+
+        xyy_to_rgb(cx, cy, lum * self.intensity)
+    }
+
+    pub(crate) fn in_sun_disk(&self, direction: &Vec3d) -> bool {
+        Vec3d::dot(&Vec3d::unit(direction), &self.sun_direction) >= self.sun_cos_max
+    }
+
+    pub(crate) fn sun_radiance(&self) -> Color {
+        self.sun_radiance
+    }
+
+    pub(crate) fn is_below_horizon(&self) -> bool {
+        self.sun_direction.y <= 0.0
+    }
+
+    // Uniformly samples a direction inside the sun cone, returns it with its pdf (per solid angle)
+    pub(crate) fn sample_sun(&self) -> (Vec3d, f32) {
+        let (u, v) = sampler::random_2d();
+
+        let cos_theta = 1.0 - u * (1.0 - self.sun_cos_max);
+        let sin_theta = f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta));
+        let phi = 2.0 * f32::consts::PI * v;
+
+        let (t, b) = Vec3d::basis(&self.sun_direction);
+        let direction = t * (sin_theta * f32::cos(phi))
+            + b * (sin_theta * f32::sin(phi))
+            + self.sun_direction.clone() * cos_theta;
+
+        (direction, self.sun_pdf())
+    }
+
+    pub(crate) fn sun_pdf(&self) -> f32 {
+        1.0 / (2.0 * f32::consts::PI * (1.0 - self.sun_cos_max))
+    }
+}
+
+fn perez(k: &[f32; 5], theta: f32, gamma: f32) -> f32 {
+    let cos_gamma = f32::cos(gamma);
+    (1.0 + k[0] * f32::exp(k[1] / f32::cos(theta)))
+        * (1.0 + k[2] * f32::exp(k[3] * gamma) + k[4] * cos_gamma * cos_gamma)
+}
+
+fn xyy_to_rgb(cx: f32, cy: f32, lum: f32) -> Color {
+    if cy <= 0.0 {
+        return Color{r: 0.0, g: 0.0, b: 0.0};
+    }
+    let x = cx / cy * lum;
+    let z = (1.0 - cx - cy) / cy * lum;
+    let y = lum;
+
+    Color{
+        r: f32::max(0.0, 3.2406 * x - 1.5372 * y - 0.4986 * z),
+        g: f32::max(0.0, -0.9689 * x + 1.8758 * y + 0.0415 * z),
+        b: f32::max(0.0, 0.0557 * x - 0.2040 * y + 1.0570 * z),
+    }
+}
+
+// Rayleigh and aerosol (Angstrom) transmittance of the sunlight through the atmosphere,
+// normalized so the sun at zenith is white
+fn sun_transmittance(theta_s: f32, turbidity: f32) -> Color {
+    let theta_deg = theta_s.to_degrees().min(93.0);
+    let relative_mass = 1.0 / (f32::cos(theta_s) + 0.15 * f32::powf(93.885 - theta_deg, -1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let alpha = 1.3;
+
+    let tau = |lambda: f32, mass: f32| {
+        let rayleigh = f32::exp(-0.008735 * f32::powf(lambda, -4.08) * mass);
+        let aerosol = f32::exp(-beta * f32::powf(lambda, -alpha) * mass);
+        rayleigh * aerosol
+    };
+    let channel = |lambda: f32| tau(lambda, relative_mass) / tau(lambda, 1.0);
+
+    Color{
+        r: channel(SUN_WAVELENGTHS[0]),
+        g: channel(SUN_WAVELENGTHS[1]),
+        b: channel(SUN_WAVELENGTHS[2]),
+    }
+}
+
+// Sun position for a day of the year (1..365), local solar time in hours and latitude in degrees
+pub(crate) fn sun_direction_from_date(day_of_year: u16, solar_time: f32, latitude: f32) -> Vec3d {
+    let j = f32::from(day_of_year);
+    let l = latitude.to_radians();
+    let declination = 0.4093 * f32::sin(2.0 * f32::consts::PI * (j - 81.0) / 368.0);
+    let hour_angle = f32::consts::PI * solar_time / 12.0;
+
+    let sin_elevation = f32::sin(l) * f32::sin(declination)
+        - f32::cos(l) * f32::cos(declination) * f32::cos(hour_angle);
+    let theta_s = f32::consts::FRAC_PI_2 - f32::asin(sin_elevation.clamp(-1.0, 1.0));
+    let phi_s = f32::atan2(
+        -f32::cos(declination) * f32::sin(hour_angle),
+        f32::cos(l) * f32::sin(declination) - f32::sin(l) * f32::cos(declination) * f32::cos(hour_angle),
+    );
+
+    Vec3d::new(
+        f32::sin(theta_s) * f32::cos(phi_s),
+        f32::cos(theta_s),
+        f32::sin(theta_s) * f32::sin(phi_s),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sky::{sun_direction_from_date, Daylight};
+    use crate::vec3d::Vec3d;
+
+    #[test]
+    fn sun_at_noon_is_highest() {
+        let morning = sun_direction_from_date(172, 9.0, 45.0);
+        let noon = sun_direction_from_date(172, 12.0, 45.0);
+        let midnight = sun_direction_from_date(172, 0.0, 45.0);
+
+        assert!(noon.y > morning.y);
+        assert!(midnight.y < 0.0);
+        // summer solstice at 45 degrees north: elevation is 90 - 45 + 23.45
+        assert!((f32::asin(noon.y).to_degrees() - 68.45).abs() < 0.5);
+    }
+
+    #[test]
+    fn sampled_sun_directions_are_in_disk() {
+        let d = Daylight::new(Vec3d::unit(&Vec3d::new(1.0, 1.0, 0.5)), 3.0, 0.05, 3.0, 0.5);
+        for _ in 0 .. 100 {
+            let (dir, pdf) = d.sample_sun();
+            assert!(d.in_sun_disk(&dir));
+            assert!(pdf > 0.0);
+        }
+    }
+
+    #[test]
+    fn zenith_is_bluer_than_horizon() {
+        let d = Daylight::new(Vec3d::unit(&Vec3d::new(1.0, 1.0, 0.0)), 3.0, 0.05, 3.0, 0.2666);
+        let zenith = d.sky_radiance(&Vec3d::new(0.0, 1.0, 0.0));
+        assert!(zenith.b > zenith.r);
+    }
+}
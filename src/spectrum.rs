@@ -0,0 +1,199 @@
+use std::ops::{Add, Mul};
+
+use crate::{sampler, Color};
+
+// Number of wavelengths carried by every path (the hero wavelength and its rotations)
+pub(crate) const SAMPLES: usize = 4;
+
+const LAMBDA_MIN: f32 = 380.0;
+const LAMBDA_MAX: f32 = 720.0;
+
+// Integral of the CIE y matching function, so a constant spectrum of 1 has luminance 1
+const CIE_Y_INTEGRAL: f32 = 106.856895;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Spectrum(pub(crate) [f32; SAMPLES]);
+
+impl Spectrum {
+    pub(crate) fn splat(v: f32) -> Spectrum {
+        Spectrum([v; SAMPLES])
+    }
+
+    // Smooth reflectance (or radiance) spectrum of an rgb color at the given wavelengths.
+    // The three basis functions sum up to one, so white stays flat and [0, 1] colors
+    // stay valid reflectances
+    pub(crate) fn from_rgb(c: Color, wavelengths: &Wavelengths) -> Spectrum {
+        let mut s = [0.0; SAMPLES];
+        for (i, l) in wavelengths.lambda.iter().enumerate() {
+            let wr = gaussian(*l, 610.0, 25.0);
+            let wg = gaussian(*l, 540.0, 25.0);
+            let wb = gaussian(*l, 450.0, 25.0);
+            s[i] = (c.r * wr + c.g * wg + c.b * wb) / (wr + wg + wb);
+        }
+        Spectrum(s)
+    }
+}
+
+impl Add for Spectrum {
+    type Output = Spectrum;
+
+    fn add(self, other: Spectrum) -> Spectrum {
+        let mut s = self.0;
+        s.iter_mut().zip(other.0).for_each(|(a, b)| *a += b);
+        Spectrum(s)
+    }
+}
+
+impl Mul for Spectrum {
+    type Output = Spectrum;
+
+    fn mul(self, other: Spectrum) -> Spectrum {
+        let mut s = self.0;
+        s.iter_mut().zip(other.0).for_each(|(a, b)| *a *= b);
+        Spectrum(s)
+    }
+}
+
+// Hero wavelength sampling (Wilkie et al. 2014): one uniformly sampled wavelength and
+// the others evenly rotated over the visible range
+pub(crate) struct Wavelengths {
+    pub(crate) lambda: [f32; SAMPLES],
+}
+
+impl Wavelengths {
+    pub(crate) fn sample() -> Wavelengths {
+        let u = sampler::random();
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; SAMPLES];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f32 / SAMPLES as f32).fract();
+            *l = LAMBDA_MIN + offset * range;
+        }
+        Wavelengths { lambda }
+    }
+
+    pub(crate) fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    // Only the hero wavelength keeps following a path after a wavelength dependent event
+    // (like dispersion), the mask keeps the estimate unbiased
+    pub(crate) fn hero_only() -> Spectrum {
+        let mut s = [0.0; SAMPLES];
+        s[0] = SAMPLES as f32;
+        Spectrum(s)
+    }
+
+    // Integrates the spectral radiance samples against the CIE matching functions and
+    // converts them to linear sRGB, normalized so a flat spectrum of 1 gives white
+    pub(crate) fn to_rgb(&self, s: &Spectrum) -> Color {
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        for (l, v) in self.lambda.iter().zip(s.0) {
+            let (cx, cy, cz) = cie_xyz(*l);
+            x += v * cx;
+            y += v * cy;
+            z += v * cz;
+        }
+        let scale = 1.0 / (SAMPLES as f32 * pdf * CIE_Y_INTEGRAL);
+        let c = xyz_to_rgb(x * scale, y * scale, z * scale);
+        Color{r: c.r / WHITE_RGB.r, g: c.g / WHITE_RGB.g, b: c.b / WHITE_RGB.b}
+    }
+}
+
+// Linear sRGB of the flat (equal energy) spectrum, used to white balance the output
+const WHITE_RGB: Color = Color{r: 1.2012, g: 0.9502, b: 0.9082};
+
+fn gaussian(x: f32, mu: f32, sigma: f32) -> f32 {
+    let t = (x - mu) / sigma;
+    f32::exp(-0.5 * t * t)
+}
+
+// Multi-lobe gaussian fit of the CIE 1931 matching functions (Wyman, Sloan, Shirley 2013)
+fn cie_xyz(lambda: f32) -> (f32, f32, f32) {
+    let g = |mu: f32, s1: f32, s2: f32| gaussian(lambda, mu, if lambda < mu { s1 } else { s2 });
+    let x = 1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+fn xyz_to_rgb(x: f32, y: f32, z: f32) -> Color {
+    Color{
+        r: 3.2406 * x - 1.5372 * y - 0.4986 * z,
+        g: -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        b: 0.0557 * x - 0.2040 * y + 1.0570 * z,
+    }
+}
+
+// Wavelength dependent index of refraction, wavelengths are in nanometers
+#[derive(Clone, Debug)]
+pub(crate) enum Dispersion {
+    Cauchy { a: f32, b: f32 },
+    // coefficients for the wavelength in micrometers
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    pub(crate) const PRESETS: &[&str] = &["bk7", "fused_silica", "diamond"];
+
+    pub(crate) fn preset(name: &str) -> Option<Dispersion> {
+        match name {
+            "bk7" => Some(Dispersion::Sellmeier {
+                b: [1.039_612, 0.231_792_3, 1.010_469],
+                c: [0.006_000_7, 0.020_017_9, 103.560_65],
+            }),
+            "fused_silica" => Some(Dispersion::Sellmeier {
+                b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+                c: [0.004_679_148, 0.013_512_06, 97.934],
+            }),
+            "diamond" => Some(Dispersion::Sellmeier {
+                b: [4.3356, 0.3306, 0.0],
+                c: [0.011_236, 0.030_625, 0.0],
+            }),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn ior(&self, lambda: f32) -> f32 {
+        match self {
+            Dispersion::Cauchy { a, b } => {
+                let l = lambda / 1000.0;
+                a + b / (l * l)
+            },
+            Dispersion::Sellmeier { b, c } => {
+                let l2 = (lambda / 1000.0) * (lambda / 1000.0);
+                let n2 = 1.0 + b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum::<f32>();
+                f32::sqrt(n2)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spectrum::{Dispersion, Spectrum, Wavelengths};
+    use crate::Color;
+
+    #[test]
+    fn white_round_trip() {
+        let mut sum = Color{r: 0.0, g: 0.0, b: 0.0};
+        let n = 20000;
+        for _ in 0 .. n {
+            let w = Wavelengths::sample();
+            let s = Spectrum::from_rgb(Color{r: 1.0, g: 1.0, b: 1.0}, &w);
+            sum = sum + w.to_rgb(&s);
+        }
+        let avg = sum * (1.0 / n as f32);
+        assert!((avg.r - 1.0).abs() < 0.03, "{avg:?}");
+        assert!((avg.g - 1.0).abs() < 0.03, "{avg:?}");
+        assert!((avg.b - 1.0).abs() < 0.03, "{avg:?}");
+    }
+
+    #[test]
+    fn blue_bends_more_than_red() {
+        let bk7 = Dispersion::preset("bk7").unwrap();
+        assert!((bk7.ior(587.6) - 1.5168).abs() < 1e-3);
+        assert!(bk7.ior(450.0) > bk7.ior(650.0));
+    }
+}
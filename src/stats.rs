@@ -0,0 +1,177 @@
+use std::{cell::Cell, sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+// Render counters. Every thread counts into its own cells and flush() adds them to the
+// counters of its render (after every row), so counting in the hot loops needs no
+// synchronization and renders running at the same time don't mix their counts
+#[derive(Clone, Copy)]
+pub(crate) enum Counter {
+    CameraRays,
+    // every traced path segment, the camera rays included
+    PathRays,
+    ShadowRays,
+    BvhNodeTests,
+    PrimitiveTests,
+}
+
+const COUNTERS: usize = 5;
+
+thread_local! {
+    static LOCAL: [Cell<u64>; COUNTERS] = const { [const { Cell::new(0) }; COUNTERS] };
+}
+
+// The counts of the render that finished last, for RenderStats::collect
+static LAST: Counters = Counters([const { AtomicU64::new(0) }; COUNTERS]);
+
+// The counts of one render
+#[derive(Default)]
+pub(crate) struct Counters([AtomicU64; COUNTERS]);
+
+impl Counters {
+    pub(crate) fn get(&self, c: Counter) -> u64 {
+        self.0[c as usize].load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reset(&self) {
+        self.0.iter().for_each(|c| c.store(0, Ordering::Relaxed));
+    }
+
+    // Makes these the counts RenderStats::collect reports
+    pub(crate) fn finish(&self) {
+        for (last, c) in LAST.0.iter().zip(&self.0) {
+            last.store(c.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+}
+
+pub(crate) fn count(c: Counter) {
+    LOCAL.with(|l| l[c as usize].set(l[c as usize].get() + 1));
+}
+
+pub(crate) fn flush(into: &Counters) {
+    LOCAL.with(|l| {
+        for (local, total) in l.iter().zip(&into.0) {
+            total.fetch_add(local.replace(0), Ordering::Relaxed);
+        }
+    });
+}
+
+/// Ray counts and timings of a render
+#[derive(Debug, Clone, Copy)]
+pub struct RenderStats {
+    /// rays from the camera
+    pub camera_rays: u64,
+    /// rays scattered by the materials
+    pub scattered_rays: u64,
+    /// rays towards the sun
+    pub shadow_rays: u64,
+    /// ray tests against the boxes of the BVH
+    pub bvh_node_tests: u64,
+    /// ray tests against the shapes
+    pub primitive_tests: u64,
+    /// time to build the scene and its BVH
+    pub build_time: Duration,
+    /// time to render
+    pub render_time: Duration,
+}
+
+impl RenderStats {
+    /// The counts of the render that finished last in this process, see
+    /// [`Camera::stats`](crate::Camera::stats) for those of a given camera
+    pub fn collect(build_time: Duration, render_time: Duration) -> RenderStats {
+        RenderStats::from_counters(&LAST, build_time, render_time)
+    }
+
+    pub(crate) fn from_counters(c: &Counters, build_time: Duration, render_time: Duration) -> RenderStats {
+        RenderStats {
+            camera_rays: c.get(Counter::CameraRays),
+            scattered_rays: c.get(Counter::PathRays).saturating_sub(c.get(Counter::CameraRays)),
+            shadow_rays: c.get(Counter::ShadowRays),
+            bvh_node_tests: c.get(Counter::BvhNodeTests),
+            primitive_tests: c.get(Counter::PrimitiveTests),
+            build_time,
+            render_time,
+        }
+    }
+
+    /// All rays traced
+    pub fn rays(&self) -> u64 {
+        self.camera_rays + self.scattered_rays + self.shadow_rays
+    }
+
+    /// Rays traced per second of the render time
+    pub fn rays_per_second(&self) -> f64 {
+        self.rays() as f64 / self.render_time.as_secs_f64().max(1e-9)
+    }
+
+    /// Path segments traced per camera ray, shadow rays not included
+    pub fn average_path_depth(&self) -> f64 {
+        (self.camera_rays + self.scattered_rays) as f64 / self.camera_rays.max(1) as f64
+    }
+
+    pub(crate) fn json(&self) -> String {
+        format!(concat!("{{\"event\":\"done\",\"camera_rays\":{},\"scattered_rays\":{},\"shadow_rays\":{},",
+            "\"rays_per_second\":{:.0},\"bvh_node_tests\":{},\"primitive_tests\":{},\"average_path_depth\":{:.3},",
+            "\"build_seconds\":{:.3},\"render_seconds\":{:.3}}}"),
+            self.camera_rays, self.scattered_rays, self.shadow_rays, self.rays_per_second(),
+            self.bvh_node_tests, self.primitive_tests, self.average_path_depth(),
+            self.build_time.as_secs_f64(), self.render_time.as_secs_f64())
+    }
+}
+
+impl std::fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Scene build (BVH): {:.2?}", self.build_time)?;
+        writeln!(f, "Rendering:         {:.2?}", self.render_time)?;
+        writeln!(f, "Rays:              {} ({} camera, {} scattered, {} shadow)",
+            self.rays(), self.camera_rays, self.scattered_rays, self.shadow_rays)?;
+        writeln!(f, "Rays per second:   {:.0}", self.rays_per_second())?;
+        writeln!(f, "BVH node tests:    {} ({:.1} per ray)", self.bvh_node_tests, self.bvh_node_tests as f64 / self.rays().max(1) as f64)?;
+        writeln!(f, "Primitive tests:   {} ({:.1} per ray)", self.primitive_tests, self.primitive_tests as f64 / self.rays().max(1) as f64)?;
+        write!(f, "Average path depth: {:.2}", self.average_path_depth())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{camera::{Camera, CameraView}, hit_record::HittableList, progress::Progress, scene::Scene, sky::Sky, stats::{count, flush, Counter, Counters}, vec3d::Vec3d, Point3d};
+
+    #[test]
+    fn counts_reach_the_totals_when_flushed() {
+        let counters = Arc::new(Counters::default());
+        count(Counter::ShadowRays);
+        count(Counter::ShadowRays);
+        let c = counters.clone();
+        std::thread::spawn(move || {
+            count(Counter::ShadowRays);
+            flush(&c);
+        }).join().unwrap();
+        assert_eq!(counters.get(Counter::ShadowRays), 1);
+        flush(&counters);
+        assert_eq!(counters.get(Counter::ShadowRays), 3);
+    }
+
+    #[test]
+    fn a_second_render_reports_only_its_own_rays() {
+        let render = || {
+            let view = CameraView {
+                vfov: 40.0,
+                lookfrom: Point3d::new(0.0, 0.0, 1.0),
+                lookat: Point3d::new(0.0, 0.0, 0.0),
+                vup: Vec3d::new(0.0, 1.0, 0.0),
+                defocus_angle: 0.0,
+                focus_dist: 1.0,
+            };
+            let camera = Arc::new(Camera::initialize(2.0, 8, 4, 3, view));
+            let scene = Scene { world: HittableList::default(), sky: Sky::Gradient };
+            Camera::render(camera.clone(), Arc::new(scene), 2, &mut Progress::quiet()).unwrap();
+            camera.stats(Duration::ZERO, Duration::from_secs(1))
+        };
+        let first = render();
+        let second = render();
+        assert_eq!(first.camera_rays, 8 * 4 * 3);
+        assert_eq!(second.camera_rays, first.camera_rays);
+        assert_eq!(second.rays(), first.rays());
+    }
+}
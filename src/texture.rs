@@ -0,0 +1,39 @@
+use crate::{hit_record::HitRecord, Color};
+
+/// A material parameter varying over the surface
+pub enum Texture {
+    /// the same color everywhere
+    Solid(Color),
+    /// 3d checker pattern in world space, scale is the size of a single cube
+    Checker {
+        /// size of a cube
+        scale: f32,
+        /// color of the cubes whose indices add up to an even number
+        even: Color,
+        /// color of the other cubes
+        odd: Color,
+    },
+}
+
+impl Texture {
+    /// A gray of v everywhere, for the scalar parameters
+    pub fn constant(v: f32) -> Texture {
+        Texture::Solid(Color{r: v, g: v, b: v})
+    }
+
+    pub(crate) fn value(&self, hr: &HitRecord) -> Color {
+        match self {
+            Texture::Solid(c) => *c,
+            Texture::Checker { scale, even, odd } => {
+                let p = &hr.point;
+                let sum = (p[0] / scale).floor() as i32 + (p[1] / scale).floor() as i32 + (p[2] / scale).floor() as i32;
+                if sum % 2 == 0 { *even } else { *odd }
+            }
+        }
+    }
+
+    // Scalar parameters (roughness, metallic...) read the red channel
+    pub(crate) fn scalar(&self, hr: &HitRecord) -> f32 {
+        self.value(hr).r
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{config, error::{unknown, Result}, interval::Interval, sampler, Color};
+
+const INTENSITY: Interval = Interval {
+    min: 0.000,
+    max: 0.999,
+};
+
+/// Tone mapping curve from radiance to display values
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Operator {
+    /// hard clip at 1, the original look
+    #[default]
+    Clamp,
+    /// L / (1 + L) on the luminance, never reaches white
+    Reinhard,
+    /// Reinhard that maps the white luminance to 1
+    ExtendedReinhard,
+    /// Hable's Uncharted 2 curve
+    Filmic,
+    /// Narkowicz's fit of the ACES reference rendering transform
+    Aces,
+}
+
+/// Turns the linear radiance of the render into display pixels: exposure, tone mapping
+/// and the sRGB transfer function, used by both the image writer and the preview
+#[derive(Debug, Clone, Copy)]
+pub struct ToneMapping {
+    /// exposure compensation in stops
+    pub exposure: f32,
+    /// the curve
+    pub operator: Operator,
+    /// luminance mapped to white by the extended Reinhard and filmic operators
+    pub white: f32,
+    /// adds up to one level of noise to hide banding in smooth gradients
+    pub dither: bool,
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping { exposure: 0.0, operator: Operator::Clamp, white: 4.0, dither: false }
+    }
+}
+
+impl ToneMapping {
+    /// The tone mapping of the `[tonemap]` settings, none is [`ToneMapping::default`]
+    pub fn new(c: &Option<config::ToneMapping>) -> Result<ToneMapping> {
+        let Some(c) = c else {
+            return Ok(ToneMapping::default());
+        };
+        let operator = match c.operator.as_str() {
+            "clamp" => Operator::Clamp,
+            "reinhard" => Operator::Reinhard,
+            "extended_reinhard" => Operator::ExtendedReinhard,
+            "filmic" => Operator::Filmic,
+            "aces" => Operator::Aces,
+            name => return Err(unknown("tonemap.operator", "operator", name, &["clamp", "reinhard", "extended_reinhard", "filmic", "aces"])),
+        };
+        Ok(ToneMapping {
+            exposure: c.exposure.unwrap_or(0.0),
+            operator,
+            white: c.white.unwrap_or(4.0).max(1e-3),
+            dither: c.dither.unwrap_or(false),
+        })
+    }
+
+    // Display referred color in [0, 1] before the transfer function
+    pub(crate) fn map(&self, c: &Color) -> Color {
+        let c = *c * f32::exp2(self.exposure);
+        let c = Color{r: c.r.max(0.0), g: c.g.max(0.0), b: c.b.max(0.0)};
+
+        match self.operator {
+            Operator::Clamp => c,
+            Operator::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+            Operator::ExtendedReinhard => {
+                let w2 = self.white * self.white;
+                scale_luminance(c, |l| l * (1.0 + l / w2) / (1.0 + l))
+            },
+            Operator::Filmic => {
+                let white_scale = 1.0 / hable(self.white);
+                per_channel(c, |x| hable(2.0 * x) * white_scale)
+            },
+            Operator::Aces => per_channel(c, aces),
+        }
+    }
+
+    // 8 bit sRGB encoded pixel at x, y, the position only seeds the dithering
+    pub(crate) fn encode(&self, c: &Color, x: u32, y: u32) -> [u8; 3] {
+        let c = self.map(c);
+        let noise = if self.dither {
+            let h = sampler::hash(x ^ sampler::hash(y));
+            // triangular noise in (-1, 1) levels from two uniform numbers
+            (h & 0xffff) as f32 / 65536.0 - (h >> 16) as f32 / 65536.0
+        } else {
+            0.0
+        };
+        let quantize = |v: f32| {
+            let v = linear_to_srgb(INTENSITY.clamp(v)) + noise / 256.0;
+            (256.0 * INTENSITY.clamp(v)) as u8
+        };
+        [quantize(c.r), quantize(c.g), quantize(c.b)]
+    }
+}
+
+fn scale_luminance(c: Color, curve: impl Fn(f32) -> f32) -> Color {
+    let l = c.luminance();
+    if l <= 0.0 {
+        return c;
+    }
+    c * (curve(l) / l)
+}
+
+fn per_channel(c: Color, curve: impl Fn(f32) -> f32) -> Color {
+    Color{r: curve(c.r), g: curve(c.g), b: curve(c.b)}
+}
+
+fn hable(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn aces(x: f32) -> f32 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    (x * (a * x + b)) / (x * (c * x + d) + e)
+}
+
+// The sRGB opto-electronic transfer function (IEC 61966-2-1)
+pub(crate) fn linear_to_srgb(l: f32) -> f32 {
+    if l <= 0.0031308 {
+        12.92 * l.max(0.0)
+    } else {
+        1.055 * l.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tonemap::{linear_to_srgb, Operator, ToneMapping};
+    use crate::Color;
+
+    #[test]
+    fn srgb_transfer() {
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-6);
+        assert!((linear_to_srgb(0.18) - 0.4614).abs() < 1e-3);
+        // both segments meet at the threshold
+        assert!((linear_to_srgb(0.0031308) - linear_to_srgb(0.0031309)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn operators_keep_black_and_stay_monotonic() {
+        let operators = [Operator::Clamp, Operator::Reinhard, Operator::ExtendedReinhard, Operator::Filmic, Operator::Aces];
+        for operator in operators {
+            let tm = ToneMapping { operator, ..ToneMapping::default() };
+            let black = tm.map(&Color{r: 0.0, g: 0.0, b: 0.0});
+            assert!(black.r.abs() < 1e-6, "{operator:?}");
+            let mut last = -1.0;
+            for i in 0 .. 100 {
+                let v = tm.map(&Color{r: i as f32 * 0.1, g: 0.0, b: 0.0}).r;
+                assert!(v >= last, "{operator:?}");
+                last = v;
+            }
+        }
+    }
+
+    #[test]
+    fn extended_reinhard_maps_white_to_one() {
+        let tm = ToneMapping { operator: Operator::ExtendedReinhard, white: 4.0, ..ToneMapping::default() };
+        let c = tm.map(&Color{r: 4.0, g: 4.0, b: 4.0});
+        assert!((c.g - 1.0).abs() < 1e-4, "{c:?}");
+    }
+}
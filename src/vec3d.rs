@@ -1,23 +1,24 @@
 use std::ops::{Index, Add, Sub, Mul, Div};
-use rand::Rng;
 
+use crate::sampler;
+
+/// A direction or an offset in the scene
 #[derive(Debug, PartialEq, Default, Clone)]
 pub struct  Vec3d {
+    /// x
     pub x: f32,
+    /// y
     pub y: f32,
+    /// z
     pub z: f32
 }
 
+// Panics on an axis past 2 like a slice does, use get() for axes that come from data
 impl Index<usize> for Vec3d {
     type Output = f32;
 
     fn index(&self, i:usize) -> &f32 {
-        match i {
-            0 => &self.x,
-            1 => &self.y,
-            2 => &self.z,
-            _ => panic!("Index out of bound for Vec3d, should be in range 0..2")
-        }
+        self.get(i).unwrap_or_else(|| panic!("Axis {i} out of range for Vec3d, should be in range 0..=2"))
     }
 }
 
@@ -74,38 +75,50 @@ impl Div<f32> for Vec3d {
 }
 
 impl Vec3d {
+    /// The coordinate along the axis 0 (x), 1 (y) or 2 (z), None for other axes
+    pub fn get(&self, axis: usize) -> Option<&f32> {
+        match axis {
+            0 => Some(&self.x),
+            1 => Some(&self.y),
+            2 => Some(&self.z),
+            _ => None,
+        }
+    }
+
+    /// The vector x, y, z
     pub fn new(x: f32, y: f32, z: f32) -> Vec3d {
         Vec3d{x, y, z}
     }
 
+    /// A vector with every coordinate random in [0, 1)
     pub fn _random() -> Vec3d {
         Vec3d::new(
-            rand::rng().random_range(0.0..1.0),
-            rand::rng().random_range(0.0..1.0),
-            rand::rng().random_range(0.0..1.0)
+            sampler::random(),
+            sampler::random(),
+            sampler::random()
         )
     }
 
+    /// A vector with every coordinate random in [i, j)
     pub fn random_range(i: f32, j: f32) -> Vec3d {
         Vec3d::new(
-            rand::rng().random_range(i .. j),
-            rand::rng().random_range(i .. j),
-            rand::rng().random_range(i .. j),
+            i + (j - i) * sampler::random(),
+            i + (j - i) * sampler::random(),
+            i + (j - i) * sampler::random(),
         )
     }
 
-    pub fn clone(&self) -> Vec3d {
-        Vec3d::new(self.x, self.y, self.z)
-    }
-    
+    /// The squared length
     pub fn length_squared(&self) -> f32 {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
+    /// The length
     pub fn length(&self) -> f32 {
         self.length_squared().sqrt()
     }
 
+    /// True if every coordinate is close to 0
     pub fn near_zero(&self) -> bool {
         let eps = 1e-8f32;
         (self.x.abs() < eps) && (self.y.abs() < eps) && (self.z.abs() < eps)
@@ -117,21 +130,23 @@ impl Vec3d {
         self
     }*/
 
+    /// v scaled to length 1
     pub fn unit(v: &Vec3d) -> Vec3d {
         let l = v.length();
         Vec3d::new(v.x / l, v.y / l, v.z / l)
     }
 
+    /// A random direction, uniform over the sphere
     pub fn random_unit() -> Vec3d {
-        loop {
-            let p = &Self::random_range(-1.0, 1.0);
-            let lensq = p.length_squared();
-            if 1e-10 < lensq && lensq <= 1.0 {
-                return Vec3d::unit(p);
-            }
-        }
+        // maps a 2d sample onto the sphere, so stratified samples stay stratified
+        let (u, v) = sampler::random_2d();
+        let z = 1.0 - 2.0 * u;
+        let r = f32::sqrt(f32::max(0.0, 1.0 - z * z));
+        let phi = 2.0 * std::f32::consts::PI * v;
+        Vec3d::new(r * f32::cos(phi), r * f32::sin(phi), z)
     }
 
+    /// A random direction on the side of the normal
     pub fn _random_on_hemisphere(normal: &Vec3d) -> Vec3d {
         let p = &Vec3d::random_unit();
         if Self::dot(p, normal) > 0.0 {
@@ -141,35 +156,43 @@ impl Vec3d {
         }
     }
 
+    /// A random point of the unit disk in the xy plane
     pub fn random_in_unit_disk() -> Vec3d {
-        loop {
-            let p = Vec3d::new(
-                rand::rng().random_range(-1.0 .. 1.0),
-                rand::rng().random_range(-1.0 .. 1.0),
-                0.0
-            );
-            if p.length_squared() < 1.0 {
-                return p;
-            }
+        // concentric mapping of a 2d sample (Shirley, Chiu 1997)
+        let (u, v) = sampler::random_2d();
+        let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3d::new(0.0, 0.0, 0.0);
         }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, std::f32::consts::FRAC_PI_4 * (b / a))
+        } else {
+            (b, std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (a / b))
+        };
+        Vec3d::new(r * f32::cos(theta), r * f32::sin(theta), 0.0)
     }
 
+    /// v1 + v2
     pub fn add(v1: &Vec3d, v2: &Vec3d) -> Vec3d {
         Vec3d { x: v1.x + v2.x, y: v1.y + v2.y, z: v1.z + v2.z }
     }
     
+    /// v1 - v2
     pub fn sub(v1: &Vec3d, v2: &Vec3d) -> Vec3d {
         Vec3d { x: v1.x - v2.x, y: v1.y - v2.y, z: v1.z - v2.z }
     }
 
+    /// v scaled by t
     pub fn mul(v: &Vec3d, t: f32) -> Vec3d {
         Vec3d{x: v.x *t, y: v.y * t, z: v.z * t}
     }
 
+    /// The dot product
     pub fn dot(v: &Vec3d, u: &Vec3d) -> f32 {
         v.x * u.x + v.y * u.y + v.z * u.z
     }
 
+    /// The cross product
     pub fn cross(u: &Vec3d, v: &Vec3d) -> Vec3d {
         Vec3d::new(
             u.y * v.z - u.z * v.y,
@@ -178,11 +201,22 @@ impl Vec3d {
         )
     }
 
+    /// Two unit vectors forming an orthonormal basis together with the unit vector n
+    pub fn basis(n: &Vec3d) -> (Vec3d, Vec3d) {
+        let a = if n.x.abs() > 0.9 { Vec3d::new(0.0, 1.0, 0.0) } else { Vec3d::new(1.0, 0.0, 0.0) };
+        let b = Vec3d::unit(&Vec3d::cross(n, &a));
+        let t = Vec3d::cross(&b, n);
+        (t, b)
+    }
+
+    /// v mirrored at the plane of the unit normal n
     pub fn reflect(v: &Vec3d, n: &Vec3d) -> Vec3d {
         let d2 = 2.0 * Self::dot(v, n);
         Self::sub(v, &Self::mul(n, d2))
     }
 
+    /// The unit vector uv refracted through the surface of the unit normal n, etai_over_etat
+    /// is the ratio of the refraction indices
     pub fn refract(uv: Vec3d, n: Vec3d, etai_over_etat: f32) -> Vec3d {
         let cos_theta = f32::min(Vec3d::dot(&Vec3d::mul(&uv.clone(), -1.0), &n.clone()), 1.0);
         let r_out_perp = Vec3d::mul(&(uv + Vec3d::mul(&n.clone(), cos_theta)), etai_over_etat);
@@ -0,0 +1,338 @@
+use std::{fs, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread, time::{Duration, Instant, SystemTime}};
+
+use raylib::prelude::*;
+use raytracer::{check_file, config, replace_ground_material, Camera, CameraView, Color as Radiance, Generator, HittableList, Image, Progress, RenderOptions, Result, Scene, Settings, Severity, Sky, ToneMapping};
+
+use crate::navigation::{self, HELP};
+
+// How often the settings file is looked at
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+const FONT_SIZE: i32 = 10;
+// Where P saves the camera
+const CAMERA_FILE: &str = "camera.toml";
+// How long a message stays in the corner
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+
+// What the render thread renders, replaced as a whole when the settings change
+#[derive(Clone)]
+struct Job {
+    scene: Arc<Scene>,
+    view: config::Camera,
+    width: u16,
+    max_depth: u8,
+    passes: u16,        // samples_per_pixel, the render stops there
+    threads: u8,
+    options: RenderOptions,
+}
+
+// The sum of the passes rendered for job number `job`
+struct Film {
+    job: u64,
+    width: u16,
+    sum: Vec<Radiance>,
+    passes: u32,
+}
+
+struct Shared {
+    job: Mutex<(u64, Job)>,
+    film: Mutex<Film>,
+    error: Mutex<Option<String>>,
+    open: AtomicBool,
+}
+
+// The settings in use and what was built from them, so a change rebuilds only what it touches
+struct Live {
+    c: Settings,
+    scene_name: Option<String>,     // raytracer --scene, wins over the file
+    generator: &'static Generator,
+    world: HittableList,
+    scene: Arc<Scene>,
+    view: config::Camera,
+    options: RenderOptions,
+    tm: ToneMapping,
+}
+
+impl Live {
+    fn new(c: Settings, scene_name: Option<String>) -> Result<Live> {
+        let generator = Generator::from_settings(&c)?;
+        let world = generator.build(&c)?;
+        let scene = Arc::new(Scene { world: world.clone(), sky: Sky::new(&c.sky)? });
+        Ok(Live {
+            view: generator.camera(&c),
+            options: RenderOptions::from_settings(&c)?,
+            tm: ToneMapping::new(&c.tonemap)?,
+            c, scene_name, generator, world, scene,
+        })
+    }
+
+    fn job(&self) -> Job {
+        Job {
+            scene: self.scene.clone(),
+            view: self.view.clone(),
+            width: self.c.width,
+            max_depth: self.c.max_depth,
+            passes: self.c.samples_per_pixel.max(1),
+            threads: if self.c.multithread_enabled { self.c.threads } else { 1 },
+            options: self.options.clone(),
+        }
+    }
+
+    // The view moved in the window, the aspect ratio stays that of the settings
+    fn set_view(&mut self, view: &CameraView) {
+        let aspect_ratio = self.view.aspect_ratio;
+        self.view = view.to_settings();
+        self.view.aspect_ratio = aspect_ratio;
+    }
+
+    fn reset_view(&mut self) {
+        self.view = self.generator.camera(&self.c);
+    }
+
+    // Reads the settings again and rebuilds what changed. The job to start over with if
+    // the render changed, the error lines to show if the new settings don't load or build
+    fn reload(&mut self, path: &str) -> std::result::Result<Option<Job>, Vec<String>> {
+        let findings = check_file(path).map_err(|e| vec![e.to_string()])?;
+        let errors: Vec<String> = findings.iter().filter(|f| f.severity == Severity::Error).map(|f| match f.line {
+            Some(line) => format!("{path}:{line}: {f}"),
+            None => format!("{path}: {f}"),
+        }).collect();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let c = Settings::from_file(path).map_err(|e| vec![e.to_string()])?;
+        let restart = self.update(c).map_err(|e| vec![e.to_string()])?;
+        Ok(restart.then(|| self.job()))
+    }
+
+    fn update(&mut self, mut c: Settings) -> Result<bool> {
+        if let Some(name) = &self.scene_name {
+            c.scene.get_or_insert_with(Default::default).generator = Some(name.clone());
+        }
+        let old = &self.c;
+        let differ = |a: &dyn std::fmt::Debug, b: &dyn std::fmt::Debug| format!("{a:?}") != format!("{b:?}");
+        let geometry = differ(&(&old.scene, &old.material), &(&c.scene, &c.material)) || differ(&old.seed, &c.seed)
+            || differ(&old.ground.center, &c.ground.center) || differ(&old.ground.radius, &c.ground.radius);
+        let materials = !geometry && differ(&old.ground, &c.ground);
+        let sky = differ(&old.sky, &c.sky);
+        let render = differ(&(old.width, old.max_depth, old.samples_per_pixel, old.multithread_enabled, old.threads), &(c.width, c.max_depth, c.samples_per_pixel, c.multithread_enabled, c.threads))
+            || differ(&(&old.spectral, &old.sampler, &old.filter), &(&c.spectral, &c.sampler, &c.filter));
+
+        // everything that can fail before anything is replaced
+        let generator = Generator::from_settings(&c)?;
+        let tm = ToneMapping::new(&c.tonemap)?;
+        let options = RenderOptions::from_settings(&c)?;
+        let world = if geometry {
+            Some(generator.build(&c)?)
+        } else if materials {
+            Some(replace_ground_material(&self.world, &c.ground)?)
+        } else {
+            None
+        };
+        let new_sky = if sky || world.is_some() { Some(Sky::new(&c.sky)?) } else { None };
+        // a camera moved in the window stays until the settings move it
+        let view = generator.camera(&c);
+        let camera = differ(&view, &self.generator.camera(old));
+
+        let mut rebuilt = vec![];
+        if geometry {
+            rebuilt.push("geometry and BVH");
+        } else if materials {
+            rebuilt.push("materials");
+        }
+        if sky {
+            rebuilt.push("sky");
+        }
+        if camera {
+            rebuilt.push("camera");
+            self.view = view;
+        }
+        if render {
+            rebuilt.push("render settings");
+        }
+        println!("Reloaded settings{}", if rebuilt.is_empty() { String::new() } else { format!(", new {}", rebuilt.join(", ")) });
+
+        if let Some(world) = world {
+            self.world = world;
+        }
+        if let Some(sky) = new_sky {
+            self.scene = Arc::new(Scene { world: self.world.clone(), sky });
+        }
+        self.generator = generator;
+        self.tm = tm;
+        self.options = options;
+        self.c = c;
+        Ok(geometry || materials || sky || camera || render)
+    }
+}
+
+impl Shared {
+    // Makes the render thread drop what it renders and start on `job`
+    fn submit(&self, job: Job) {
+        let mut current = self.job.lock().unwrap();
+        *current = (current.0 + 1, job);
+        *self.film.lock().unwrap() = Film { job: current.0, width: 0, sum: vec![], passes: 0 };
+    }
+
+    // Renders passes of a sample per pixel until the job has them all or the window closes
+    fn render_loop(&self) {
+        while self.open.load(Ordering::Relaxed) {
+            let (id, job) = self.job.lock().unwrap().clone();
+            let pass = self.film.lock().unwrap().passes;
+            if pass >= u32::from(job.passes) {
+                thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+
+            let aspect_ratio = job.view.aspect_ratio.unwrap_or(16.0 / 9.0);
+            let camera = Camera::initialize(aspect_ratio, job.width, job.max_depth, 1, CameraView::new(&Some(job.view.clone())))
+                .with_options(job.options.progressive_pass(pass));
+            let camera = Arc::new(camera);
+            if let Err(e) = Camera::render(camera.clone(), job.scene.clone(), job.threads, &mut Progress::quiet()) {
+                *self.error.lock().unwrap() = Some(e.to_string());
+                thread::sleep(Duration::from_millis(500));
+                continue;
+            }
+
+            let image = camera.image();
+            let mut film = self.film.lock().unwrap();
+            if film.job != id {
+                continue;
+            }
+            if film.passes == 0 {
+                film.width = image.width;
+                film.sum = image.pixels;
+            } else {
+                film.sum.iter_mut().zip(image.pixels).for_each(|(s, p)| *s = *s + p);
+            }
+            film.passes += 1;
+        }
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Shows the scene in a window, rendered progressively, and reloads the settings from
+/// `path` when the file changes. Settings that don't load show as an overlay while the
+/// last good ones go on rendering
+pub(crate) fn run(path: &str, c: Settings, scene_name: Option<String>) -> Result<bool> {
+    let mut live = Live::new(c, scene_name)?;
+    let job = live.job();
+    let shared = Arc::new(Shared {
+        job: Mutex::new((0, job.clone())),
+        film: Mutex::new(Film { job: 0, width: 0, sum: vec![], passes: 0 }),
+        error: Mutex::new(None),
+        open: AtomicBool::new(true),
+    });
+    let renderer = shared.clone();
+    thread::spawn(move || renderer.render_loop());
+
+    let size = |job: &Job| {
+        let aspect_ratio = job.view.aspect_ratio.unwrap_or(16.0 / 9.0);
+        let width = i32::from(job.width);
+        (width, ((width as f32 / aspect_ratio) as i32).max(1))
+    };
+    let (mut width, mut height) = size(&job);
+    let (mut rl, thread) = raylib::init()
+        .size(width, height)
+        .title(&format!("raytracer - {path}"))
+        .build();
+    rl.set_target_fps(30);
+    println!("{HELP}");
+
+    let mut last_modified = modified(path);
+    let mut last_check = Instant::now();
+    let mut errors: Vec<String> = vec![];
+    let mut pixels: Vec<[u8; 3]> = vec![];
+    let (mut shown_width, mut shown_passes) = (0u16, 0u32);
+    let mut passes = job.passes;
+    let mut message: Option<(String, Instant)> = None;
+
+    while !rl.window_should_close() {
+        let mut restart = None;
+        if last_check.elapsed() >= WATCH_INTERVAL {
+            last_check = Instant::now();
+            let m = modified(path);
+            if m != last_modified {
+                last_modified = m;
+                match live.reload(path) {
+                    Ok(job) => {
+                        errors.clear();
+                        restart = job;
+                        // the tone mapping applies to what is on screen already
+                        shown_passes = 0;
+                    }
+                    Err(e) => errors = e.iter().flat_map(|e| e.lines()).map(str::to_string).collect(),
+                }
+            }
+        }
+
+        let mut view = CameraView::new(&Some(live.view.clone()));
+        if navigation::navigate(&rl, &mut view, height) {
+            live.set_view(&view);
+            restart = Some(live.job());
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_R) {
+            live.reset_view();
+            restart = Some(live.job());
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_P) {
+            let snippet = live.view.to_toml();
+            println!("{snippet}");
+            let saved = match fs::write(CAMERA_FILE, &snippet) {
+                Ok(()) => format!("camera saved to {CAMERA_FILE}"),
+                Err(e) => format!("cannot write {CAMERA_FILE}: {e}"),
+            };
+            message = Some((saved, Instant::now()));
+        }
+
+        if let Some(job) = restart {
+            passes = job.passes;
+            let (w, h) = size(&job);
+            if (w, h) != (width, height) {
+                (width, height) = (w, h);
+                rl.set_window_size(width, height);
+            }
+            shared.submit(job);
+        }
+        message = message.filter(|(_, shown)| shown.elapsed() < MESSAGE_TIME);
+
+        {
+            let film = shared.film.lock().unwrap();
+            if film.passes > 0 && (film.passes != shown_passes || film.width != shown_width) {
+                let scale = 1.0 / film.passes as f32;
+                let image = Image { width: film.width, height: (film.sum.len() / usize::from(film.width)) as u16, pixels: film.sum.iter().map(|p| *p * scale).collect() };
+                pixels = image.to_rgb8(&live.tm);
+                (shown_width, shown_passes) = (film.width, film.passes);
+            }
+        }
+        let render_error = shared.error.lock().unwrap().take();
+        if let Some(e) = render_error {
+            errors = vec![e];
+        }
+
+        let mut d = rl.begin_drawing(&thread);
+        d.clear_background(raylib::color::Color::BLACK);
+        let w = i32::from(shown_width.max(1));
+        pixels.iter().enumerate().for_each(|(i, [r, g, b])| {
+            d.draw_pixel(i as i32 % w, i as i32 / w, raylib::color::Color::new(*r, *g, *b, 255));
+        });
+        let status = match &message {
+            Some((m, _)) => format!("{shown_passes}/{passes}  {m}"),
+            None => format!("{shown_passes}/{passes}"),
+        };
+        d.draw_text(&status, 4, height - FONT_SIZE - 4, FONT_SIZE, raylib::color::Color::WHITE);
+
+        // the errors of the file over the last good render
+        if !errors.is_empty() {
+            let line_height = FONT_SIZE + 2;
+            d.draw_rectangle(0, 0, width, line_height * errors.len() as i32 + 8, raylib::color::Color::new(0, 0, 0, 200));
+            for (i, e) in errors.iter().enumerate() {
+                d.draw_text(e, 4, 4 + line_height * i as i32, FONT_SIZE, raylib::color::Color::RED);
+            }
+        }
+    }
+    shared.open.store(false, Ordering::Relaxed);
+    Ok(true)
+}